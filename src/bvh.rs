@@ -0,0 +1,364 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+
+use crate::hittables::{HitRecord, Hittable};
+use crate::util::{Float, Point3, Ray, Vec3};
+use crate::world::RayCounters;
+
+/// Sphere around an object, a cheaper but looser test than `Aabb`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3,
+    pub radius: Float,
+}
+
+impl BoundingSphere {
+    pub fn new(center: Point3, radius: Float) -> Self {
+        BoundingSphere { center, radius }
+    }
+
+    /// Sphere through the corners of the box
+    pub fn from_aabb(bbox: &Aabb) -> Self {
+        BoundingSphere::new(bbox.center(), bbox.size().magnitude() / 2.0)
+    }
+
+    /// Whether the line of the ray comes closer to the center than the radius.
+    /// Ignores the t range, only the distance of closest approach is compared.
+    pub fn hit(&self, r: &Ray) -> bool {
+        let oc = self.center - r.orig;
+        let along = oc.dot(&r.dir);
+        let closest_distance_squared =
+            oc.magnitude_squared() - along * along / r.dir.magnitude_squared();
+        // Slack for rounding, objects that are barely touched must not be skipped
+        closest_distance_squared <= self.radius * self.radius * (1.0 + 1e-4)
+    }
+}
+
+/// Axis aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub minimum: Point3,
+    pub maximum: Point3,
+}
+
+impl Aabb {
+    pub fn new(minimum: Point3, maximum: Point3) -> Self {
+        Aabb { minimum, maximum }
+    }
+
+    /// Smallest box containing both boxes
+    pub fn surrounding(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            minimum: self.minimum.inf(&other.minimum),
+            maximum: self.maximum.sup(&other.maximum),
+        }
+    }
+
+    /// Box of the points in both boxes. Minimum and maximum are swapped in some axis if
+    /// the boxes don't overlap.
+    pub fn intersection(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            minimum: self.minimum.sup(&other.minimum),
+            maximum: self.maximum.inf(&other.maximum),
+        }
+    }
+
+    pub fn center(&self) -> Point3 {
+        (self.minimum + self.maximum) / 2.0
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.maximum - self.minimum
+    }
+
+    /// The 12 edges between the corners
+    pub fn edges(&self) -> Vec<(Point3, Point3)> {
+        let corner = |i: usize| {
+            Point3::from_fn(|axis, _| {
+                if i & (1 << axis) == 0 {
+                    self.minimum[axis]
+                } else {
+                    self.maximum[axis]
+                }
+            })
+        };
+        // Corners that differ in one coordinate
+        (0..8)
+            .flat_map(|i| (0..3).map(move |axis| (i, i | 1 << axis)))
+            .filter(|(i, j)| i != j)
+            .map(|(i, j)| (corner(i), corner(j)))
+            .collect()
+    }
+
+    pub fn surface_area(&self) -> Float {
+        let s = self.size();
+        2.0 * (s.x * s.y + s.y * s.z + s.z * s.x)
+    }
+
+    /// Slab test: Intersect the t intervals in which the ray is between the two planes of each axis
+    pub fn hit(&self, r: &Ray, mut t_min: Float, mut t_max: Float) -> bool {
+        for a in 0..3 {
+            let inv_d = 1.0 / r.dir[a];
+            let mut t0 = (self.minimum[a] - r.orig[a]) * inv_d;
+            let mut t1 = (self.maximum[a] - r.orig[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Node of a bounding volume hierarchy. Leafs are the objects themselves.
+pub struct BvhNode {
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+    bbox: Aabb,
+}
+
+/// How a set of objects is divided into the two children of a `BvhNode`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BvhSplit {
+    /// Half of the objects on each side, along the longest axis of the box centers
+    Median,
+    /// Surface area heuristic: Minimize the surface area of each child times its number of
+    /// objects, which is proportional to the expected cost of the intersection tests
+    Sah,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BvhOptions {
+    pub split: BvhSplit,
+    /// Build large subtrees in parallel
+    pub parallel: bool,
+}
+
+impl Default for BvhOptions {
+    fn default() -> Self {
+        BvhOptions {
+            split: BvhSplit::Sah,
+            parallel: true,
+        }
+    }
+}
+
+/// Subtrees with fewer objects are built on the current thread
+const PARALLEL_BUILD_THRESHOLD: usize = 4096;
+
+fn bbox(object: &Arc<dyn Hittable>) -> Aabb {
+    object
+        .bounding_box()
+        .expect("Objects in a BvhNode must have a bounding box")
+}
+
+/// Partially sorts `objects` and returns the index of the first object of the right child
+fn median_split(objects: &mut [Arc<dyn Hittable>]) -> usize {
+    let centers = objects
+        .iter()
+        .map(|o| {
+            let c = bbox(o).center();
+            Aabb::new(c, c)
+        })
+        .reduce(|a, b| a.surrounding(&b))
+        .unwrap();
+    let axis = centers.size().imax();
+    let mid = objects.len() / 2;
+    objects.select_nth_unstable_by(mid, |a, b| {
+        bbox(a).center()[axis].total_cmp(&bbox(b).center()[axis])
+    });
+    mid
+}
+
+/// Sorts `objects` and returns the index of the first object of the right child
+fn sah_split(objects: &mut [Arc<dyn Hittable>]) -> usize {
+    let n = objects.len();
+    let mut items: Vec<(Aabb, Arc<dyn Hittable>)> =
+        objects.iter().map(|o| (bbox(o), Arc::clone(o))).collect();
+    let sort_items = |items: &mut Vec<(Aabb, Arc<dyn Hittable>)>, axis: usize| {
+        items.sort_unstable_by(|a, b| a.0.center()[axis].total_cmp(&b.0.center()[axis]))
+    };
+
+    // (cost, axis, index)
+    let mut best = (Float::INFINITY, 0, n / 2);
+    let mut right_areas = vec![0.0; n];
+    for axis in 0..3 {
+        sort_items(&mut items, axis);
+
+        // right_areas[i]: Surface area of the box around the objects i..n
+        let mut right = items[n - 1].0;
+        for i in (1..n).rev() {
+            right = right.surrounding(&items[i].0);
+            right_areas[i] = right.surface_area();
+        }
+
+        let mut left = items[0].0;
+        for i in 1..n {
+            left = left.surrounding(&items[i - 1].0);
+            let cost = left.surface_area() * i as Float + right_areas[i] * (n - i) as Float;
+            if cost < best.0 {
+                best = (cost, axis, i);
+            }
+        }
+    }
+
+    if best.1 != 2 {
+        sort_items(&mut items, best.1);
+    }
+    for (object, (_, item)) in objects.iter_mut().zip(items) {
+        *object = item;
+    }
+    best.2
+}
+
+impl BvhNode {
+    /// Builds a tree over `objects`, which all must have a bounding box. A single object is
+    /// returned as it is. Uses the surface area heuristic and builds large subtrees in parallel.
+    pub fn new(objects: &mut [Arc<dyn Hittable>]) -> Arc<dyn Hittable> {
+        Self::with_options(objects, BvhOptions::default())
+    }
+
+    pub fn with_options(
+        objects: &mut [Arc<dyn Hittable>],
+        options: BvhOptions,
+    ) -> Arc<dyn Hittable> {
+        assert!(!objects.is_empty(), "BvhNode needs at least one object");
+
+        let (left, right) = match objects.len() {
+            1 => return Arc::clone(&objects[0]),
+            2 => (Arc::clone(&objects[0]), Arc::clone(&objects[1])),
+            n => {
+                let mid = match options.split {
+                    BvhSplit::Median => median_split(objects),
+                    BvhSplit::Sah => sah_split(objects),
+                };
+                let (left_objects, right_objects) = objects.split_at_mut(mid);
+                if options.parallel && n > PARALLEL_BUILD_THRESHOLD {
+                    rayon::join(
+                        || BvhNode::with_options(left_objects, options),
+                        || BvhNode::with_options(right_objects, options),
+                    )
+                } else {
+                    (
+                        BvhNode::with_options(left_objects, options),
+                        BvhNode::with_options(right_objects, options),
+                    )
+                }
+            }
+        };
+
+        Arc::new(BvhNode {
+            bbox: bbox(&left).surrounding(&bbox(&right)),
+            left,
+            right,
+        })
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return None;
+        }
+        RayCounters::count(0, 2);
+
+        let hit_left = self.left.hit(r, t_min, t_max);
+        let t_max = hit_left.as_ref().map(|h| h.t).unwrap_or(t_max);
+        let hit_right = self.right.hit(r, t_min, t_max);
+        hit_right.or(hit_left)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittables::{Sphere, Triangle};
+    use crate::material::Lambertian;
+    use crate::util::{vec3_random, Color};
+    use rand::distributions::Uniform;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+    use std::time::Instant;
+
+    #[test]
+    fn test_single_object_is_the_leaf() {
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let sphere = Sphere::new(0.0, 0.0, -1.0, 0.5, &material);
+        let bvh = BvhNode::new(&mut [Arc::clone(&sphere)]);
+        assert!(Arc::ptr_eq(&bvh, &sphere));
+
+        // Three objects split into a node and a single object
+        let mut objects = vec![
+            Sphere::new(0.0, 0.0, -1.0, 0.5, &material),
+            Sphere::new(0.0, 0.0, -3.0, 0.5, &material),
+            Sphere::new(0.0, 0.0, -5.0, 0.5, &material),
+        ];
+        let bvh = BvhNode::new(&mut objects);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(bvh.hit(&ray, 0.001, Float::INFINITY).unwrap().t, 0.5);
+        let ray = Ray::new(Point3::new(0.0, 0.0, -6.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(bvh.hit(&ray, 0.001, Float::INFINITY).unwrap().t, 0.5);
+    }
+
+    /// Benchmark, run with `cargo test --release bvh_build -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_bvh_build_serial_vs_parallel() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let distr = Uniform::new(-100.0, 100.0);
+        let distr_edge = Uniform::new(-5.0, 5.0);
+        // Thin triangles in all orientations, their boxes overlap a lot unlike those of spheres
+        let objects: Vec<Arc<dyn Hittable>> = (0..100_000)
+            .map(|_| {
+                let v0 = vec3_random(&distr, &mut rng);
+                let v1 = v0 + vec3_random(&distr_edge, &mut rng);
+                let v2 = v0 + 0.05 * vec3_random(&distr_edge, &mut rng);
+                Triangle::new(v0, v1, v2, &material)
+            })
+            .collect();
+
+        for split in [BvhSplit::Median, BvhSplit::Sah] {
+            let mut serial_objects = objects.clone();
+            let start = Instant::now();
+            let serial = BvhNode::with_options(
+                &mut serial_objects,
+                BvhOptions {
+                    split,
+                    parallel: false,
+                },
+            );
+            let serial_time = start.elapsed();
+
+            let mut parallel_objects = objects.clone();
+            let start = Instant::now();
+            let parallel = BvhNode::with_options(
+                &mut parallel_objects,
+                BvhOptions {
+                    split,
+                    parallel: true,
+                },
+            );
+            let parallel_time = start.elapsed();
+
+            println!(
+                "{:?} BVH build over {} objects: serial {:.2?}, parallel {:.2?} on {} threads",
+                split,
+                objects.len(),
+                serial_time,
+                parallel_time,
+                rayon::current_num_threads()
+            );
+            assert_eq!(serial.bounding_box(), parallel.bounding_box());
+        }
+    }
+}
@@ -1,16 +1,51 @@
-use rand::rngs::SmallRng;
+use std::error::Error;
+use std::fmt;
 
-use crate::util::{random_in_unit_disk, AsRgb, Color, Point3, Ray, Vec3};
+use clap::ValueEnum;
+use log::warn;
+use nalgebra::{Rotation3, Unit};
+
+use crate::world::World;
+
+use crate::util::{
+    consts, random_in_unit_disk, random_in_unit_polygon, AsRgb, Color, Float, Point3, Ray,
+    RenderRng, Vec3,
+};
+
+/// Shape of the lens opening. Out of focus highlights (bokeh) take on this shape.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApertureShape {
+    Circle,
+    /// Regular polygon with the given number of blades (>= 3)
+    Polygon(u32),
+}
+
+/// How directions are mapped onto the image
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Projection {
+    /// Straight lines stay straight, the field of view must be below 180°
+    Perspective,
+    /// Fisheye with the angle from the viewing direction proportional to the distance from
+    /// the image center. The image circle touches the top and bottom of the image and covers
+    /// the vertical field of view, which may exceed 180°.
+    FisheyeEquidistant,
+    /// Fisheye that keeps the area of solid angles (like most real fisheye lenses), which
+    /// compresses the rim more than the equidistant projection
+    FisheyeEquisolid,
+}
 
 #[derive(Clone)]
 pub struct CameraBuilder {
     pub lookfrom: Option<Point3>,
     pub lookat: Option<Point3>,
     pub vup: Option<Vec3>,
-    pub vfov: Option<f64>,
-    pub aspect_ratio: Option<f64>,
-    pub aperture: Option<f64>,
-    pub focus_dist: Option<f64>,
+    pub vfov: Option<Float>,
+    pub aspect_ratio: Option<Float>,
+    pub aperture: Option<Float>,
+    pub focus_dist: Option<Float>,
+    pub aperture_shape: Option<ApertureShape>,
+    pub stereo: Option<Float>,
+    pub projection: Option<Projection>,
 }
 
 impl CameraBuilder {
@@ -23,6 +58,9 @@ impl CameraBuilder {
             aspect_ratio: None,
             aperture: None,
             focus_dist: None,
+            aperture_shape: None,
+            stereo: None,
+            projection: None,
         }
     }
     pub fn lookfrom(&mut self, lookfrom: Point3) -> &mut Self {
@@ -37,35 +75,193 @@ impl CameraBuilder {
         self.vup = Some(vup);
         self
     }
-    pub fn vfov(&mut self, vfov: f64) -> &mut Self {
+    pub fn vfov(&mut self, vfov: Float) -> &mut Self {
         self.vfov = Some(vfov);
         self
     }
-    pub fn aspect_ratio(&mut self, aspect_ratio: f64) -> &mut Self {
+    pub fn aspect_ratio(&mut self, aspect_ratio: Float) -> &mut Self {
         self.aspect_ratio = Some(aspect_ratio);
         self
     }
-    pub fn aperture(&mut self, aperture: f64) -> &mut Self {
+    pub fn aperture(&mut self, aperture: Float) -> &mut Self {
         self.aperture = Some(aperture);
         self
     }
-    pub fn focus_dist(&mut self, focus_dist: f64) -> &mut Self {
+    pub fn focus_dist(&mut self, focus_dist: Float) -> &mut Self {
         self.focus_dist = Some(focus_dist);
         self
     }
-    pub fn build(&self) -> Option<Camera> {
-        Some(Camera::new(
-            self.lookfrom?,
-            self.lookat?,
-            self.vup?,
-            self.vfov?,
-            self.aspect_ratio?,
-            self.aperture?,
-            self.focus_dist?,
-        ))
+    /// Optional, defaults to `ApertureShape::Circle`
+    pub fn aperture_shape(&mut self, aperture_shape: ApertureShape) -> &mut Self {
+        self.aperture_shape = Some(aperture_shape);
+        self
+    }
+    /// Optional, enables stereo rendering with the given inter-pupillary distance
+    pub fn stereo(&mut self, ipd: Float) -> &mut Self {
+        self.stereo = Some(ipd);
+        self
+    }
+    /// Optional, defaults to `Projection::Perspective`
+    pub fn projection(&mut self, projection: Projection) -> &mut Self {
+        self.projection = Some(projection);
+        self
+    }
+    /// Moves `lookfrom` on a circle around `lookat`, turning by `degrees` around `vup`
+    /// (counterclockwise seen from above). Needs `lookfrom`, `lookat` and `vup`.
+    pub fn orbit(&mut self, degrees: Float) -> &mut Self {
+        if let (Some(lookfrom), Some(lookat), Some(vup)) = (self.lookfrom, self.lookat, self.vup) {
+            let rotation =
+                Rotation3::from_axis_angle(&Unit::new_normalize(vup), degrees.to_radians());
+            self.lookfrom = Some(lookat + rotation * (lookfrom - lookat));
+        }
+        self
+    }
+    /// Moves `lookfrom` on a circle around `lookat` towards `vup` by `degrees` (away for
+    /// negative degrees). Stops a degree before looking straight along `vup`, where the view
+    /// would flip. Needs `lookfrom`, `lookat` and `vup`.
+    pub fn tilt(&mut self, degrees: Float) -> &mut Self {
+        if let (Some(lookfrom), Some(lookat), Some(vup)) = (self.lookfrom, self.lookat, self.vup) {
+            let w = lookfrom - lookat;
+            let Some(axis) = Unit::try_new(w.cross(&vup), 1e-9) else {
+                return self;
+            };
+            let angle_to_vup = w.angle(&vup);
+            let limit = (1.0 as Float).to_radians();
+            let new_angle = (angle_to_vup - degrees.to_radians()).clamp(limit, consts::PI - limit);
+            let rotation = Rotation3::from_axis_angle(&axis, angle_to_vup - new_angle);
+            self.lookfrom = Some(lookat + rotation * w);
+        }
+        self
+    }
+    /// Moves `lookfrom` towards `lookat` (`factor` < 1) or away from it, and the focus with it.
+    /// Needs `lookfrom` and `lookat`.
+    pub fn dolly(&mut self, factor: Float) -> &mut Self {
+        if let (Some(lookfrom), Some(lookat)) = (self.lookfrom, self.lookat) {
+            self.lookfrom = Some(lookat + factor * (lookfrom - lookat));
+            self.focus_dist = self.focus_dist.map(|focus_dist| focus_dist * factor);
+        }
+        self
+    }
+    /// Moves `lookfrom` and `lookat` to the right and up as seen through the camera, by
+    /// `right` and `up` times the distance between them. Needs `lookfrom`, `lookat` and `vup`.
+    pub fn pan(&mut self, right: Float, up: Float) -> &mut Self {
+        if let (Some(lookfrom), Some(lookat), Some(vup)) = (self.lookfrom, self.lookat, self.vup) {
+            let w = lookfrom - lookat;
+            let Some(u) = Unit::try_new(vup.cross(&w), 1e-9) else {
+                return self;
+            };
+            let v = w.normalize().cross(&u);
+            let offset = w.magnitude() * (right * u.into_inner() + up * v);
+            self.lookfrom = Some(lookfrom + offset);
+            self.lookat = Some(lookat + offset);
+        }
+        self
+    }
+    /// Looks at the center of the bounding box of `world` from a distance at which the whole
+    /// box is visible and in focus. Keeps the viewing direction if `lookfrom` and `lookat` are
+    /// set, otherwise looks into -z. Needs `vfov`, uses `aspect_ratio` if already set.
+    /// Does nothing if the world has no bounded objects.
+    pub fn frame_scene(&mut self, world: &World) -> &mut Self {
+        let (Some(bbox), Some(vfov)) = (world.bounding_box(), self.vfov) else {
+            return self;
+        };
+        let direction = match (self.lookfrom, self.lookat) {
+            (Some(lookfrom), Some(lookat)) if lookfrom != lookat => (lookfrom - lookat).normalize(),
+            _ => Vec3::new(0.0, 0.0, 1.0),
+        };
+
+        // Fit the bounding sphere of the box into the narrower field of view
+        let half_vfov = vfov.to_radians() / 2.0;
+        let half_hfov = (half_vfov.tan() * self.aspect_ratio.unwrap_or(1.0)).atan();
+        let radius = bbox.size().magnitude() / 2.0;
+        let distance = radius / half_vfov.min(half_hfov).sin();
+
+        self.lookat = Some(bbox.center());
+        self.lookfrom = Some(bbox.center() + distance * direction);
+        self.focus_dist = Some(distance);
+        if self.vup.is_none() {
+            self.vup = Some(Vec3::new(0.0, 1.0, 0.0));
+        }
+        self
+    }
+    pub fn build(&self) -> Result<Camera, CameraBuildError> {
+        use CameraBuildError::*;
+        let lookfrom = self.lookfrom.ok_or(MissingField("lookfrom"))?;
+        let lookat = self.lookat.ok_or(MissingField("lookat"))?;
+        let vup = self.vup.ok_or(MissingField("vup"))?;
+        let vfov = self.vfov.ok_or(MissingField("vfov"))?;
+        let aspect_ratio = self.aspect_ratio.ok_or(MissingField("aspect_ratio"))?;
+        let aperture = self.aperture.ok_or(MissingField("aperture"))?;
+        let focus_dist = self.focus_dist.ok_or(MissingField("focus_dist"))?;
+
+        let vfov_in_range = match self.projection.unwrap_or(Projection::Perspective) {
+            Projection::Perspective => vfov > 0.0 && vfov < 180.0,
+            Projection::FisheyeEquidistant | Projection::FisheyeEquisolid => {
+                vfov > 0.0 && vfov <= 360.0
+            }
+        };
+        if !vfov_in_range {
+            return Err(VfovOutOfRange(vfov));
+        }
+        if focus_dist <= 0.0 || focus_dist.is_nan() {
+            return Err(FocusDistNotPositive(focus_dist));
+        }
+        if vup == Vec3::zeros() {
+            return Err(ZeroVup);
+        }
+
+        let mut camera = Camera::new(
+            lookfrom,
+            lookat,
+            vup,
+            vfov,
+            aspect_ratio,
+            aperture,
+            focus_dist,
+        );
+        if let Some(aperture_shape) = self.aperture_shape {
+            camera.aperture_shape = aperture_shape;
+        }
+        camera.ipd = self.stereo;
+        if let Some(projection) = self.projection {
+            camera.projection = projection;
+        }
+        Ok(camera)
+    }
+}
+
+/// Why `CameraBuilder::build` can't make a camera
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraBuildError {
+    /// The field with this name was never set
+    MissingField(&'static str),
+    /// Zero or negative
+    FocusDistNotPositive(Float),
+    /// Not in 0..180 degrees, or up to 360 for the fisheye projections
+    VfovOutOfRange(Float),
+    /// `vup` has zero length, so there is no up direction
+    ZeroVup,
+}
+
+impl fmt::Display for CameraBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraBuildError::MissingField(field) => write!(f, "{} is not set", field),
+            CameraBuildError::FocusDistNotPositive(focus_dist) => {
+                write!(f, "focus_dist must be positive, but is {}", focus_dist)
+            }
+            CameraBuildError::VfovOutOfRange(vfov) => write!(
+                f,
+                "vfov must be above 0 and below 180 degrees (up to 360 for fisheye), but is {}",
+                vfov
+            ),
+            CameraBuildError::ZeroVup => write!(f, "vup must not be zero"),
+        }
     }
 }
 
+impl Error for CameraBuildError {}
+
 #[derive(Clone)]
 pub struct Camera {
     /// Eye
@@ -76,25 +272,34 @@ pub struct Camera {
     pub vertical: Vec3,
     /// Vector from lower_left_corner to top side of viewport
     horizontal: Vec3,
-    ///
+    /// Camera basis: u to the right, v to the top, w opposite to the viewing direction
     u: Vec3,
     v: Vec3,
     w: Vec3,
-    ///
-    lens_radius: f64,
+    /// Half the aperture
+    lens_radius: Float,
+    aperture_shape: ApertureShape,
+    /// Inter-pupillary distance if this is a stereo camera
+    ipd: Option<Float>,
+    projection: Projection,
+    /// Half the vertical field of view in radians
+    half_vfov: Float,
+    aspect_ratio: Float,
+    focus_dist: Float,
 }
 
 impl Camera {
-    /// vup: Defines `up` for camera
+    /// vup: Defines `up` for camera. If it is parallel to the viewing direction (e.g. when
+    /// looking straight down), the z axis is used instead, or the x axis when looking along z.
     /// vfov: vertical field of view
     pub fn new(
         lookfrom: Point3,
         lookat: Point3,
         vup: Vec3,
-        vfov: f64,
-        aspect_ratio: f64,
-        aperture: f64,
-        focus_dist: f64,
+        vfov: Float,
+        aspect_ratio: Float,
+        aperture: Float,
+        focus_dist: Float,
     ) -> Self {
         let theta = vfov.to_radians();
         let h = (theta / 2.0).tan();
@@ -102,7 +307,21 @@ impl Camera {
         let viewport_width = viewport_height * aspect_ratio;
 
         let w = (lookfrom - lookat).normalize();
-        let u = vup.cross(&w).normalize();
+        let mut u = vup.cross(&w);
+        // The cross product vanishes for parallel vectors and normalizing gives NaN
+        if u.magnitude() < 1e-6 * vup.magnitude() {
+            let fallback = if w.z.abs() < 0.9 {
+                Vec3::new(0.0, 0.0, 1.0)
+            } else {
+                Vec3::new(1.0, 0.0, 0.0)
+            };
+            warn!(
+                "vup ({}, {}, {}) is parallel to the viewing direction, using ({}, {}, {})",
+                vup.x, vup.y, vup.z, fallback.x, fallback.y, fallback.z
+            );
+            u = fallback.cross(&w);
+        }
+        let u = u.normalize();
         let v = w.cross(&u);
 
         let horizontal = focus_dist * viewport_width * u;
@@ -118,15 +337,387 @@ impl Camera {
             v,
             w,
             lens_radius: aperture / 2.,
+            aperture_shape: ApertureShape::Circle,
+            ipd: None,
+            projection: Projection::Perspective,
+            half_vfov: theta / 2.0,
+            aspect_ratio,
+            focus_dist,
+        }
+    }
+
+    /// Copy of this camera moved sideways along `u` by `offset`.
+    /// The viewing direction is kept (parallel axes, no toe-in).
+    pub fn shifted(&self, offset: Float) -> Camera {
+        let shift = offset * self.u;
+        Camera {
+            origin: self.origin + shift,
+            lower_left_corner: self.lower_left_corner + shift,
+            ipd: None,
+            ..self.clone()
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64, rng: &mut SmallRng) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk(rng);
+    /// Left and right eye camera if this is a stereo camera
+    pub fn stereo_eyes(&self) -> Option<(Camera, Camera)> {
+        self.ipd
+            .map(|ipd| (self.shifted(-ipd / 2.0), self.shifted(ipd / 2.0)))
+    }
+
+    /// Ray through the viewport position `s` (0 left to 1 right), `t` (0 bottom to 1 top).
+    /// `None` outside the image circle of a fisheye projection.
+    pub fn get_ray(&self, s: Float, t: Float, rng: &mut RenderRng) -> Option<Ray> {
+        let focus_point = match self.projection {
+            Projection::Perspective => {
+                self.lower_left_corner + s * self.horizontal + t * self.vertical
+            }
+            Projection::FisheyeEquidistant | Projection::FisheyeEquisolid => {
+                self.origin + self.focus_dist * self.fisheye_direction(s, t)?
+            }
+        };
+        let rd = self.lens_radius
+            * match self.aperture_shape {
+                ApertureShape::Circle => random_in_unit_disk(rng),
+                ApertureShape::Polygon(n) => random_in_unit_polygon(n, rng),
+            };
         let offset = self.u * rd.x + self.v * rd.y;
-        Ray::new(
+        Some(Ray::new(
             self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-        )
+            focus_point - self.origin - offset,
+        ))
+    }
+
+    /// Angle in radians between the rays through two neighboring pixels at the image center,
+    /// for an image that is `image_height` pixels high
+    pub fn pixel_angle(&self, image_height: u32) -> Float {
+        let image_height = image_height as Float;
+        match self.projection {
+            Projection::Perspective => 2.0 * self.half_vfov.tan() / image_height,
+            Projection::FisheyeEquidistant => 2.0 * self.half_vfov / image_height,
+            // Slope of the angle over the radius of `fisheye_direction` at the center
+            Projection::FisheyeEquisolid => 4.0 * (self.half_vfov / 2.0).sin() / image_height,
+        }
+    }
+
+    fn fisheye_direction(&self, s: Float, t: Float) -> Option<Vec3> {
+        // Coordinates with the image circle as unit circle
+        let x = (2.0 * s - 1.0) * self.aspect_ratio;
+        let y = 2.0 * t - 1.0;
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let theta = match self.projection {
+            Projection::FisheyeEquisolid => {
+                2.0 * (r * (self.half_vfov / 2.0).sin()).clamp(-1.0, 1.0).asin()
+            }
+            _ => r * self.half_vfov,
+        };
+        let radial = if r > 0.0 {
+            (x * self.u + y * self.v) / r
+        } else {
+            Vec3::zeros()
+        };
+        Some(theta.cos() * -self.w + theta.sin() * radial)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittables::Sphere;
+    use crate::material::Lambertian;
+    use approx::assert_relative_eq;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_fisheye() {
+        let mut rng = RenderRng::seed_from_u64(1);
+        let mut builder = CameraBuilder::new();
+        builder
+            .lookfrom(Point3::new(1.0, 2.0, 3.0))
+            .lookat(Point3::new(1.0, 2.0, 0.0))
+            .vup(Vec3::new(0.0, 1.0, 0.0))
+            .vfov(180.0)
+            .aspect_ratio(2.0)
+            .aperture(0.0)
+            .focus_dist(1.0)
+            .projection(Projection::FisheyeEquidistant);
+        let camera = builder.build().unwrap();
+        let direction = |camera: &Camera, s, t| {
+            let mut rng = RenderRng::seed_from_u64(1);
+            camera
+                .get_ray(s, t, &mut rng)
+                .map(|ray| ray.direction().normalize())
+        };
+
+        // The center looks ahead, the rim of the image circle sideways at 90°
+        assert_relative_eq!(
+            direction(&camera, 0.5, 0.5).unwrap(),
+            Vec3::new(0.0, 0.0, -1.0)
+        );
+        assert_relative_eq!(
+            direction(&camera, 0.75, 0.5).unwrap(),
+            Vec3::new(1.0, 0.0, 0.0),
+            epsilon = 1e-6
+        );
+        assert_relative_eq!(
+            direction(&camera, 0.5, 1.0).unwrap(),
+            Vec3::new(0.0, 1.0, 0.0),
+            epsilon = 1e-6
+        );
+        // Equidistant: Half the radius is half the angle
+        let halfway = direction(&camera, 0.5, 0.75).unwrap();
+        assert_relative_eq!(halfway.y.asin(), consts::FRAC_PI_4, epsilon = 1e-6);
+        // Outside the image circle
+        assert!(direction(&camera, 0.9, 0.5).is_none());
+        assert!(camera.get_ray(0.0, 0.0, &mut rng).is_none());
+
+        let equisolid = builder
+            .projection(Projection::FisheyeEquisolid)
+            .build()
+            .unwrap();
+        let halfway = direction(&equisolid, 0.5, 0.75).unwrap();
+        let expected = 2.0 * (0.5 * consts::FRAC_PI_4.sin()).asin();
+        assert_relative_eq!(halfway.y.asin(), expected, epsilon = 1e-6);
+        assert_relative_eq!(
+            direction(&equisolid, 0.5, 1.0).unwrap(),
+            Vec3::new(0.0, 1.0, 0.0),
+            epsilon = 1e-6
+        );
+    }
+
+    #[test]
+    fn test_pixel_angle() {
+        let image_height = 100;
+        for (projection, vfov) in [
+            (Projection::Perspective, 20.0),
+            (Projection::Perspective, 90.0),
+            (Projection::FisheyeEquidistant, 180.0),
+            (Projection::FisheyeEquisolid, 180.0),
+        ] {
+            let camera = CameraBuilder::new()
+                .lookfrom(Point3::new(0.0, 0.0, 1.0))
+                .lookat(Point3::zeros())
+                .vup(Vec3::new(0.0, 1.0, 0.0))
+                .vfov(vfov)
+                .aspect_ratio(1.5)
+                .aperture(0.0)
+                .focus_dist(1.0)
+                .projection(projection)
+                .build()
+                .unwrap();
+            let mut rng = RenderRng::seed_from_u64(1);
+            let mut direction = |t| camera.get_ray(0.5, t, &mut rng).unwrap().direction();
+            let half_pixel = 0.5 / image_height as Float;
+            let (below, above) = (direction(0.5 - half_pixel), direction(0.5 + half_pixel));
+            let angle = below.cross(&above).magnitude().atan2(below.dot(&above));
+            assert_relative_eq!(camera.pixel_angle(image_height), angle, max_relative = 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_vup_parallel_to_view() {
+        let mut rng = RenderRng::seed_from_u64(1);
+        let mut builder = CameraBuilder::new();
+        builder
+            .lookfrom(Point3::new(1.0, 0.0, 0.0))
+            .lookat(Point3::new(1.0, 5.0, 0.0))
+            .vup(Vec3::new(0.0, 1.0, 0.0))
+            .vfov(90.0)
+            .aspect_ratio(1.0)
+            .aperture(0.1)
+            .focus_dist(5.0);
+        for (lookat, expected) in [
+            // Straight up
+            (Point3::new(1.0, 5.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+            // Straight down
+            (Point3::new(1.0, -5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)),
+        ] {
+            let camera = builder.lookat(lookat).build().unwrap();
+            let center = camera.get_ray(0.5, 0.5, &mut rng).unwrap();
+            assert_relative_eq!(center.dir.normalize(), expected, epsilon = 1e-2);
+            let corner = camera.get_ray(0.0, 1.0, &mut rng).unwrap();
+            assert!(corner
+                .dir
+                .iter()
+                .chain(corner.orig.iter())
+                .all(|c| c.is_finite()));
+        }
+
+        // Looking along z with vup = z falls back to x
+        let camera = builder
+            .lookat(Point3::new(1.0, 0.0, 5.0))
+            .vup(Vec3::new(0.0, 0.0, 2.0))
+            .aperture(0.0)
+            .build()
+            .unwrap();
+        let top = camera.get_ray(0.5, 1.0, &mut rng).unwrap();
+        assert!(top.dir.x > 0.0 && top.dir.iter().all(|c| c.is_finite()));
+    }
+
+    #[test]
+    fn test_orbit() {
+        let mut builder = CameraBuilder::new();
+        builder
+            .lookfrom(Point3::new(1.0, 2.0, 5.0))
+            .lookat(Point3::new(1.0, 0.0, 0.0))
+            .vup(Vec3::new(0.0, 1.0, 0.0));
+        builder.orbit(90.0);
+        assert_relative_eq!(
+            builder.lookfrom.unwrap(),
+            Point3::new(6.0, 2.0, 0.0),
+            epsilon = 1e-5
+        );
+        builder.orbit(270.0);
+        assert_relative_eq!(
+            builder.lookfrom.unwrap(),
+            Point3::new(1.0, 2.0, 5.0),
+            epsilon = 1e-5
+        );
+        assert_eq!(builder.lookat, Some(Point3::new(1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_tilt_dolly_pan() {
+        let mut builder = CameraBuilder::new();
+        builder
+            .lookfrom(Point3::new(1.0, 0.0, 4.0))
+            .lookat(Point3::new(1.0, 0.0, 0.0))
+            .vup(Vec3::new(0.0, 1.0, 0.0))
+            .focus_dist(4.0);
+        builder.tilt(90.0);
+        // Stops short of straight above
+        let above = builder.lookfrom.unwrap();
+        assert_relative_eq!(
+            above.y,
+            4.0 * (1.0 as Float).to_radians().cos(),
+            epsilon = 1e-5
+        );
+        assert!(above.z > 0.0);
+        builder.tilt(-89.0);
+        assert_relative_eq!(
+            builder.lookfrom.unwrap(),
+            Point3::new(1.0, 0.0, 4.0),
+            epsilon = 1e-5
+        );
+
+        builder.dolly(0.5);
+        assert_relative_eq!(
+            builder.lookfrom.unwrap(),
+            Point3::new(1.0, 0.0, 2.0),
+            epsilon = 1e-5
+        );
+        assert_relative_eq!(builder.focus_dist.unwrap(), 2.0, epsilon = 1e-5);
+
+        // Looking into -z, right is +x
+        builder.pan(0.5, -1.0);
+        assert_relative_eq!(
+            builder.lookfrom.unwrap(),
+            Point3::new(2.0, -2.0, 2.0),
+            epsilon = 1e-5
+        );
+        assert_relative_eq!(
+            builder.lookat.unwrap(),
+            Point3::new(2.0, -2.0, 0.0),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn test_frame_scene() {
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let mut world = World::new();
+        world.add(Sphere::new(-3.0, 0.0, 0.0, 1.0, &material));
+        world.add(Sphere::new(5.0, 2.0, -1.0, 2.0, &material));
+        let bbox = world.bounding_box().unwrap();
+        assert_eq!(bbox.minimum, Point3::new(-4.0, -1.0, -3.0));
+        assert_eq!(bbox.maximum, Point3::new(7.0, 4.0, 1.0));
+
+        let mut builder = CameraBuilder::new();
+        builder
+            .lookfrom(Point3::new(0.0, 0.0, 10.0))
+            .lookat(Point3::new(0.0, 0.0, 0.0))
+            .vfov(40.0)
+            .aspect_ratio(2.0)
+            .aperture(0.0)
+            .frame_scene(&world);
+        let lookfrom = builder.lookfrom.unwrap();
+        let lookat = builder.lookat.unwrap();
+        assert_eq!(lookat, Point3::new(1.5, 1.5, -1.0));
+        assert!(
+            (lookfrom - lookat)
+                .normalize()
+                .dot(&Vec3::new(0.0, 0.0, 1.0))
+                > 0.9999
+        );
+        builder.build().unwrap();
+
+        // All corners of the box are within the vertical field of view
+        let view_dir = (lookat - lookfrom).normalize();
+        for corner in 0..8 {
+            let pick = |bit: usize, axis: usize| {
+                if corner & (1 << bit) == 0 {
+                    bbox.minimum[axis]
+                } else {
+                    bbox.maximum[axis]
+                }
+            };
+            let p = Point3::new(pick(0, 0), pick(1, 1), pick(2, 2));
+            let angle = (p - lookfrom)
+                .normalize()
+                .dot(&view_dir)
+                .acos()
+                .to_degrees();
+            assert!(angle < 20.0, "corner {} is {}° off the view axis", p, angle);
+        }
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let mut builder = CameraBuilder::new();
+        assert_eq!(
+            builder.build().err(),
+            Some(CameraBuildError::MissingField("lookfrom"))
+        );
+        builder
+            .lookfrom(Point3::new(0.0, 0.0, 1.0))
+            .lookat(Point3::new(0.0, 0.0, 0.0))
+            .vup(Vec3::new(0.0, 1.0, 0.0))
+            .vfov(90.0)
+            .aspect_ratio(2.0)
+            .aperture(0.0);
+        assert_eq!(
+            builder.build().err(),
+            Some(CameraBuildError::MissingField("focus_dist"))
+        );
+        builder.focus_dist(1.0);
+        assert!(builder.build().is_ok());
+
+        let error = |builder: &mut CameraBuilder| builder.clone().build().err();
+        assert_eq!(
+            error(builder.clone().focus_dist(0.0)),
+            Some(CameraBuildError::FocusDistNotPositive(0.0))
+        );
+        assert_eq!(
+            error(builder.clone().vup(Vec3::zeros())),
+            Some(CameraBuildError::ZeroVup)
+        );
+        assert_eq!(
+            error(builder.clone().vfov(180.0)),
+            Some(CameraBuildError::VfovOutOfRange(180.0))
+        );
+        assert_eq!(
+            error(builder.clone().vfov(-10.0)),
+            Some(CameraBuildError::VfovOutOfRange(-10.0))
+        );
+        // Fisheyes see behind the camera
+        let mut fisheye = builder.clone();
+        fisheye.projection(Projection::FisheyeEquisolid);
+        assert!(fisheye.clone().vfov(360.0).build().is_ok());
+        assert_eq!(
+            error(fisheye.vfov(361.0)),
+            Some(CameraBuildError::VfovOutOfRange(361.0))
+        );
     }
 }
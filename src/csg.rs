@@ -0,0 +1,272 @@
+use std::sync::Arc;
+
+use crate::bvh::Aabb;
+use crate::hittables::{HitRecord, Hittable};
+use crate::util::{Float, Point3, Ray, Vec3};
+
+/// How `Csg` combines its two solids
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsgOp {
+    /// Points in either solid
+    Union,
+    /// Points in both solids
+    Intersection,
+    /// Points in `a` but not in `b`, e.g. a hole
+    Difference,
+}
+
+impl CsgOp {
+    fn contains(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            CsgOp::Union => in_a || in_b,
+            CsgOp::Intersection => in_a && in_b,
+            CsgOp::Difference => in_a && !in_b,
+        }
+    }
+}
+
+/// Constructive solid geometry: the solid made of the solids `a` and `b` by `op`.
+/// Both have to be closed surfaces that implement `Hittable::crossings`, like `Sphere`,
+/// `Cuboid`, `Capsule`, `Torus`, tapered cylinders and `Csg` itself. Each part of the surface
+/// keeps the material of the solid it comes from, so the walls of a hole have the material
+/// of the solid that is subtracted.
+pub struct Csg {
+    pub op: CsgOp,
+    pub a: Arc<dyn Hittable>,
+    pub b: Arc<dyn Hittable>,
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> Arc<dyn Hittable> {
+        // Objects without a volume return `None` for every ray
+        let probe = Ray::new(Point3::zeros(), Vec3::new(1.0, 0.0, 0.0));
+        for child in [a, b] {
+            assert!(
+                child.crossings(&probe).is_some(),
+                "{} is not a solid and can't be used in CSG",
+                child.name()
+            );
+        }
+        Arc::new(Csg {
+            op,
+            a: a.clone(),
+            b: b.clone(),
+        })
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        self.crossings(r)?
+            .into_iter()
+            .find(|hit| t_min < hit.t && hit.t < t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let (a, b) = (self.a.bounding_box(), self.b.bounding_box());
+        match self.op {
+            CsgOp::Union => Some(a?.surrounding(&b?)),
+            CsgOp::Intersection => match (a, b) {
+                (Some(a), Some(b)) => Some(a.intersection(&b)),
+                (a, b) => a.or(b),
+            },
+            CsgOp::Difference => a,
+        }
+    }
+
+    /// Walks along the crossings of both solids and keeps those where the ray enters or
+    /// leaves the combined solid. Concave solids are entered several times, which works the
+    /// same way.
+    fn crossings(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        let a = self.a.crossings(r)?;
+        let b = self.b.crossings(r)?;
+        // The ray starts inside a solid if it leaves it first (a hollow sphere is the space
+        // around it)
+        let mut in_a = a.first().is_some_and(|hit| !hit.front_face);
+        let mut in_b = b.first().is_some_and(|hit| !hit.front_face);
+        let mut inside = self.op.contains(in_a, in_b);
+
+        let mut crossings = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            // The state is set from each crossing instead of toggled, so a crossing missed by
+            // a tangent ray doesn't turn inside and outside around for the rest of the ray
+            let hit = if j == b.len() || (i < a.len() && a[i].t <= b[j].t) {
+                in_a = a[i].front_face;
+                i += 1;
+                &a[i - 1]
+            } else {
+                in_b = b[j].front_face;
+                j += 1;
+                &b[j - 1]
+            };
+            let now_inside = self.op.contains(in_a, in_b);
+            if now_inside != inside {
+                inside = now_inside;
+                // The normal already faces the ray, only entering and leaving can change,
+                // e.g. entering the subtracted solid leaves the result
+                let mut hit = hit.clone();
+                hit.front_face = inside;
+                crossings.push(hit);
+            }
+        }
+        Some(crossings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittables::{Cuboid, Cylinder, Quad, Sphere, Torus};
+    use crate::material::{Lambertian, Material};
+    use crate::util::Color;
+    use approx::assert_relative_eq;
+
+    fn material() -> Arc<dyn Material> {
+        Lambertian::new(Color::new(0.5, 0.5, 0.5))
+    }
+
+    /// t and front_face of all crossings
+    fn crossings(object: &Arc<dyn Hittable>, r: &Ray) -> Vec<(Float, bool)> {
+        object
+            .crossings(r)
+            .unwrap()
+            .iter()
+            .map(|hit| (hit.t, hit.front_face))
+            .collect()
+    }
+
+    fn assert_crossings(actual: &[(Float, bool)], expected: &[(Float, bool)]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        let eps = if cfg!(feature = "precision-f32") {
+            1e-4
+        } else {
+            1e-6
+        };
+        for ((t, front_face), (expected_t, expected_front_face)) in actual.iter().zip(expected) {
+            assert_relative_eq!(t, expected_t, epsilon = eps);
+            assert_eq!(front_face, expected_front_face, "{:?}", actual);
+        }
+    }
+
+    #[test]
+    fn test_cylindrical_hole_through_box() {
+        let material = material();
+        let cuboid = Cuboid::new(
+            Point3::new(-1.0, -1.0, -1.0),
+            Point3::new(1.0, 1.0, 1.0),
+            &material,
+        );
+        let drill = Cylinder::tapered(
+            Point3::new(0.0, -2.0, 0.0),
+            Point3::new(0.0, 2.0, 0.0),
+            0.5,
+            0.5,
+            &material,
+        );
+        let csg = Csg::new(CsgOp::Difference, &cuboid, &drill);
+
+        // Straight through the hole
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        assert!(csg
+            .hit(&Ray::new(Point3::new(0.0, 5.0, 0.0), down), 0.001, 100.0)
+            .is_none());
+        // Next to the hole onto the top face
+        let hit = csg
+            .hit(&Ray::new(Point3::new(0.8, 5.0, 0.0), down), 0.001, 100.0)
+            .unwrap();
+        assert_relative_eq!(hit.t, 4.0, epsilon = 1e-9);
+        assert_eq!(hit.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert!(hit.front_face);
+
+        // From the side across the hole: out of the box into the hole and back in
+        let side = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_crossings(
+            &crossings(&csg, &side),
+            &[(4.0, true), (4.5, false), (5.5, true), (6.0, false)],
+        );
+        let wall = csg.hit(&side, 4.1, 100.0).unwrap();
+        // Faces the ray, which leaves the solid through the wall of the hole
+        assert_relative_eq!(wall.normal, Vec3::new(-1.0, 0.0, 0.0), epsilon = 1e-9);
+        assert!(!wall.front_face);
+
+        let bbox = csg.bounding_box().unwrap();
+        assert_eq!(bbox.minimum, Point3::new(-1.0, -1.0, -1.0));
+        assert_eq!(bbox.maximum, Point3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_concave_child() {
+        let material = material();
+        let torus = Torus::new(
+            Point3::zeros(),
+            Vec3::new(0.0, 1.0, 0.0),
+            2.0,
+            0.5,
+            &material,
+        );
+        // Cuts away the upper half of the ring
+        let upper = Cuboid::new(
+            Point3::new(-3.0, 0.0, -3.0),
+            Point3::new(3.0, 1.0, 3.0),
+            &material,
+        );
+        let half_ring = Csg::new(CsgOp::Difference, &torus, &upper);
+
+        // Below the cut the ray passes both sides of the ring
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let offset = (0.25 as Float - 0.01).sqrt();
+        assert_crossings(
+            &crossings(&half_ring, &Ray::new(Point3::new(-5.0, -0.1, 0.0), x)),
+            &[
+                (3.0 - offset, true),
+                (3.0 + offset, false),
+                (7.0 - offset, true),
+                (7.0 + offset, false),
+            ],
+        );
+        assert!(crossings(&half_ring, &Ray::new(Point3::new(-5.0, 0.1, 0.0), x)).is_empty());
+
+        // From above the ray enters through the cut face
+        let down = Ray::new(Point3::new(2.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert_crossings(&crossings(&half_ring, &down), &[(5.0, true), (5.5, false)]);
+        assert_eq!(
+            half_ring.hit(&down, 0.001, 100.0).unwrap().normal,
+            Vec3::new(0.0, 1.0, 0.0)
+        );
+
+        // A concave CSG solid as a child: only the part of the ring inside the sphere
+        let sphere = Sphere::new(2.0, 0.0, 0.0, 1.0, &material);
+        let piece = Csg::new(CsgOp::Intersection, &half_ring, &sphere);
+        assert_crossings(
+            &crossings(&piece, &Ray::new(Point3::new(-5.0, -0.1, 0.0), x)),
+            &[(7.0 - offset, true), (7.0 + offset, false)],
+        );
+        let union = Csg::new(CsgOp::Union, &half_ring, &sphere);
+        // Through the sphere, which swallows the right side of the ring
+        let sphere_x = (1.0 as Float - 0.01).sqrt();
+        assert_crossings(
+            &crossings(&union, &Ray::new(Point3::new(-5.0, -0.1, 0.0), x)),
+            &[
+                (3.0 - offset, true),
+                (3.0 + offset, false),
+                (7.0 - sphere_x, true),
+                (7.0 + sphere_x, false),
+            ],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Quad is not a solid")]
+    fn test_flat_children_are_rejected() {
+        let material = material();
+        let quad = Quad::new(
+            Point3::zeros(),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            &material,
+        );
+        let sphere = Sphere::new(0.0, 0.0, 0.0, 1.0, &material);
+        Csg::new(CsgOp::Union, &sphere, &quad);
+    }
+}
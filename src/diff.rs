@@ -0,0 +1,120 @@
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use image::{Rgb, RgbImage};
+
+/// Per channel differences of two 8 bit images, in 0..255
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiffStats {
+    pub mean_abs_error: f64,
+    pub max_abs_error: u8,
+    pub mse: f64,
+    /// Infinite for identical images
+    pub psnr: f64,
+}
+
+impl fmt::Display for DiffStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mean abs error {:.3}, max abs error {}, MSE {:.3}, PSNR {:.2} dB",
+            self.mean_abs_error, self.max_abs_error, self.mse, self.psnr
+        )
+    }
+}
+
+/// Loads both images and compares them with `diff`
+pub fn diff_files(
+    a: impl AsRef<Path>,
+    b: impl AsRef<Path>,
+) -> Result<(RgbImage, DiffStats), Box<dyn Error + Send + Sync + 'static>> {
+    let load = |path: &Path| {
+        image::open(path)
+            .map(|img| img.into_rgb8())
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    };
+    Ok(diff(&load(a.as_ref())?, &load(b.as_ref())?)?)
+}
+
+/// Heatmap of the largest channel difference of each pixel (black: equal, white: the
+/// largest difference in the image) and the error statistics over all RGB channels
+pub fn diff(a: &RgbImage, b: &RgbImage) -> Result<(RgbImage, DiffStats), String> {
+    if a.dimensions() != b.dimensions() {
+        return Err(format!(
+            "Image sizes differ: {}x{} and {}x{}",
+            a.width(),
+            a.height(),
+            b.width(),
+            b.height()
+        ));
+    }
+
+    let pixel_error =
+        |pa: &Rgb<u8>, pb: &Rgb<u8>| (0..3).map(|c| pa[c].abs_diff(pb[c])).max().unwrap_or(0);
+    let mut sum_abs = 0u64;
+    let mut sum_sq = 0u64;
+    let mut max_abs_error = 0u8;
+    for (pa, pb) in a.pixels().zip(b.pixels()) {
+        for c in 0..3 {
+            let d = pa[c].abs_diff(pb[c]) as u64;
+            sum_abs += d;
+            sum_sq += d * d;
+        }
+        max_abs_error = max_abs_error.max(pixel_error(pa, pb));
+    }
+    let n = (a.width() as u64 * a.height() as u64 * 3).max(1) as f64;
+    let mse = sum_sq as f64 / n;
+    let stats = DiffStats {
+        mean_abs_error: sum_abs as f64 / n,
+        max_abs_error,
+        mse,
+        psnr: 10.0 * (255.0 * 255.0 / mse).log10(),
+    };
+
+    let heatmap = RgbImage::from_fn(a.width(), a.height(), |x, y| {
+        let error = pixel_error(a.get_pixel(x, y), b.get_pixel(x, y));
+        let t = if max_abs_error == 0 {
+            0.0
+        } else {
+            error as f64 / max_abs_error as f64
+        };
+        heat_color(t)
+    });
+    Ok((heatmap, stats))
+}
+
+/// Black over red and yellow to white for t in 0..1
+fn heat_color(t: f64) -> Rgb<u8> {
+    let channel = |offset: f64| ((3.0 * t - offset).clamp(0.0, 1.0) * 255.0).round() as u8;
+    Rgb([channel(0.0), channel(1.0), channel(2.0)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let a = RgbImage::from_fn(4, 2, |x, _y| Rgb([x as u8 * 10, 0, 0]));
+        let mut b = a.clone();
+        let (heatmap, stats) = diff(&a, &b).unwrap();
+        assert_eq!(stats.max_abs_error, 0);
+        assert_eq!(stats.mse, 0.0);
+        assert!(stats.psnr.is_infinite());
+        assert!(heatmap.pixels().all(|p| *p == Rgb([0, 0, 0])));
+
+        b.put_pixel(1, 1, Rgb([10, 6, 0]));
+        b.put_pixel(3, 0, Rgb([30, 0, 3]));
+        let (heatmap, stats) = diff(&a, &b).unwrap();
+        assert_eq!(stats.max_abs_error, 6);
+        assert_eq!(stats.mean_abs_error, 9.0 / 24.0);
+        assert_eq!(stats.mse, 45.0 / 24.0);
+        assert_eq!(*heatmap.get_pixel(1, 1), Rgb([255, 255, 255]));
+        assert_eq!(*heatmap.get_pixel(3, 0), Rgb([255, 128, 0]));
+        assert_eq!(*heatmap.get_pixel(0, 0), Rgb([0, 0, 0]));
+
+        let err = diff(&a, &RgbImage::new(4, 3)).unwrap_err();
+        assert_eq!(err, "Image sizes differ: 4x2 and 4x3");
+    }
+}
@@ -0,0 +1,191 @@
+use std::path::Path;
+
+use image::{ImageResult, Rgb32FImage};
+use rand::Rng;
+
+use crate::texture::load_float_image;
+use crate::util::{consts::PI, luminance, Color, Float, Vec3};
+
+/// Light arriving from infinitely far away, stored as a latitude-longitude image.
+/// The top row is +y, the center column looks into -z.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    /// Cumulative distribution of the rows, `height + 1` entries from 0 to 1
+    marginal_cdf: Vec<Float>,
+    /// Cumulative distribution of the texels within each row, `width + 1` entries per row
+    conditional_cdf: Vec<Float>,
+}
+
+impl EnvironmentMap {
+    /// Loads an image, usually a Radiance `.hdr` or an OpenEXR file
+    pub fn load(path: impl AsRef<Path>) -> ImageResult<Self> {
+        Ok(Self::from_image(&load_float_image(path)?))
+    }
+
+    pub fn from_image(img: &Rgb32FImage) -> Self {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        assert!(width > 0 && height > 0, "Empty environment map");
+        let pixels: Vec<Color> = img
+            .pixels()
+            .map(|p| Color::new(p[0] as Float, p[1] as Float, p[2] as Float))
+            .collect();
+
+        // Texels near the poles cover less solid angle, weight them by sin(theta)
+        let mut conditional_cdf = Vec::with_capacity(height * (width + 1));
+        let mut row_sums = Vec::with_capacity(height);
+        for y in 0..height {
+            let sin_theta = (PI * (y as Float + 0.5) / height as Float).sin();
+            let mut sum = 0.0;
+            conditional_cdf.push(0.0);
+            for x in 0..width {
+                sum += luminance(&pixels[y * width + x]) * sin_theta;
+                conditional_cdf.push(sum);
+            }
+            normalize_cdf(&mut conditional_cdf[y * (width + 1)..]);
+            row_sums.push(sum);
+        }
+        let mut marginal_cdf = Vec::with_capacity(height + 1);
+        marginal_cdf.push(0.0);
+        let mut sum = 0.0;
+        for row_sum in row_sums {
+            sum += row_sum;
+            marginal_cdf.push(sum);
+        }
+        normalize_cdf(&mut marginal_cdf);
+
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            marginal_cdf,
+            conditional_cdf,
+        }
+    }
+
+    /// Radiance arriving from direction `dir`
+    pub fn eval(&self, dir: &Vec3) -> Color {
+        let (x, y) = self.texel(dir);
+        self.pixels[y * self.width + x]
+    }
+
+    /// Direction sampled proportional to the brightness of the map, and its probability
+    /// density with respect to solid angle (for weighting with other sampling strategies)
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> (Vec3, Float) {
+        let y = sample_cdf(&self.marginal_cdf, rng.gen());
+        let row = &self.conditional_cdf[y * (self.width + 1)..(y + 1) * (self.width + 1)];
+        let x = sample_cdf(row, rng.gen());
+
+        let u = (x as Float + rng.gen::<Float>()) / self.width as Float;
+        let v = (y as Float + rng.gen::<Float>()) / self.height as Float;
+        let dir = direction(u, v);
+        (dir, self.texel_pdf(x, y, v))
+    }
+
+    /// Probability density of `sample` returning `dir`, with respect to solid angle
+    pub fn pdf(&self, dir: &Vec3) -> Float {
+        let (x, y) = self.texel(dir);
+        let v = dir.normalize().y.clamp(-1.0, 1.0).acos() / PI;
+        self.texel_pdf(x, y, v)
+    }
+
+    fn texel_pdf(&self, x: usize, y: usize, v: Float) -> Float {
+        let row = y * (self.width + 1);
+        let p_row = self.marginal_cdf[y + 1] - self.marginal_cdf[y];
+        let p_texel = p_row * (self.conditional_cdf[row + x + 1] - self.conditional_cdf[row + x]);
+        // Density on the unit square, converted to solid angle
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        p_texel * (self.width * self.height) as Float / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn texel(&self, dir: &Vec3) -> (usize, usize) {
+        let dir = dir.normalize();
+        let u = 0.5 + dir.x.atan2(-dir.z) / (2.0 * PI);
+        let v = dir.y.clamp(-1.0, 1.0).acos() / PI;
+        let x = ((u * self.width as Float) as usize).min(self.width - 1);
+        let y = ((v * self.height as Float) as usize).min(self.height - 1);
+        (x, y)
+    }
+}
+
+/// Inverse of `EnvironmentMap::texel`, u and v are in 0..1
+fn direction(u: Float, v: Float) -> Vec3 {
+    let phi = (u - 0.5) * 2.0 * PI;
+    let theta = v * PI;
+    Vec3::new(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    )
+}
+
+/// Scales the running sums to end at 1. All black falls back to a uniform distribution.
+fn normalize_cdf(cdf: &mut [Float]) {
+    let n = cdf.len() - 1;
+    let total = cdf[n];
+    for (i, value) in cdf.iter_mut().enumerate() {
+        *value = if total > 0.0 {
+            *value / total
+        } else {
+            i as Float / n as Float
+        };
+    }
+}
+
+/// Index of the bucket `cdf[i]..cdf[i + 1]` containing `xi`, skipping empty buckets
+fn sample_cdf(cdf: &[Float], xi: Float) -> usize {
+    let n = cdf.len() - 1;
+    cdf.partition_point(|&value| value <= xi)
+        .saturating_sub(1)
+        .min(n - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_sampling_concentrates_on_bright_texel() {
+        let (width, height) = (64, 32);
+        let (sun_x, sun_y) = (40, 10);
+        let img = Rgb32FImage::from_fn(width, height, |x, y| {
+            if (x, y) == (sun_x, sun_y) {
+                Rgb([5000.0, 5000.0, 4000.0])
+            } else {
+                Rgb([0.2, 0.3, 0.5])
+            }
+        });
+        let env = EnvironmentMap::from_image(&img);
+        let sun = direction(
+            (sun_x as Float + 0.5) / width as Float,
+            (sun_y as Float + 0.5) / height as Float,
+        );
+        assert_eq!(env.texel(&sun), (sun_x as usize, sun_y as usize));
+
+        let mut rng = SmallRng::seed_from_u64(5);
+        let n = 10000;
+        let mut near_sun = 0;
+        for _ in 0..n {
+            let (dir, pdf) = env.sample(&mut rng);
+            assert!((dir.magnitude() - 1.0).abs() < 1e-4);
+            assert!((env.pdf(&dir) - pdf).abs() <= 1e-3 * pdf);
+            if dir.dot(&sun) > (6.0 as Float).to_radians().cos() {
+                near_sun += 1;
+            }
+        }
+        // The sun has about 85% of the weight but covers less than 0.1% of the sphere
+        assert!(
+            near_sun > n * 3 / 4,
+            "only {} of {} near the sun",
+            near_sun,
+            n
+        );
+    }
+}
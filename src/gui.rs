@@ -1,23 +1,37 @@
 use std::{
     ops::RangeInclusive,
+    path::PathBuf,
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering::Relaxed},
         Arc, Mutex,
     },
+    time::{Duration, Instant},
 };
 
 use eframe::{
-    egui::{self, Context, Id, Slider, Ui},
+    egui::{self, Context, Id, Key, Rect, Sense, Slider, Ui},
     epaint::{ColorImage, Pos2, Vec2},
     NativeOptions,
 };
 use egui_extras::RetainedImage;
-use image::RgbImage;
+use image::{RgbImage, RgbaImage};
+use log::debug;
 use poll_promise::Promise;
 
-use crate::{camera::CameraBuilder, util::ProgressBarWrapper, world::World, RaytraceParams};
+use crate::{
+    camera::{CameraBuilder, Projection},
+    util::{Float, ProgressBarWrapper, SampleEvent},
+    world::{RayCounters, World},
+    RaytraceParams, SamplesAdder,
+};
 
-pub fn run_gui(params: RaytraceParams, world: World, camerabuilder: CameraBuilder) {
+/// `save_path` is where "Save PNG" and auto-save write to, initially the output filename
+pub fn run_gui(
+    params: RaytraceParams,
+    world: World,
+    camerabuilder: CameraBuilder,
+    save_path: PathBuf,
+) {
     let options = eframe::NativeOptions {
         initial_window_size: Some(Vec2::new(2000.0, 1300.0)),
         initial_window_pos: Some(Pos2::new(600.0, 300.0)),
@@ -26,54 +40,154 @@ pub fn run_gui(params: RaytraceParams, world: World, camerabuilder: CameraBuilde
     eframe::run_native(
         "Raytracer",
         options,
-        Box::new(move |_cc| Box::new(RaytracerApp::new(params, world, camerabuilder))),
+        Box::new(move |_cc| Box::new(RaytracerApp::new(params, world, camerabuilder, save_path))),
     );
 }
 
+/// Fraction of the image width rendered while a slider is dragged
+const PREVIEW_SCALE: u32 = 4;
+/// Time after the last slider change until the full resolution is rendered
+const PREVIEW_SETTLE: Duration = Duration::from_millis(200);
+/// Camera rotation when dragging the image by one point
+const ORBIT_DEGREES_PER_POINT: Float = 0.3;
+/// Scrolling this many points moves the camera e times closer
+const DOLLY_POINTS: Float = 500.0;
+/// Pan speed with WASD, in distances between camera and `lookat` per second
+const PAN_SPEED: Float = 0.5;
+
+/// Render settings of a preset button in the control panel
+struct Preset {
+    name: &'static str,
+    samples_per_pixel: u32,
+    max_depth: u32,
+    image_width: u32,
+    light_samples: u32,
+}
+
+const PRESETS: [Preset; 3] = [
+    Preset {
+        name: "Draft",
+        samples_per_pixel: 1,
+        max_depth: 5,
+        image_width: 200,
+        light_samples: 1,
+    },
+    Preset {
+        name: "Preview",
+        samples_per_pixel: 16,
+        max_depth: 20,
+        image_width: 400,
+        light_samples: 1,
+    },
+    Preset {
+        name: "Final",
+        samples_per_pixel: 500,
+        max_depth: 50,
+        image_width: 1200,
+        light_samples: 4,
+    },
+];
+
+impl Preset {
+    /// Returns true if the image width changed
+    fn apply(&self, params: &mut RaytraceParams) -> bool {
+        params.samples_per_pixel = self.samples_per_pixel;
+        params.max_depth = self.max_depth;
+        params.light_samples = self.light_samples;
+        let resized = params.image_width != self.image_width;
+        params.image_width = self.image_width;
+        resized
+    }
+}
+
 struct RaytracerApp {
     startup_done: bool,
     render_action: Option<RenderAction>,
     final_render: Option<RetainedImage>,
+    /// Linear samples of `final_render`, to display them again with other display settings
+    final_accumulator: Option<SamplesAdder>,
+    /// Changes that only affect the display (like dithering) reuse the samples instead of
+    /// rendering again
+    accumulate: bool,
     num_draws: u32,
     params: RaytraceParams,
     world: Arc<World>,
     camerabuilder: CameraBuilder,
+    /// Time of the last slider change by dragging, `None` once the interaction is over
+    last_interaction: Option<Instant>,
+    /// The current or last render has a reduced resolution
+    showing_preview: bool,
+    /// Cost of the last finished render, shown in the status bar
+    render_summary: Option<String>,
+    /// Target of "Save PNG" and of the auto-save
+    save_path: String,
+    /// Write the running render to `save_path` every `autosave_every` samples
+    autosave: bool,
+    autosave_every: u32,
+    /// Result of the last "Save PNG"
+    save_status: Option<String>,
+    /// Visible part of the image in the last frame, where the mouse moves the camera
+    image_rect: Option<Rect>,
 }
 
 struct RenderAction {
-    image_promise: Promise<RetainedImage>,
+    image_promise: Promise<SamplesAdder>,
     immediate_image: Option<RetainedImage>,
+    /// Pixels of `immediate_image`, to save them
+    immediate_pixels: Option<ColorImage>,
     progress: Arc<ProgressInfo>,
     stop: Arc<AtomicBool>,
 }
 
 impl RenderAction {
     fn take_immediate_image(&mut self) {
-        let mut immediate_image = self.progress.immediate_image.lock().unwrap();
-        if let Some(immediate_image) = immediate_image.take() {
+        let immediate_image = self.progress.immediate_image.lock().unwrap().take();
+        if let Some(immediate_image) = immediate_image {
             self.immediate_image = Some(RetainedImage::from_color_image(
                 "immediate_image",
-                immediate_image,
+                immediate_image.clone(),
             ));
+            self.immediate_pixels = Some(immediate_image);
         }
     }
 }
 
+/// What "Save PNG" writes, see `RaytracerApp::image_to_save`
+enum SaveSource<'a> {
+    Running(&'a ColorImage),
+    Finished(&'a SamplesAdder),
+}
+
+/// The previews are gamma encoded and dithered like `SamplesAdder::normalized`
+fn colorimage_to_rgba(img: &ColorImage) -> RgbaImage {
+    let [width, height] = img.size;
+    RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+        image::Rgba(img.pixels[y as usize * width + x as usize].to_array())
+    })
+}
+
 struct ProgressInfo {
     current: AtomicU64,
     len: AtomicU64,
     finished: AtomicBool,
     immediate_image: Mutex<Option<ColorImage>>,
+    /// Size of the rendered image
+    resolution: (u32, u32),
+    last_sample: Mutex<Option<SampleEvent>>,
+    ray_counters: Mutex<Option<RayCounters>>,
     ctx: egui::Context,
 }
 
 impl ProgressInfo {
-    fn new(ctx: egui::Context) -> Self {
+    fn new(ctx: egui::Context, resolution: (u32, u32)) -> Self {
         ProgressInfo {
             current: 0.into(),
             len: 0.into(),
             finished: false.into(),
             immediate_image: Mutex::new(None),
+            resolution,
+            last_sample: Mutex::new(None),
+            ray_counters: Mutex::new(None),
             ctx,
         }
     }
@@ -81,6 +195,20 @@ impl ProgressInfo {
     fn percentage(&self) -> f32 {
         self.current.load(Relaxed) as f32 / self.len.load(Relaxed) as f32
     }
+
+    /// E.g. "400×225 px, 10 samples in 1.52s, 484 objects, 1203344 rays"
+    fn summary(&self, num_objects: usize, with_rays: bool) -> String {
+        let (width, height) = self.resolution;
+        let mut summary = format!("{}×{} px", width, height);
+        if let Some(event) = *self.last_sample.lock().unwrap() {
+            summary += &format!(", {} samples in {:.2?}", event.completed, event.elapsed);
+        }
+        summary += &format!(", {} objects", num_objects);
+        if let (true, Some(counters)) = (with_rays, *self.ray_counters.lock().unwrap()) {
+            summary += &format!(", {} rays", counters.rays);
+        }
+        summary
+    }
 }
 
 impl ProgressBarWrapper for Arc<ProgressInfo> {
@@ -89,9 +217,13 @@ impl ProgressBarWrapper for Arc<ProgressInfo> {
         self.ctx.request_repaint();
     }
 
-    fn inc(&self, delta: u64, get_immediate_image: &dyn Fn() -> ColorImage) {
+    fn inc(&self, delta: u64, get_immediate_image: Option<&dyn Fn() -> ColorImage>) {
         self.current.fetch_add(delta, Relaxed);
-        *self.immediate_image.lock().unwrap() = Some(get_immediate_image());
+        if let Some(get_immediate_image) = get_immediate_image {
+            // Normalize before locking, the GUI thread takes the image from the same lock
+            let image = get_immediate_image();
+            *self.immediate_image.lock().unwrap() = Some(image);
+        }
         self.ctx.request_repaint();
     }
 
@@ -99,22 +231,68 @@ impl ProgressBarWrapper for Arc<ProgressInfo> {
         self.finished.store(true, Relaxed);
         self.ctx.request_repaint();
     }
+
+    fn on_sample_complete(&self, event: &SampleEvent) {
+        let mut last_sample = self.last_sample.lock().unwrap();
+        // Samples finish in any order
+        if last_sample.is_none_or(|last| last.completed < event.completed) {
+            *last_sample = Some(*event);
+        }
+    }
+
+    fn on_render_stats(&self, counters: &RayCounters) {
+        *self.ray_counters.lock().unwrap() = Some(*counters);
+    }
 }
 
 impl RaytracerApp {
-    fn new(params: RaytraceParams, world: World, camerabuilder: CameraBuilder) -> Self {
+    fn new(
+        params: RaytraceParams,
+        world: World,
+        camerabuilder: CameraBuilder,
+        save_path: PathBuf,
+    ) -> Self {
         RaytracerApp {
+            autosave: params.progressive_output.is_some(),
+            autosave_every: params.progressive_output.unwrap_or(10),
             startup_done: false,
             render_action: None,
             final_render: None,
+            final_accumulator: None,
+            accumulate: false,
             params,
             world: Arc::new(world),
             camerabuilder,
             num_draws: 0,
+            last_interaction: None,
+            showing_preview: false,
+            render_summary: None,
+            save_path: save_path.display().to_string(),
+            save_status: None,
+            image_rect: None,
         }
     }
 
-    fn start_render(&mut self, ctx: &egui::Context) {
+    /// A slider is dragged or was changed by dragging a moment ago
+    fn interaction_in_progress(&self, ctx: &egui::Context) -> bool {
+        self.last_interaction
+            .map(|t| ctx.is_using_pointer() || t.elapsed() < PREVIEW_SETTLE)
+            .unwrap_or(false)
+    }
+
+    /// With `quick_preview`, a single sample at reduced resolution is shown first, e.g. after a
+    /// resize, where the previous image doesn't fit anymore
+    fn start_render(&mut self, ctx: &egui::Context, quick_preview: bool) {
+        self.camerabuilder.aspect_ratio(self.params.aspect_ratio);
+        let camera = match self.camerabuilder.build() {
+            Ok(camera) => camera,
+            Err(e) => {
+                // Keep showing the last image
+                self.render_summary = Some(format!("Invalid camera: {}", e));
+                return;
+            }
+        };
+
         if let Some(old_render_action) = self.render_action.take() {
             old_render_action.stop.store(true, Relaxed);
             // Prevent flickering by not falling back to the last finished render
@@ -125,31 +303,51 @@ impl RaytracerApp {
 
         let (sender, promise) = Promise::new();
 
+        let mut params = self.params.clone();
+        self.showing_preview = self.interaction_in_progress(ctx);
+        if self.showing_preview {
+            params.image_width = (params.image_width / PREVIEW_SCALE).max(50);
+        }
+        // Written like the command line output by `accumulate`, but only at full resolution
+        params.autosave_interval = None;
+        if self.autosave && !self.showing_preview {
+            params.autosave_path = Some(PathBuf::from(&self.save_path));
+            params.progressive_output = Some(self.autosave_every);
+        } else {
+            params.autosave_path = None;
+            params.progressive_output = None;
+        }
+
+        let resolution = (params.image_width, params.image_height());
         let render_action = RenderAction {
             image_promise: promise,
             immediate_image: None,
-            progress: Arc::new(ProgressInfo::new(ctx.clone())),
+            immediate_pixels: None,
+            progress: Arc::new(ProgressInfo::new(ctx.clone(), resolution)),
             stop: Arc::new(AtomicBool::new(false)),
         };
-
-        self.camerabuilder.aspect_ratio(self.params.aspect_ratio);
-
-        let params = self.params.clone();
+        let quick_preview = quick_preview && !self.showing_preview;
         let world = Arc::clone(&self.world);
-        let camera = self.camerabuilder.build().unwrap();
         let stop = Arc::clone(&render_action.stop);
 
-        let progress: Box<dyn ProgressBarWrapper> = Box::new(Arc::clone(&render_action.progress));
+        let progress = Arc::clone(&render_action.progress);
 
-        println!("Start render with vfow={:?}", camera.vertical);
+        debug!("Start render with vfow={:?}", camera.vertical);
         rayon::spawn(move || {
-            let img = crate::render_live(&params, &world, &camera, &progress, stop);
-            let img = ColorImage::from_rgba_unmultiplied(
-                [img.width() as usize, img.height() as usize],
-                img.as_flat_samples().samples,
-            );
-            println!("Done rendering with vfow={:?}", camera.vertical);
-            sender.send(RetainedImage::from_color_image("rendered_image", img));
+            if quick_preview {
+                let mut preview_params = params.clone();
+                preview_params.image_width = (params.image_width / PREVIEW_SCALE).max(50);
+                let (img, _) =
+                    crate::render_sample(&preview_params, &world, &camera, 0, stop.clone());
+                let mut preview =
+                    SamplesAdder::new(preview_params.image_width, preview_params.image_height());
+                preview.add_image(&img);
+                progress.inc(0, Some(&|| preview.normalized_colorimage(params.dither)));
+            }
+            let adder = SamplesAdder::new(params.image_width, params.image_height());
+            let adder = crate::accumulate(&params, &world, &camera, &progress, stop, adder);
+            debug!("Done rendering with vfow={:?}", camera.vertical);
+            sender.send(adder);
         });
 
         self.render_action = Some(render_action);
@@ -164,12 +362,97 @@ impl RaytracerApp {
 
         if render_available {
             let render_action = self.render_action.take().unwrap();
-            let image = render_action.image_promise.try_take().ok().unwrap();
-            println!("Get finished render");
-            self.final_render = Some(image);
+            self.render_summary = Some(
+                render_action
+                    .progress
+                    .summary(self.world.len(), self.params.stats),
+            );
+            let adder = render_action.image_promise.try_take().ok().unwrap();
+            debug!("Get finished render");
+            self.final_accumulator = Some(adder);
+            self.show_final_accumulator();
         }
     }
 
+    /// Updates `final_render` with the current display settings
+    fn show_final_accumulator(&mut self) {
+        if let Some(adder) = &self.final_accumulator {
+            let img = adder.normalized_colorimage(self.params.dither);
+            self.final_render = Some(RetainedImage::from_color_image("rendered_image", img));
+        }
+    }
+
+    /// The image on display at full resolution: the running render so far, or else the last
+    /// finished one. `None` while a reduced preview is shown, and while the running render has
+    /// no full resolution image yet, the previous image was rendered with other settings.
+    fn image_to_save(&self) -> Option<SaveSource<'_>> {
+        if self.showing_preview {
+            return None;
+        }
+        match &self.render_action {
+            Some(ra) => {
+                let (width, height) = ra.progress.resolution;
+                ra.immediate_pixels
+                    .as_ref()
+                    // Not the quick preview after a resize
+                    .filter(|pixels| pixels.size == [width as usize, height as usize])
+                    .map(SaveSource::Running)
+            }
+            None => self.final_accumulator.as_ref().map(SaveSource::Finished),
+        }
+    }
+
+    fn save_image(&mut self) {
+        let img = self.image_to_save().map(|source| match source {
+            SaveSource::Running(pixels) => colorimage_to_rgba(pixels),
+            SaveSource::Finished(adder) => adder.normalized(self.params.dither),
+        });
+        self.save_status = Some(match img {
+            None => "Nothing rendered yet".to_string(),
+            Some(img) => match img.save(&self.save_path) {
+                Ok(()) => format!("Saved {}", self.save_path),
+                Err(e) => format!("Saving {} failed: {}", self.save_path, e),
+            },
+        });
+    }
+
+    /// Orbits the camera when the image is dragged, dollies when scrolling over it and pans
+    /// with WASD. Returns true if the camera moved.
+    fn navigate(&mut self, ctx: &egui::Context, drag_delta: Vec2, over_image: bool) -> bool {
+        let (scroll, zoom, dt) = {
+            let input = ctx.input();
+            (input.scroll_delta.y, input.zoom_delta(), input.stable_dt)
+        };
+        let mut moved = false;
+
+        if drag_delta != Vec2::ZERO {
+            self.camerabuilder
+                .orbit(-drag_delta.x as Float * ORBIT_DEGREES_PER_POINT)
+                .tilt(drag_delta.y as Float * ORBIT_DEGREES_PER_POINT);
+            moved = true;
+        }
+        // Pinching and ctrl+scrolling zoom the image instead
+        if over_image && scroll != 0.0 && zoom == 1.0 {
+            self.camerabuilder
+                .dolly((-scroll as Float / DOLLY_POINTS).exp());
+            moved = true;
+        }
+
+        if !ctx.wants_keyboard_input() {
+            let step = dt.min(0.1) as Float * PAN_SPEED;
+            let key_down = |key| ctx.input().key_down(key);
+            let right = step * (key_down(Key::D) as i32 - key_down(Key::A) as i32) as Float;
+            let up = step * (key_down(Key::W) as i32 - key_down(Key::S) as i32) as Float;
+            if right != 0.0 || up != 0.0 {
+                self.camerabuilder.pan(right, up);
+                // Keep moving while the key is held
+                ctx.request_repaint();
+                moved = true;
+            }
+        }
+        moved
+    }
+
     /// Returns true on change
     fn slider<Num: eframe::emath::Numeric>(
         ui: &mut Ui,
@@ -200,7 +483,7 @@ impl RaytracerApp {
 impl eframe::App for RaytracerApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         if !self.startup_done {
-            self.start_render(ctx);
+            self.start_render(ctx, false);
             self.startup_done = true;
         }
 
@@ -219,12 +502,25 @@ impl eframe::App for RaytracerApp {
 
         self.check_render_finished();
 
+        // Replace the preview once the user stops dragging
+        if self.showing_preview {
+            if self.interaction_in_progress(ctx) {
+                ctx.request_repaint_after(PREVIEW_SETTLE);
+            } else {
+                self.last_interaction = None;
+                self.start_render(ctx, false);
+            }
+        }
+
         egui::TopBottomPanel::bottom("status_bar")
             .default_height(40.0)
             .show(ctx, |ui| {
                 ui.horizontal(|ui| {
                     ui.label(format!("Drawn {} times.", self.num_draws));
                     ui.add(progressbar);
+                    if let Some(summary) = &self.render_summary {
+                        ui.label(summary);
+                    }
                     ui.allocate_space(ui.available_size());
                 });
             });
@@ -237,19 +533,24 @@ impl eframe::App for RaytracerApp {
             .show(ctx, |ui| {
                 ui.heading("Raytracer");
                 if ui.button("Render").clicked() {
-                    self.start_render(ctx);
+                    self.start_render(ctx, false);
                 }
                 ui.style_mut().spacing.slider_width = 400.0;
 
                 let mut changed = false;
 
                 ui.heading("Camera");
+                // The perspective projection can't show 180° and more
+                let max_vfov = match self.camerabuilder.projection {
+                    None | Some(Projection::Perspective) => 179.0,
+                    Some(_) => 360.0,
+                };
                 changed |= Self::slider(
                     ui,
                     self.camerabuilder.vfov.as_mut().unwrap(),
                     "Vertical Field of View",
                     "°",
-                    30.0..=180.0,
+                    30.0..=max_vfov,
                     |s| s,
                 );
                 changed |= Self::slider(
@@ -346,6 +647,15 @@ impl eframe::App for RaytracerApp {
                 );
 
                 ui.heading("Rendering");
+                let mut preset_resized = false;
+                ui.horizontal(|ui| {
+                    for preset in &PRESETS {
+                        if ui.button(preset.name).clicked() {
+                            preset_resized |= preset.apply(&mut self.params);
+                            changed = true;
+                        }
+                    }
+                });
                 changed |= Self::slider(
                     ui,
                     &mut self.params.aspect_ratio,
@@ -371,24 +681,95 @@ impl eframe::App for RaytracerApp {
                     |s| s,
                 );
                 changed |= Self::slider(
+                    ui,
+                    &mut self.params.background_intensity,
+                    "Background intensity",
+                    "",
+                    0.0..=2.0,
+                    |s| s,
+                );
+                changed |= Self::slider(
+                    ui,
+                    &mut self.params.light_samples,
+                    "Light samples",
+                    "",
+                    1..=64,
+                    |s: egui::Slider| s.logarithmic(true),
+                );
+                let resized = Self::slider(
                     ui,
                     &mut self.params.image_width,
                     "Image Width",
                     "px",
                     50..=3000,
                     |s| s,
-                );
-                if changed {
-                    self.start_render(ui.ctx());
+                ) || preset_resized;
+                changed |= resized;
+                ui.add_space(5.0);
+                ui.horizontal(|ui| {
+                    // Same seed and settings give the same noise as on the command line
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut self.params.seed))
+                        .changed();
+                    ui.label("Seed");
+                });
+                ui.add_space(5.0);
+                let display_changed = ui.checkbox(&mut self.params.dither, "Dither").changed();
+                ui.add_space(5.0);
+                ui.checkbox(&mut self.accumulate, "Keep samples on display changes");
+                if display_changed && !changed && self.accumulate {
+                    // A running render picks up the new settings when it finishes
+                    if self.render_action.is_none() {
+                        self.show_final_accumulator();
+                    }
+                } else if changed || display_changed {
+                    if ui.ctx().is_using_pointer() {
+                        self.last_interaction = Some(Instant::now());
+                    }
+                    if resized {
+                        // Never show the samples of the old size again on a display change
+                        self.final_accumulator = None;
+                    }
+                    self.start_render(ui.ctx(), resized);
+                }
+
+                ui.heading("Output");
+                ui.add_space(5.0);
+                ui.text_edit_singleline(&mut self.save_path);
+                ui.add_space(5.0);
+                let can_save = self.image_to_save().is_some();
+                if ui
+                    .add_enabled(can_save, egui::Button::new("Save PNG"))
+                    .clicked()
+                {
+                    self.save_image();
+                }
+                if let Some(status) = &self.save_status {
+                    ui.label(status);
                 }
+                ui.add_space(5.0);
+                ui.horizontal(|ui| {
+                    // Takes effect with the next render
+                    ui.checkbox(&mut self.autosave, "Auto-save every");
+                    ui.add(egui::DragValue::new(&mut self.autosave_every).clamp_range(1..=10000));
+                    ui.label("samples");
+                });
             });
 
         egui::CentralPanel::default().show(ctx, |ui| {
+            let over_image = self
+                .image_rect
+                .zip(ui.input().pointer.hover_pos())
+                .is_some_and(|(rect, pos)| rect.contains(pos));
+            let mut drag_delta = Vec2::ZERO;
             egui::ScrollArea::both()
                 .id_source("main_img_scroll")
                 .max_width(f32::INFINITY)
                 .max_height(f32::INFINITY)
                 .auto_shrink([false, false])
+                // Dragging and scrolling the image move the camera, the scroll bars still
+                // move the image
+                .enable_scrolling(!over_image)
                 .show(ui, |ui| {
                     let zoomstateid = Id::new("main_img_zoom");
                     let mut zoomstate = ZoomState::load(ui.ctx(), zoomstateid).unwrap_or_default();
@@ -401,17 +782,32 @@ impl eframe::App for RaytracerApp {
                     //     zoomstate.zoom,
                     //     ui.input().zoom_delta()
                     // ));
-                    self.render_action
+                    let shown = self
+                        .render_action
                         .as_mut()
-                        .map(|ra| {
+                        .and_then(|ra| {
                             ra.take_immediate_image();
                             ra.immediate_image.as_ref()
                         })
-                        .flatten()
-                        .or_else(|| self.final_render.as_ref())
-                        .map(|i| i.show_scaled(ui, zoomstate.zoom as f32));
+                        .or(self.final_render.as_ref())
+                        // Previews are shown at the size of the full resolution
+                        .map(|i| {
+                            let scale = self.params.image_width as f32 / i.size()[0] as f32;
+                            i.show_scaled(ui, zoomstate.zoom as f32 * scale)
+                        });
                     zoomstate.store(ui.ctx(), zoomstateid);
+
+                    self.image_rect = shown.map(|response| response.rect.intersect(ui.clip_rect()));
+                    if let Some(rect) = self.image_rect {
+                        let response = ui.interact(rect, Id::new("main_img_drag"), Sense::drag());
+                        drag_delta = response.drag_delta();
+                    }
                 });
+
+            if self.navigate(ui.ctx(), drag_delta, over_image) {
+                self.last_interaction = Some(Instant::now());
+                self.start_render(ui.ctx(), false);
+            }
         });
     }
 }
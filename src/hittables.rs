@@ -1,16 +1,30 @@
+use std::fmt;
 use std::sync::Arc;
 
-use nalgebra::Matrix3;
+use nalgebra::{Matrix3, Matrix4};
+use rand::Rng;
 
+use crate::bvh::{Aabb, BoundingSphere};
 use crate::material::Material;
-use crate::util::{AsRgb, Color, Point3, Ray, Vec3};
+use crate::util::{consts, AsRgb, Color, Float, Onb, Point3, Ray, RenderRng, Vec3};
 
+/// Distance of the origin of scattered rays from the surface, relative to the coordinates
+const RAY_OFFSET: Float = 16.0 * Float::EPSILON;
+
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
     pub material: Arc<dyn Material>,
-    pub t: f64,
+    pub t: Float,
     pub front_face: bool,
+    /// Surface coordinates, in 0..1 except for unbounded objects like `Plane`.
+    /// Zero for objects without a parametrization.
+    pub u: Float,
+    pub v: Float,
+    /// Direction of increasing `u` on the surface, for anisotropic materials.
+    /// `None` for objects without a parametrization and where it is undefined (at poles).
+    pub tangent: Option<Vec3>,
 }
 
 impl HitRecord {
@@ -18,7 +32,7 @@ impl HitRecord {
         p: Vec3,
         outward_normal: &Vec3,
         material: &Arc<dyn Material>,
-        t: f64,
+        t: Float,
         ray: &Ray,
     ) -> HitRecord {
         let front_face = ray.direction().dot(outward_normal) < 0.;
@@ -33,42 +47,158 @@ impl HitRecord {
             material: material.clone(),
             t,
             front_face,
+            u: 0.0,
+            v: 0.0,
+            tangent: None,
         }
     }
+
+    pub fn with_uv(mut self, u: Float, v: Float) -> HitRecord {
+        self.u = u;
+        self.v = v;
+        self
+    }
+
+    pub fn with_tangent(mut self, tangent: Vec3) -> HitRecord {
+        self.tangent = Some(tangent.normalize()).filter(|t| t.iter().all(|c| c.is_finite()));
+        self
+    }
+
+    /// Ray leaving the surface at the hit point. The computed hit point is off the surface by
+    /// a few units in the last place of its coordinates, so the origin is moved further than
+    /// that, to the side `direction` points to. Without, the fixed `t_min` of `World::hit`
+    /// lets rays far from the origin hit their own surface again (shadow acne).
+    pub fn scattered(&self, direction: Vec3) -> Ray {
+        let offset = RAY_OFFSET * (self.p.amax() + 1.0);
+        let normal = if direction.dot(&self.normal) < 0.0 {
+            -self.normal
+        } else {
+            self.normal
+        };
+        Ray::new(self.p + offset * normal, direction)
+    }
 }
 
 pub trait Hittable: Sync + Send {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord>;
+
+    /// `None` for unbounded objects
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// `None` for unbounded objects. By default the sphere around the bounding box, override
+    /// it for shapes that fit into a tighter sphere.
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.bounding_box()
+            .map(|bbox| BoundingSphere::from_aabb(&bbox))
+    }
+
+    /// Line segments that `Integrator::Wireframe` draws, by default the edges of the
+    /// bounding box. None for unbounded objects.
+    fn wireframe_edges(&self) -> Vec<(Point3, Point3)> {
+        self.bounding_box()
+            .map(|bbox| bbox.edges())
+            .unwrap_or_default()
+    }
+
+    /// All points where `r` crosses the surface, at any t and sorted by t, for constructive
+    /// solid geometry. `front_face` is true where the ray enters the solid. `None` for objects
+    /// that don't enclose a volume.
+    fn crossings(&self, _r: &Ray) -> Option<Vec<HitRecord>> {
+        None
+    }
+
+    /// Probability density, with respect to solid angle, that `random(origin)` returns
+    /// `direction`. Zero for objects that can't be sampled as lights and for directions that
+    /// `random` practically never returns.
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> Float {
+        0.0
+    }
+
+    /// Direction from `origin` to a random point on the object, not normalized. `None` for
+    /// objects that can't be sampled as lights.
+    fn random(&self, _origin: &Point3, _rng: &mut RenderRng) -> Option<Vec3> {
+        None
+    }
+
+    /// Short type name, e.g. `Sphere`
+    fn name(&self) -> &'static str {
+        let full_name = std::any::type_name::<Self>();
+        full_name.rsplit("::").next().unwrap_or(full_name)
+    }
+
+    /// One line description for debugging, by default the name and the bounding box
+    fn describe(&self) -> String {
+        match self.bounding_box() {
+            Some(bbox) => format!(
+                "{} in ({:.2}, {:.2}, {:.2}) to ({:.2}, {:.2}, {:.2})",
+                self.name(),
+                bbox.minimum.x,
+                bbox.minimum.y,
+                bbox.minimum.z,
+                bbox.maximum.x,
+                bbox.maximum.y,
+                bbox.maximum.z
+            ),
+            None => format!("{} (unbounded)", self.name()),
+        }
+    }
+}
+
+impl fmt::Debug for dyn Hittable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.describe())
+    }
 }
 
 pub struct Sphere {
     pub center: Point3,
-    pub radius: f64,
+    /// A negative radius inverts the normals like `invert_normals`, prefer `Sphere::hollow`
+    pub radius: Float,
     pub material: Arc<dyn Material>,
+    /// Normals point to the center, e.g. for the air bubble inside a glass sphere
+    pub invert_normals: bool,
 }
 
 impl Sphere {
     pub fn new(
-        cx: f64,
-        cy: f64,
-        cz: f64,
-        r: f64,
+        cx: Float,
+        cy: Float,
+        cz: Float,
+        r: Float,
         material: &Arc<dyn Material>,
     ) -> Arc<dyn Hittable> {
         Arc::new(Sphere {
             center: Point3::new(cx, cy, cz),
             radius: r,
             material: material.clone(),
+            invert_normals: false,
+        })
+    }
+
+    /// Sphere with inward facing normals. Placed inside a dielectric sphere with the same
+    /// material it turns the sphere into a hollow shell.
+    pub fn hollow(
+        cx: Float,
+        cy: Float,
+        cz: Float,
+        r: Float,
+        material: &Arc<dyn Material>,
+    ) -> Arc<dyn Hittable> {
+        Arc::new(Sphere {
+            center: Point3::new(cx, cy, cz),
+            radius: r.abs(),
+            material: material.clone(),
+            invert_normals: true,
         })
     }
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
         let oc: Vec3 = r.origin() - self.center;
-        let a: f64 = r.direction().magnitude_squared();
-        let half_b: f64 = oc.dot(&r.direction());
-        let c: f64 = oc.magnitude_squared() - self.radius * self.radius;
+        let a: Float = r.direction().magnitude_squared();
+        let half_b: Float = oc.dot(&r.direction());
+        let c: Float = oc.magnitude_squared() - self.radius * self.radius;
         let discriminant = half_b * half_b - a * c;
         if discriminant < 0.0 {
             None
@@ -85,16 +215,74 @@ impl Hittable for Sphere {
             }
 
             let p = r.at(t);
-            let normal = (p - self.center) / self.radius;
-            Some(HitRecord::new(p, &normal, &self.material, t, &r))
+            let outward = (p - self.center) / self.radius.abs();
+            let normal = if self.invert_normals || self.radius < 0.0 {
+                -outward
+            } else {
+                outward
+            };
+            let (u, v) = sphere_uv(&outward);
+            // Along the latitude, towards increasing u
+            let tangent = Vec3::new(outward.z, 0.0, -outward.x);
+            Some(
+                HitRecord::new(p, &normal, &self.material, t, r)
+                    .with_uv(u, v)
+                    .with_tangent(tangent),
+            )
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3::repeat(self.radius.abs());
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        Some(BoundingSphere::new(self.center, self.radius.abs()))
+    }
+
+    fn crossings(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        Some(crossings_by_hit(self, r))
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "Sphere at ({:.2}, {:.2}, {:.2}) with radius {:.2}",
+            self.center.x, self.center.y, self.center.z, self.radius
+        )
+    }
+}
+
+/// Most rays cross a surface a few times, this only guards against endless loops
+const MAX_CROSSINGS: usize = 16;
+
+/// `Hittable::crossings` of a closed surface, found by calling `hit` again just behind each
+/// hit. Tangent rays may lose one of two crossings at the same t.
+pub(crate) fn crossings_by_hit(object: &dyn Hittable, r: &Ray) -> Vec<HitRecord> {
+    let mut crossings = Vec::new();
+    let mut t_min = Float::NEG_INFINITY;
+    while let Some(hit) = object.hit(r, t_min, Float::INFINITY) {
+        t_min = hit.t + 16.0 * Float::EPSILON * (hit.t.abs() + 1.0);
+        crossings.push(hit);
+        if crossings.len() == MAX_CROSSINGS {
+            break;
         }
     }
+    crossings
+}
+
+/// Texture coordinates of a point `p` on the unit sphere: u is the angle around the y axis
+/// starting at -x, v the angle from -y to +y
+fn sphere_uv(p: &Point3) -> (Float, Float) {
+    let theta = (-p.y).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z).atan2(p.x) + consts::PI;
+    (phi / (2.0 * consts::PI), theta / consts::PI)
 }
 
 pub struct Cylinder {
     pub start: Point3,
     pub dir: Vec3,
-    pub radius: f64,
+    pub radius: Float,
     pub material: Arc<dyn Material>,
 }
 
@@ -102,7 +290,7 @@ impl Cylinder {
     pub fn new(
         start: Point3,
         dir: Vec3,
-        radius: f64,
+        radius: Float,
         material: &Arc<dyn Material>,
     ) -> Arc<dyn Hittable> {
         Arc::new(Cylinder {
@@ -112,31 +300,845 @@ impl Cylinder {
             material: material.clone(),
         })
     }
+
+    /// Closed cylinder from `start` to `end` whose radius changes linearly from `r_start` to
+    /// `r_end`, so the body is a truncated cone. Flat caps close both ends.
+    pub fn tapered(
+        start: Point3,
+        end: Point3,
+        r_start: Float,
+        r_end: Float,
+        material: &Arc<dyn Material>,
+    ) -> Arc<dyn Hittable> {
+        Arc::new(TaperedCylinder {
+            start,
+            end,
+            r_start,
+            r_end,
+            material: material.clone(),
+        })
+    }
+}
+
+/// See `Cylinder::tapered`
+pub struct TaperedCylinder {
+    pub start: Point3,
+    pub end: Point3,
+    pub r_start: Float,
+    pub r_end: Float,
+    pub material: Arc<dyn Material>,
+}
+
+impl Hittable for TaperedCylinder {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        let axis = self.end - self.start;
+        let length = axis.magnitude();
+        let w = axis / length;
+        // Change of the radius per unit along the axis
+        let slope = (self.r_end - self.r_start) / length;
+
+        let mut nearest: Option<(Float, Vec3)> = None;
+        let mut consider = |t: Float, outward_normal: Vec3| {
+            if t_min < t && t < t_max && nearest.map(|(t_n, _)| t < t_n).unwrap_or(true) {
+                nearest = Some((t, outward_normal));
+            }
+        };
+
+        // Split into the position along the axis and the part perpendicular to it. On the body,
+        // the perpendicular distance equals the radius at that position:
+        // |q_o + t q_d|² = (r_start + slope (y_o + t y_d))²
+        let oc = r.origin() - self.start;
+        let (y_o, y_d) = (oc.dot(&w), r.direction().dot(&w));
+        let (q_o, q_d) = (oc - y_o * w, r.direction() - y_d * w);
+        let radius_o = self.r_start + slope * y_o;
+        for t in solve_quadratic(
+            q_d.magnitude_squared() - slope * slope * y_d * y_d,
+            q_o.dot(&q_d) - radius_o * slope * y_d,
+            q_o.magnitude_squared() - radius_o * radius_o,
+        )
+        .into_iter()
+        .flatten()
+        {
+            let y = y_o + t * y_d;
+            if (0.0..=length).contains(&y) {
+                let radial = (q_o + t * q_d).normalize();
+                // Tilted against the axis where the radius grows
+                consider(t, (radial - slope * w).normalize());
+            }
+        }
+
+        // Caps
+        if y_d != 0.0 {
+            for (y, radius, normal) in [(0.0, self.r_start, -w), (length, self.r_end, w)] {
+                let t = (y - y_o) / y_d;
+                if (q_o + t * q_d).magnitude_squared() <= radius * radius {
+                    consider(t, normal);
+                }
+            }
+        }
+
+        nearest.map(|(t, outward_normal)| {
+            HitRecord::new(r.at(t), &outward_normal, &self.material, t, r)
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3::repeat(self.r_start.max(self.r_end));
+        Some(Aabb::new(
+            self.start.inf(&self.end) - r,
+            self.start.sup(&self.end) + r,
+        ))
+    }
+
+    fn crossings(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        Some(crossings_by_hit(self, r))
+    }
 }
 
 impl Hittable for Cylinder {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let (t_ray, n, p_ray, p_centerline) = nearest_points(r.orig, r.dir, self.start, self.dir);
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        // A ray parallel to the axis never crosses the mantle
+        let (t_ray, n, p_ray, p_centerline) = nearest_points(r.orig, r.dir, self.start, self.dir)?;
         let d = (p_ray - p_centerline).magnitude();
         if d < self.radius && t_min < t_ray && t_ray < t_max {
             // println!("{} !!", d);
-            Some(HitRecord::new(p_ray, &n, &self.material, t_ray, &r))
+            Some(HitRecord::new(p_ray, &n, &self.material, t_ray, r))
         } else {
             // println!("{}", d);
             None
         }
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
 }
 
+/// Cylinder between `start` and `end` with half spheres as caps
+pub struct Capsule {
+    pub start: Point3,
+    pub end: Point3,
+    pub radius: Float,
+    pub material: Arc<dyn Material>,
+}
+
+impl Capsule {
+    pub fn new(
+        start: Point3,
+        end: Point3,
+        radius: Float,
+        material: &Arc<dyn Material>,
+    ) -> Arc<dyn Hittable> {
+        Arc::new(Capsule {
+            start,
+            end,
+            radius,
+            material: material.clone(),
+        })
+    }
+}
+
+impl Hittable for Capsule {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        let axis = self.end - self.start;
+        let axis_len_sq = axis.magnitude_squared();
+        // Position along the axis, 0 at start, axis_len_sq at end
+        let axial = |p: &Point3| (p - self.start).dot(&axis);
+
+        // Each surface (infinite body, start sphere, end sphere) only counts in its own zone
+        let mut nearest: Option<(Float, Vec3)> = None;
+        let mut consider = |t: Float, outward_normal: Vec3| {
+            if t_min < t && t < t_max && nearest.map(|(t_n, _)| t < t_n).unwrap_or(true) {
+                nearest = Some((t, outward_normal));
+            }
+        };
+
+        // Body: Remove the axial part of all vectors and solve like a sphere in the plane
+        if axis_len_sq > 0.0 {
+            let reject = |v: Vec3| v - axis * v.dot(&axis) / axis_len_sq;
+            let oc = reject(r.origin() - self.start);
+            let d = reject(r.direction());
+            for t in solve_quadratic(
+                d.magnitude_squared(),
+                oc.dot(&d),
+                oc.magnitude_squared() - self.radius * self.radius,
+            )
+            .into_iter()
+            .flatten()
+            {
+                let p = r.at(t);
+                let y = axial(&p);
+                if 0.0 <= y && y <= axis_len_sq {
+                    let on_axis = self.start + axis * y / axis_len_sq;
+                    consider(t, (p - on_axis) / self.radius);
+                }
+            }
+        }
+
+        // Caps, the start cap lies before the start point and the end cap after the end point
+        for (center, is_start) in [(self.start, true), (self.end, false)] {
+            let oc = r.origin() - center;
+            for t in solve_quadratic(
+                r.direction().magnitude_squared(),
+                oc.dot(&r.direction()),
+                oc.magnitude_squared() - self.radius * self.radius,
+            )
+            .into_iter()
+            .flatten()
+            {
+                let p = r.at(t);
+                let y = axial(&p);
+                if (is_start && y < 0.0) || (!is_start && y > axis_len_sq) {
+                    consider(t, (p - center) / self.radius);
+                }
+            }
+        }
+
+        nearest.map(|(t, outward_normal)| {
+            HitRecord::new(r.at(t), &outward_normal, &self.material, t, r)
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3::repeat(self.radius);
+        Some(Aabb::new(
+            self.start.inf(&self.end) - r,
+            self.start.sup(&self.end) + r,
+        ))
+    }
+
+    fn crossings(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        Some(crossings_by_hit(self, r))
+    }
+}
+
+/// Axis aligned box
+pub struct Cuboid {
+    pub minimum: Point3,
+    pub maximum: Point3,
+    pub material: Arc<dyn Material>,
+}
+
+impl Cuboid {
+    /// Box with opposite corners `a` and `b`
+    pub fn new(a: Point3, b: Point3, material: &Arc<dyn Material>) -> Arc<dyn Hittable> {
+        Arc::new(Cuboid {
+            minimum: a.inf(&b),
+            maximum: a.sup(&b),
+            material: material.clone(),
+        })
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        // Intersect the slabs between the two planes of each axis. The ray is inside the box
+        // from the last entry into a slab to the first exit.
+        let (origin, dir) = (r.origin(), r.direction());
+        let (mut t_enter, mut enter_axis) = (Float::NEG_INFINITY, 0);
+        let (mut t_exit, mut exit_axis) = (Float::INFINITY, 0);
+        for axis in 0..3 {
+            if dir[axis] == 0.0 {
+                if origin[axis] < self.minimum[axis] || origin[axis] > self.maximum[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (self.minimum[axis] - origin[axis]) / dir[axis];
+            let t1 = (self.maximum[axis] - origin[axis]) / dir[axis];
+            if t0.min(t1) > t_enter {
+                (t_enter, enter_axis) = (t0.min(t1), axis);
+            }
+            if t0.max(t1) < t_exit {
+                (t_exit, exit_axis) = (t0.max(t1), axis);
+            }
+        }
+        if t_enter > t_exit {
+            return None;
+        }
+
+        // The face normal points against the ray where it enters and along it where it leaves
+        let (t, axis, sign) = if t_min < t_enter && t_enter < t_max {
+            (t_enter, enter_axis, -dir[enter_axis].signum())
+        } else if t_min < t_exit && t_exit < t_max {
+            (t_exit, exit_axis, dir[exit_axis].signum())
+        } else {
+            return None;
+        };
+        let mut outward_normal = Vec3::zeros();
+        outward_normal[axis] = sign;
+        let p = r.at(t);
+        let size = self.maximum - self.minimum;
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let u = (p[a] - self.minimum[a]) / size[a];
+        let v = (p[b] - self.minimum[b]) / size[b];
+        Some(HitRecord::new(p, &outward_normal, &self.material, t, r).with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.minimum, self.maximum))
+    }
+
+    fn crossings(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        Some(crossings_by_hit(self, r))
+    }
+}
+
+pub struct Triangle {
+    pub v0: Point3,
+    pub v1: Point3,
+    pub v2: Point3,
+    pub material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    /// The outward normal follows the right hand rule for v0, v1, v2
+    pub fn new(
+        v0: Point3,
+        v1: Point3,
+        v2: Point3,
+        material: &Arc<dyn Material>,
+    ) -> Arc<dyn Hittable> {
+        Arc::new(Triangle {
+            v0,
+            v1,
+            v2,
+            material: material.clone(),
+        })
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        let (t, u, v) = intersect_triangle(r, &self.v0, &self.v1, &self.v2)?;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        let normal = (self.v1 - self.v0).cross(&(self.v2 - self.v0)).normalize();
+        Some(HitRecord::new(r.at(t), &normal, &self.material, t, r).with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(triangle_bbox(&self.v0, &self.v1, &self.v2))
+    }
+
+    fn wireframe_edges(&self) -> Vec<(Point3, Point3)> {
+        triangle_edges(&self.v0, &self.v1, &self.v2)
+    }
+}
+
+/// Parallelogram with the corner `q` and the edges `u` and `v`
+pub struct Quad {
+    pub q: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Arc<dyn Material>,
+    /// Unit normal, following the right hand rule for u, v
+    normal: Vec3,
+    /// Plane equation `normal · p = d`
+    d: Float,
+    /// `n / (n · n)` with the unnormalized normal `n = u × v`, projects onto u and v
+    w: Vec3,
+    area: Float,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, material: &Arc<dyn Material>) -> Arc<dyn Hittable> {
+        let n = u.cross(&v);
+        let normal = n.normalize();
+        Arc::new(Quad {
+            q,
+            u,
+            v,
+            material: material.clone(),
+            normal,
+            d: normal.dot(&q),
+            w: n / n.dot(&n),
+            area: n.magnitude(),
+        })
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        let denom = self.normal.dot(&r.direction());
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.d - self.normal.dot(&r.origin())) / denom;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        let p = r.at(t);
+        let planar = p - self.q;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some(
+            HitRecord::new(p, &self.normal, &self.material, t, r)
+                .with_uv(alpha, beta)
+                .with_tangent(self.u),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let (q, u, v) = (self.q, self.u, self.v);
+        Some(
+            triangle_bbox(&q, &(q + u), &(q + v)).surrounding(&triangle_bbox(
+                &(q + u),
+                &(q + v),
+                &(q + u + v),
+            )),
+        )
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> Float {
+        let Some(hit) = self.hit(&Ray::new(*origin, *direction), 0.001, Float::INFINITY) else {
+            return 0.0;
+        };
+        let distance_squared = hit.t * hit.t * direction.magnitude_squared();
+        let cosine = (direction.dot(&self.normal) / direction.magnitude()).abs();
+        // Grazing directions would have a practically infinite density
+        if cosine < 1e-6 {
+            return 0.0;
+        }
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut RenderRng) -> Option<Vec3> {
+        Some(self.q + rng.gen::<Float>() * self.u + rng.gen::<Float>() * self.v - origin)
+    }
+
+    fn wireframe_edges(&self) -> Vec<(Point3, Point3)> {
+        let corners = [
+            self.q,
+            self.q + self.u,
+            self.q + self.u + self.v,
+            self.q + self.v,
+        ];
+        (0..4).map(|i| (corners[i], corners[(i + 1) % 4])).collect()
+    }
+}
+
+/// Infinite plane. The texture coordinates are the distances from `point` along two
+/// directions in the plane, so they are unbounded and a tiling texture repeats forever.
+pub struct Plane {
+    pub point: Point3,
+    /// Unit normal
+    pub normal: Vec3,
+    pub material: Arc<dyn Material>,
+    /// Unit directions of increasing u and v
+    u_axis: Vec3,
+    v_axis: Vec3,
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, material: &Arc<dyn Material>) -> Arc<dyn Hittable> {
+        let normal = normal.normalize();
+        let (u_axis, v_axis) = perpendicular_basis(&normal);
+        Arc::new(Plane {
+            point,
+            normal,
+            material: material.clone(),
+            u_axis,
+            v_axis,
+        })
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        let denom = self.normal.dot(&r.direction());
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.point - r.origin()).dot(&self.normal) / denom;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        let p = r.at(t);
+        let planar = p - self.point;
+        Some(
+            HitRecord::new(p, &self.normal, &self.material, t, r)
+                .with_uv(planar.dot(&self.u_axis), planar.dot(&self.v_axis))
+                .with_tangent(self.u_axis),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+fn triangle_edges(v0: &Point3, v1: &Point3, v2: &Point3) -> Vec<(Point3, Point3)> {
+    vec![(*v0, *v1), (*v1, *v2), (*v2, *v0)]
+}
+
+/// Padded, a flat box would be missed by the slab test
+fn triangle_bbox(v0: &Point3, v1: &Point3, v2: &Point3) -> Aabb {
+    let pad = Vec3::repeat(1e-4);
+    Aabb::new(v0.inf(v1).inf(v2) - pad, v0.sup(v1).sup(v2) + pad)
+}
+
+/// Triangle with per vertex normals that are interpolated across the face, so a mesh of
+/// them shades like a smooth surface
+pub struct SmoothTriangle {
+    pub v0: Point3,
+    pub v1: Point3,
+    pub v2: Point3,
+    pub n0: Vec3,
+    pub n1: Vec3,
+    pub n2: Vec3,
+    pub material: Arc<dyn Material>,
+}
+
+impl SmoothTriangle {
+    /// `vertices` and their `normals`, the normals are normalized
+    pub fn new(
+        vertices: [Point3; 3],
+        normals: [Vec3; 3],
+        material: &Arc<dyn Material>,
+    ) -> Arc<dyn Hittable> {
+        let [v0, v1, v2] = vertices;
+        let [n0, n1, n2] = normals.map(|n| n.normalize());
+        Arc::new(SmoothTriangle {
+            v0,
+            v1,
+            v2,
+            n0,
+            n1,
+            n2,
+            material: material.clone(),
+        })
+    }
+}
+
+impl Hittable for SmoothTriangle {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        let (t, u, v) = intersect_triangle(r, &self.v0, &self.v1, &self.v2)?;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        // The geometric normal decides front and back, the shading normal is only turned
+        // to the same side
+        let geometric = (self.v1 - self.v0).cross(&(self.v2 - self.v0)).normalize();
+        let mut rec = HitRecord::new(r.at(t), &geometric, &self.material, t, r).with_uv(u, v);
+        let shading = ((1.0 - u - v) * self.n0 + u * self.n1 + v * self.n2).normalize();
+        rec.normal = if rec.front_face { shading } else { -shading };
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(triangle_bbox(&self.v0, &self.v1, &self.v2))
+    }
+
+    fn wireframe_edges(&self) -> Vec<(Point3, Point3)> {
+        triangle_edges(&self.v0, &self.v1, &self.v2)
+    }
+}
+
+/// Möller-Trumbore intersection. Returns t and the barycentric coordinates u (weight of v1)
+/// and v (weight of v2). Both sides of the triangle are hit.
+fn intersect_triangle(
+    r: &Ray,
+    v0: &Point3,
+    v1: &Point3,
+    v2: &Point3,
+) -> Option<(Float, Float, Float)> {
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let pvec = r.direction().cross(&edge2);
+    let det = edge1.dot(&pvec);
+    // Ray parallel to the plane of the triangle
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let tvec = r.origin() - v0;
+    let u = tvec.dot(&pvec) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let qvec = tvec.cross(&edge1);
+    let v = r.direction().dot(&qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    Some((edge2.dot(&qvec) * inv_det, u, v))
+}
+
+/// Ring around `axis` through `center`: all points at distance `minor_radius` from the circle
+/// with `major_radius` in the plane perpendicular to the axis
+pub struct Torus {
+    pub center: Point3,
+    /// Unit vector
+    pub axis: Vec3,
+    pub major_radius: Float,
+    pub minor_radius: Float,
+    pub material: Arc<dyn Material>,
+    /// Perpendicular to `axis` and each other, span the plane of the ring
+    u: Vec3,
+    v: Vec3,
+}
+
+impl Torus {
+    pub fn new(
+        center: Point3,
+        axis: Vec3,
+        major_radius: Float,
+        minor_radius: Float,
+        material: &Arc<dyn Material>,
+    ) -> Arc<dyn Hittable> {
+        let axis = axis.normalize();
+        let (u, v) = perpendicular_basis(&axis);
+        Arc::new(Torus {
+            center,
+            axis,
+            major_radius,
+            minor_radius,
+            material: material.clone(),
+            u,
+            v,
+        })
+    }
+
+    /// Coordinates with the center as origin and the axis as y
+    fn to_local(&self, p: &Vec3) -> Vec3 {
+        Vec3::new(p.dot(&self.u), p.dot(&self.axis), p.dot(&self.v))
+    }
+
+    fn from_local(&self, p: &Vec3) -> Vec3 {
+        p.x * self.u + p.y * self.axis + p.z * self.v
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        // In local coordinates the torus is (|p|² + R² - r²)² = 4R² (x² + z²).
+        // With p = o + s * d and |d| = 1 this is a quartic in s = t * |r.dir|.
+        let dir_len = r.direction().magnitude();
+        let o = self.to_local(&(r.origin() - self.center));
+        let d = self.to_local(&r.direction()) / dir_len;
+        let rr = self.major_radius * self.major_radius;
+        let e = o.magnitude_squared() + rr - self.minor_radius * self.minor_radius;
+        let f = o.dot(&d);
+        let coefficients = [
+            e * e - 4.0 * rr * (o.x * o.x + o.z * o.z),
+            4.0 * e * f - 8.0 * rr * (o.x * d.x + o.z * d.z),
+            4.0 * f * f + 2.0 * e - 4.0 * rr * (d.x * d.x + d.z * d.z),
+            4.0 * f,
+        ];
+
+        let t = solve_monic_quartic(coefficients)
+            .into_iter()
+            .map(|s| s / dir_len)
+            .filter(|t| t_min < *t && *t < t_max)
+            .min_by(|a, b| a.total_cmp(b))?;
+        let s = t * dir_len;
+
+        // Gradient of the implicit function
+        let p = o + s * d;
+        let k = p.magnitude_squared() + rr - self.minor_radius * self.minor_radius;
+        let gradient = Vec3::new(
+            4.0 * k * p.x - 8.0 * rr * p.x,
+            4.0 * k * p.y,
+            4.0 * k * p.z - 8.0 * rr * p.z,
+        );
+        let outward_normal = self.from_local(&gradient).normalize();
+        Some(HitRecord::new(
+            r.at(t),
+            &outward_normal,
+            &self.material,
+            t,
+            r,
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let extent = Vec3::repeat(self.major_radius + self.minor_radius);
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        Some(BoundingSphere::new(
+            self.center,
+            self.major_radius + self.minor_radius,
+        ))
+    }
+
+    fn crossings(&self, r: &Ray) -> Option<Vec<HitRecord>> {
+        Some(crossings_by_hit(self, r))
+    }
+}
+
+/// Dish around `axis` with its vertex at `vertex`: in coordinates with the vertex as origin
+/// and the axis as y, the points with `x² + z² = 4 f y` and `0 <= y <= height`.
+/// The focus lies at `vertex + focal_length * axis`, so a `Metal` dish collects rays
+/// parallel to the axis there.
+pub struct Paraboloid {
+    pub vertex: Point3,
+    /// Unit vector
+    pub axis: Vec3,
+    pub focal_length: Float,
+    pub height: Float,
+    pub material: Arc<dyn Material>,
+    /// Perpendicular to `axis` and each other
+    u: Vec3,
+    v: Vec3,
+}
+
+impl Paraboloid {
+    pub fn new(
+        vertex: Point3,
+        axis: Vec3,
+        focal_length: Float,
+        height: Float,
+        material: &Arc<dyn Material>,
+    ) -> Arc<dyn Hittable> {
+        let axis = axis.normalize();
+        let (u, v) = perpendicular_basis(&axis);
+        Arc::new(Paraboloid {
+            vertex,
+            axis,
+            focal_length,
+            height,
+            material: material.clone(),
+            u,
+            v,
+        })
+    }
+
+    fn to_local(&self, p: &Vec3) -> Vec3 {
+        Vec3::new(p.dot(&self.u), p.dot(&self.axis), p.dot(&self.v))
+    }
+
+    fn from_local(&self, p: &Vec3) -> Vec3 {
+        p.x * self.u + p.y * self.axis + p.z * self.v
+    }
+}
+
+impl Hittable for Paraboloid {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        let o = self.to_local(&(r.origin() - self.vertex));
+        let d = self.to_local(&r.direction());
+        let f4 = 4.0 * self.focal_length;
+        let a = d.x * d.x + d.z * d.z;
+        let half_b = o.x * d.x + o.z * d.z - 0.5 * f4 * d.y;
+        let c = o.x * o.x + o.z * o.z - f4 * o.y;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        // The usual formula cancels out for rays (almost) parallel to the axis, where `a` is
+        // (close to) zero and the equation is (almost) linear
+        let q = -(half_b + discriminant.sqrt().copysign(half_b));
+        if q == 0.0 {
+            return None;
+        }
+        let t = [q / a, c / q]
+            .into_iter()
+            .filter(|t| t_min < *t && *t < t_max)
+            .filter(|t| (0.0..=self.height).contains(&(o.y + t * d.y)))
+            .min_by(|a, b| a.total_cmp(b))?;
+
+        // Gradient of x² + z² - 4 f y, pointing away from the focus
+        let p = o + t * d;
+        let gradient = Vec3::new(2.0 * p.x, -f4, 2.0 * p.z);
+        let outward_normal = self.from_local(&gradient).normalize();
+        Some(HitRecord::new(
+            r.at(t),
+            &outward_normal,
+            &self.material,
+            t,
+            r,
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let rim = (4.0 * self.focal_length * self.height).sqrt();
+        let corners = [-rim, rim].into_iter().flat_map(|x| {
+            [0.0, self.height]
+                .into_iter()
+                .flat_map(move |y| [-rim, rim].into_iter().map(move |z| Vec3::new(x, y, z)))
+        });
+        let (min, max) = corners
+            .map(|corner| self.vertex + self.from_local(&corner))
+            .fold(
+                (
+                    Vec3::repeat(Float::INFINITY),
+                    Vec3::repeat(Float::NEG_INFINITY),
+                ),
+                |(min, max), p| (min.inf(&p), max.sup(&p)),
+            );
+        Some(Aabb::new(min, max))
+    }
+}
+
+/// Two unit vectors perpendicular to the unit vector `axis` and each other, u × v = axis
+pub(crate) fn perpendicular_basis(axis: &Vec3) -> (Vec3, Vec3) {
+    let onb = Onb::from_w(axis);
+    (onb.u, onb.v)
+}
+
+/// Real roots of `x⁴ + c[3] x³ + c[2] x² + c[1] x + c[0]`, as eigenvalues of the companion
+/// matrix, refined with Newton's method
+fn solve_monic_quartic(c: [Float; 4]) -> Vec<Float> {
+    #[rustfmt::skip]
+    let companion = Matrix4::new(
+        0.0, 0.0, 0.0, -c[0],
+        1.0, 0.0, 0.0, -c[1],
+        0.0, 1.0, 0.0, -c[2],
+        0.0, 0.0, 1.0, -c[3],
+    );
+    let poly = |x: Float| (((x + c[3]) * x + c[2]) * x + c[1]) * x + c[0];
+    let derivative = |x: Float| ((4.0 * x + 3.0 * c[3]) * x + 2.0 * c[2]) * x + c[1];
+    companion
+        .complex_eigenvalues()
+        .iter()
+        // Double roots (grazing rays) come out with a small imaginary part
+        .filter(|root| root.im.abs() <= 1e-3 * (1.0 + root.re.abs()))
+        .map(|root| {
+            let mut x = root.re;
+            for _ in 0..3 {
+                let slope = derivative(x);
+                if slope == 0.0 {
+                    break;
+                }
+                x -= poly(x) / slope;
+            }
+            x
+        })
+        .collect()
+}
+
+/// Roots of `a * t² + 2 * half_b * t + c`, smaller one first
+fn solve_quadratic(a: Float, half_b: Float, c: Float) -> Option<[Float; 2]> {
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 || a == 0.0 {
+        return None;
+    }
+    let sqrtd = discriminant.sqrt();
+    Some([(-half_b - sqrtd) / a, (-half_b + sqrtd) / a])
+}
+
+/// Nearest points of the lines `K + t * l` and `A + s * b`.
+/// Returns `None` if the lines are parallel.
 #[allow(non_snake_case)]
-pub fn nearest_points(K: Point3, l: Vec3, A: Point3, b: Vec3) -> (f64, Vec3, Point3, Point3) {
+pub fn nearest_points(
+    K: Point3,
+    l: Vec3,
+    A: Point3,
+    b: Vec3,
+) -> Option<(Float, Vec3, Point3, Point3)> {
     let n = l.cross(&b);
     let m = Matrix3::from_columns(&[l, n, b]);
-    let t = m.try_inverse().unwrap() * (A - K);
+    let t = m.try_inverse()? * (A - K);
     let p1 = K + t.x * l;
     let p2 = p1 + t.y * n;
 
-    (t.x, n, p1, p2)
+    Some((t.x, n, p1, p2))
 }
 
 #[cfg(test)]
@@ -144,12 +1146,13 @@ mod tests {
     use super::*;
     use approx::assert_relative_eq;
     use approx::assert_relative_ne;
+    use rand::SeedableRng;
 
     #[rustfmt::skip]
     #[test]
     fn test_nearest_points() {
         let (t, _n, p1, p2) = nearest_points(Point3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.0), 
-                                            Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+                                            Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)).unwrap();
         assert_eq!(p1, Point3::new(0.0, 0.0, 1.0));
         assert_eq!(p2, Point3::new(0.0, 1.0, 1.0));
         assert_eq!(t, 0.0);
@@ -158,19 +1161,491 @@ mod tests {
         let l = Vec3::new(1.0, 0.2, 0.0);
         let b = Vec3::new(0.2, 0.0, 1.0);
         let (t, _n, p1, p2) = nearest_points(Point3::new(0.0, 0.0, 1.0), l, 
-                                            Point3::new(0.0, 1.0, 0.0), b);
+                                            Point3::new(0.0, 1.0, 0.0), b).unwrap();
         // Values taken from test, but they are logically consistent
-        assert_relative_eq!(p1, Point3::new(0.3917050, 0.0783410, 1.0), epsilon = 1e-5);
+        assert_relative_eq!(p1, Point3::new(0.391705, 0.0783410, 1.0), epsilon = 1e-5);
         assert_relative_eq!(p2, Point3::new(0.2073732, 1.0, 1.0368663), epsilon = 1e-5);
-        assert_relative_eq!(t, 0.3917050, epsilon = 1e-5);
+        assert_relative_eq!(t, 0.391705, epsilon = 1e-5);
 
         // Test: Independence of line origin and length of direction vector gives same results
         let (t_2, _n_2, p1_2, p2_2) = nearest_points(Point3::new(0.0, 0.0, 1.0) + 200.0 * l, 10.0 * l, 
-                                                    Point3::new(0.0, 1.0, 0.0) - 14.2*b, 0.1 * b);
-        assert_relative_ne!(t, t_2, epsilon = 1e-8);
-        assert_relative_eq!(p1, p1_2, epsilon = 1e-8);
-        assert_relative_eq!(p2, p2_2, epsilon = 1e-8);
+                                                    Point3::new(0.0, 1.0, 0.0) - 14.2*b, 0.1 * b).unwrap();
+        // The offset of 200 * l costs f32 most of its digits
+        let eps = if cfg!(feature = "precision-f32") { 1e-3 } else { 1e-8 };
+        assert_relative_ne!(t, t_2, epsilon = eps);
+        assert_relative_eq!(p1, p1_2, epsilon = eps);
+        assert_relative_eq!(p2, p2_2, epsilon = eps);
+
+
+    }
+
+    #[test]
+    fn test_sphere_tangent_follows_u() {
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let sphere = Sphere::new(0.0, 0.0, 0.0, 2.0, &material);
+        let hit_towards = |target: Point3| {
+            let origin = 5.0 * target.normalize();
+            sphere
+                .hit(&Ray::new(origin, -origin), 0.001, 1000.0)
+                .unwrap()
+        };
+        let hit = hit_towards(Point3::new(0.3, 0.4, 0.8));
+        let tangent = hit.tangent.unwrap();
+        assert_relative_eq!(tangent.magnitude(), 1.0, epsilon = 1e-6);
+        assert_relative_eq!(tangent.dot(&hit.normal), 0.0, epsilon = 1e-6);
+        // A step along the tangent increases u and keeps v
+        let step = hit_towards(hit.p + 0.01 * tangent);
+        assert!(step.u > hit.u);
+        assert_relative_eq!(step.v, hit.v, epsilon = 1e-4);
+        // Undefined at the poles
+        assert!(hit_towards(Point3::new(0.0, 1.0, 0.0)).tangent.is_none());
+    }
+
+    #[test]
+    fn test_sphere_uv() {
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let sphere = Sphere::new(0.0, 0.0, 0.0, 2.0, &material);
+        // From +z the hit is at the front, a quarter turn away from -x
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = sphere.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.u, 0.25, epsilon = 1e-6);
+        assert_relative_eq!(hit.v, 0.5, epsilon = 1e-6);
+        // From above
+        let ray = Ray::new(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit = sphere.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.v, 1.0, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_hollow_sphere_normals_point_inward() {
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        for sphere in [
+            Sphere::hollow(0.0, 0.0, 0.0, 2.0, &material),
+            Sphere::new(0.0, 0.0, 0.0, -2.0, &material),
+        ] {
+            // Coming from outside, the ray runs along the inward normal: it hits a back face
+            let hit = sphere.hit(&ray, 0.001, 1000.0).unwrap();
+            assert_relative_eq!(hit.p, Point3::new(0.0, 0.0, 2.0), epsilon = 1e-6);
+            assert!(!hit.front_face);
+            assert_relative_eq!(hit.normal, Vec3::new(0.0, 0.0, 1.0), epsilon = 1e-6);
+
+            // From the center the inside is the front
+            let inner = Ray::new(Point3::zeros(), Vec3::new(1.0, 0.0, 0.0));
+            let hit = sphere.hit(&inner, 0.001, 1000.0).unwrap();
+            assert!(hit.front_face);
+            assert_relative_eq!(hit.normal, Vec3::new(-1.0, 0.0, 0.0), epsilon = 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_triangle() {
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let triangle = Triangle::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 0.0),
+            Point3::new(0.0, 2.0, 0.0),
+            &material,
+        );
+        let ray = Ray::new(Point3::new(0.5, 0.25, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = triangle.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.t, 3.0, epsilon = 1e-6);
+        assert_relative_eq!(hit.normal, Vec3::new(0.0, 0.0, 1.0), epsilon = 1e-6);
+        assert!(hit.front_face);
+        assert_relative_eq!(hit.u, 0.25, epsilon = 1e-6);
+        assert_relative_eq!(hit.v, 0.125, epsilon = 1e-6);
+
+        // Outside of the hypotenuse
+        let ray = Ray::new(Point3::new(1.5, 1.0, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(triangle.hit(&ray, 0.001, 1000.0).is_none());
+
+        // The flat bounding box still is hit by the slab test
+        let ray = Ray::new(Point3::new(0.5, 0.25, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(triangle.bounding_box().unwrap().hit(&ray, 0.001, 1000.0));
+    }
+
+    #[test]
+    fn test_smooth_triangle_interpolates_normals() {
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let n0 = Vec3::new(-1.0, 0.0, 1.0).normalize();
+        let n1 = Vec3::new(1.0, 0.0, 1.0).normalize();
+        let triangle = SmoothTriangle::new(
+            [
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(2.0, 0.0, 0.0),
+                Point3::new(0.0, 2.0, 0.0),
+            ],
+            [n0, n1, Vec3::new(0.0, 1.0, 1.0)],
+            &material,
+        );
+
+        // Midpoint of the edge v0 v1, the normal is halfway between n0 and n1
+        let ray = Ray::new(Point3::new(1.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = triangle.hit(&ray, 0.001, 1000.0).unwrap();
+        assert!(hit.front_face);
+        assert_relative_eq!(hit.normal, (n0 + n1).normalize(), epsilon = 1e-6);
+
+        // A quarter along the edge
+        let ray = Ray::new(Point3::new(0.5, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = triangle.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(
+            hit.normal,
+            (0.75 * n0 + 0.25 * n1).normalize(),
+            epsilon = 1e-6
+        );
+
+        // From behind, the normal faces the ray
+        let ray = Ray::new(Point3::new(1.0, 0.0, -3.0), Vec3::new(0.0, 0.0, 1.0));
+        let hit = triangle.hit(&ray, 0.001, 1000.0).unwrap();
+        assert!(!hit.front_face);
+        assert_relative_eq!(hit.normal, -(n0 + n1).normalize(), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_torus() {
+        // The quartic loses more precision than the other intersections
+        let eps = if cfg!(feature = "precision-f32") {
+            1e-3
+        } else {
+            1e-6
+        };
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let torus = Torus::new(
+            Point3::new(1.0, 2.0, 3.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            2.0,
+            0.5,
+            &material,
+        );
+
+        // Down the symmetry axis through the hole
+        let ray = Ray::new(Point3::new(1.0, 10.0, 3.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(torus.hit(&ray, 0.001, 1000.0).is_none());
+
+        // From the side through the center: outer equator at distance R + r
+        let ray = Ray::new(Point3::new(10.0, 2.0, 3.0), Vec3::new(-2.0, 0.0, 0.0));
+        let hit = torus.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.p, Point3::new(3.5, 2.0, 3.0), epsilon = eps);
+        assert_relative_eq!(hit.t, 3.25, epsilon = eps);
+        assert_relative_eq!(hit.normal, Vec3::new(1.0, 0.0, 0.0), epsilon = eps);
+        assert!(hit.front_face);
+
+        // The nearest hit from inside the tube is its inner wall
+        let ray = Ray::new(Point3::new(3.0, 2.0, 3.0), Vec3::new(-1.0, 0.0, 0.0));
+        let hit = torus.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.p, Point3::new(2.5, 2.0, 3.0), epsilon = eps);
+        assert!(!hit.front_face);
+
+        // From above onto the top of the tube
+        let ray = Ray::new(Point3::new(-1.0, 5.0, 3.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit = torus.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.p, Point3::new(-1.0, 2.5, 3.0), epsilon = eps);
+        assert_relative_eq!(hit.normal, Vec3::new(0.0, 1.0, 0.0), epsilon = eps);
+
+        // Tilted axis: Down the axis misses, across the ring plane hits
+        let axis = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let tilted = Torus::new(Point3::zeros(), axis, 2.0, 0.5, &material);
+        assert!(tilted
+            .hit(&Ray::new(5.0 * axis, -axis), 0.001, 1000.0)
+            .is_none());
+        let across = Vec3::new(0.0, 0.0, 1.0);
+        let hit = tilted
+            .hit(&Ray::new(10.0 * across, -across), 0.001, 1000.0)
+            .unwrap();
+        assert_relative_eq!(hit.p, 2.5 * across, epsilon = eps);
+    }
+
+    #[test]
+    fn test_paraboloid() {
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let vertex = Point3::new(1.0, -1.0, 2.0);
+        let dish = Paraboloid::new(vertex, Vec3::new(0.0, 2.0, 0.0), 0.5, 2.0, &material);
+
+        // The axial ray hits the vertex, from the concave side
+        let ray = Ray::new(vertex + Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -2.0, 0.0));
+        let hit = dish.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.p, vertex, epsilon = 1e-6);
+        assert_relative_eq!(hit.t, 2.5, epsilon = 1e-6);
+        assert_relative_eq!(hit.normal, Vec3::new(0.0, 1.0, 0.0), epsilon = 1e-6);
+        assert!(!hit.front_face);
+
+        // Rays parallel to the axis are reflected through the focus
+        let focus = vertex + Vec3::new(0.0, 0.5, 0.0);
+        for offset in [Vec3::new(0.5, 0.0, 0.0), Vec3::new(-0.3, 0.0, 1.1)] {
+            let ray = Ray::new(
+                vertex + offset + Vec3::new(0.0, 5.0, 0.0),
+                Vec3::new(0.0, -1.0, 0.0),
+            );
+            let hit = dish.hit(&ray, 0.001, 1000.0).unwrap();
+            let y = offset.magnitude_squared() / 2.0;
+            assert_relative_eq!(
+                hit.p,
+                vertex + offset + Vec3::new(0.0, y, 0.0),
+                epsilon = 1e-5
+            );
+            let reflected = crate::util::reflect(&ray.direction(), &hit.normal);
+            let to_focus = focus - hit.p;
+            assert_relative_eq!(reflected.cross(&to_focus).magnitude(), 0.0, epsilon = 1e-5);
+            assert!(reflected.dot(&to_focus) > 0.0);
+        }
+
+        // Beyond the rim at height 2 (radius 2) the dish is open
+        let ray = Ray::new(vertex + Vec3::new(2.5, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(dish.hit(&ray, 0.001, 1000.0).is_none());
+        // From the side, only the outside of the far wall below the rim
+        let ray = Ray::new(vertex + Vec3::new(-5.0, 0.5, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let hit = dish.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.p, vertex + Vec3::new(-1.0, 0.5, 0.0), epsilon = 1e-5);
+        assert!(hit.front_face);
+
+        let bbox = dish.bounding_box().unwrap();
+        assert_relative_eq!(
+            bbox.minimum,
+            vertex + Vec3::new(-2.0, 0.0, -2.0),
+            epsilon = 1e-5
+        );
+        assert_relative_eq!(
+            bbox.maximum,
+            vertex + Vec3::new(2.0, 2.0, 2.0),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn test_plane_checker() {
+        use crate::texture::CheckerTexture;
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let plane = Plane::new(Point3::zeros(), Vec3::new(0.0, 1.0, 0.0), &material);
+        let checker = CheckerTexture::from_colors(1.0, Color::repeat(1.0), Color::zeros());
+        let floor_at = |x: Float, z: Float| {
+            let ray = Ray::new(Point3::new(x, 3.0, z), Vec3::new(0.0, -1.0, 0.0));
+            plane.hit(&ray, 0.001, 1000.0).unwrap()
+        };
+
+        let hit = floor_at(0.0, 0.0);
+        assert_relative_eq!(hit.t, 3.0, epsilon = 1e-6);
+        assert_eq!((hit.u, hit.v), (0.0, 0.0));
+        // The coordinates are distances in the plane, and don't wrap
+        let far = floor_at(30.0, -40.0);
+        assert_relative_eq!(far.u.hypot(far.v), 50.0, epsilon = 1e-4);
+        assert!(plane
+            .hit(
+                &Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0)),
+                0.001,
+                1000.0
+            )
+            .is_none());
+
+        // Adjacent tiles alternate, also across the origin, and far away
+        let color_at = |x, z| {
+            let hit = floor_at(x, z);
+            checker.value(hit.u, hit.v, &hit.p)
+        };
+        for (x, z) in [(0.5, 0.5), (-0.5, 0.5), (-0.5, -0.5), (1000.5, -2000.5)] {
+            assert_ne!(color_at(x, z), color_at(x + 1.0, z));
+            assert_ne!(color_at(x, z), color_at(x, z + 1.0));
+            assert_eq!(color_at(x, z), color_at(x + 1.0, z + 1.0));
+        }
+    }
+
+    #[test]
+    fn test_quad() {
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        // Ceiling light at y = 2, facing down
+        let quad = Quad::new(
+            Point3::new(-0.5, 2.0, -1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.5),
+            &material,
+        );
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let hit = quad.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.t, 2.0, epsilon = 1e-6);
+        assert!(hit.front_face);
+        assert_relative_eq!(hit.u, 0.5, epsilon = 1e-6);
+        assert_relative_eq!(hit.v, 1.0 / 1.5, epsilon = 1e-6);
+        let beside = Ray::new(Point3::new(0.7, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(quad.hit(&beside, 0.001, 1000.0).is_none());
+
+        // Sampled directions end on the quad
+        let origin = Point3::new(0.3, 0.5, 0.2);
+        let mut rng = RenderRng::seed_from_u64(2);
+        for _ in 0..1000 {
+            let direction = quad.random(&origin, &mut rng).unwrap();
+            let hit = quad
+                .hit(&Ray::new(origin, direction), 0.001, 1000.0)
+                .unwrap();
+            assert_relative_eq!(hit.t, 1.0, epsilon = 1e-5);
+            assert!(quad.pdf_value(&origin, &direction) > 0.0);
+        }
+        assert_eq!(quad.pdf_value(&origin, &Vec3::new(0.0, -1.0, 0.0)), 0.0);
+        // Hits the quad at a grazing angle
+        let grazing_origin = Point3::new(0.0, 2.0 - 2e-7, -2.0);
+        let grazing = Vec3::new(0.0, 1e-7, 1.0);
+        assert!(quad
+            .hit(&Ray::new(grazing_origin, grazing), 0.001, 1000.0)
+            .is_some());
+        assert_eq!(quad.pdf_value(&grazing_origin, &grazing), 0.0);
+        // Only objects with light sampling return directions
+        let sphere = Sphere::new(0.0, 0.0, 0.0, 1.0, &material);
+        assert!(sphere.random(&origin, &mut rng).is_none());
+        assert_eq!(sphere.pdf_value(&origin, &Vec3::new(1.0, 0.0, 0.0)), 0.0);
+
+        // The pdf integrates to 1 over the sphere of directions
+        let n = 200_000;
+        let integral = (0..n)
+            .map(|_| quad.pdf_value(&origin, &crate::util::random_unit_vector(&mut rng)))
+            .sum::<Float>()
+            * 4.0
+            * consts::PI
+            / n as Float;
+        assert_relative_eq!(integral, 1.0, epsilon = 0.03);
+    }
+
+    #[test]
+    fn test_capsule() {
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let capsule = Capsule::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 2.0, 0.0),
+            0.5,
+            &material,
+        );
+
+        // Grazing the rounded cap above the end point
+        let ray = Ray::new(Point3::new(-5.0, 2.3, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let hit = capsule.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.p, Point3::new(-0.4, 2.3, 0.0), epsilon = 1e-6);
+        assert_relative_eq!(hit.normal, Vec3::new(-0.8, 0.6, 0.0), epsilon = 1e-6);
+        assert!(hit.front_face);
+
+        // Passing above the cap, where the finite cylinder would still be hit
+        let ray = Ray::new(Point3::new(-5.0, 2.45, 0.4), Vec3::new(1.0, 0.0, 0.0));
+        assert!(capsule.hit(&ray, 0.001, 1000.0).is_none());
+
+        // Body
+        let ray = Ray::new(Point3::new(-5.0, 1.0, 0.0), Vec3::new(2.0, 0.0, 0.0));
+        let hit = capsule.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.p, Point3::new(-0.5, 1.0, 0.0), epsilon = 1e-6);
+        assert_relative_eq!(hit.normal, Vec3::new(-1.0, 0.0, 0.0), epsilon = 1e-6);
+
+        // From the inside along the axis, leaving through the start cap
+        let ray = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit = capsule.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.p, Point3::new(0.0, -0.5, 0.0), epsilon = 1e-6);
+        assert!(!hit.front_face);
+    }
+
+    #[test]
+    fn test_nearest_points_parallel() {
+        let dir = Vec3::new(0.0, 1.0, 0.0);
+        assert!(nearest_points(
+            Point3::new(0.0, 0.0, 0.0),
+            dir,
+            Point3::new(1.0, 0.0, 0.0),
+            2.0 * dir
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_tapered_cylinder() {
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        // Narrow at the bottom, twice as wide at the top, like a goblet
+        let frustum = Cylinder::tapered(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 2.0, 0.0),
+            0.5,
+            1.0,
+            &material,
+        );
+        let hit_from = |origin: Point3, direction: Vec3| {
+            frustum.hit(&Ray::new(origin, direction), 0.001, 1000.0)
+        };
+
+        // Down the axis onto the center of the wide end
+        let hit = hit_from(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+        assert_relative_eq!(hit.t, 3.0, epsilon = 1e-6);
+        assert_relative_eq!(hit.normal, Vec3::new(0.0, 1.0, 0.0), epsilon = 1e-6);
+        // Close to the rim of the wide end, but beyond the narrow one
+        let hit = hit_from(Point3::new(0.9, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+        assert_relative_eq!(hit.p.y, 2.0, epsilon = 1e-6);
+        assert!(
+            hit_from(Point3::new(0.9, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0))
+                .unwrap()
+                .p
+                .y
+                > 0.0
+        );
+
+        // From the side, the radius at the height of the ray is hit, with a normal tilted
+        // downwards by the slope of 1/4
+        for (y, radius) in [(0.0, 0.5), (1.0, 0.75), (1.9, 0.975)] {
+            let hit = hit_from(Point3::new(5.0, y, 0.0), Vec3::new(-1.0, 0.0, 0.0)).unwrap();
+            assert_relative_eq!(hit.p.x, radius, epsilon = 1e-6);
+            if y > 0.0 {
+                let expected = Vec3::new(1.0, -0.25, 0.0).normalize();
+                assert_relative_eq!(hit.normal, expected, epsilon = 1e-6);
+            }
+        }
+        assert!(hit_from(Point3::new(5.0, 2.1, 0.0), Vec3::new(-1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn test_cylinder_ray_parallel_to_axis() {
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let cylinder = Cylinder::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            0.5,
+            &material,
+        );
+
+        // Inside and outside of the mantle
+        let ray = Ray::new(Point3::new(0.1, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(cylinder.hit(&ray, 0.001, 1000.0).is_none());
+        let ray = Ray::new(Point3::new(2.0, -5.0, 0.0), Vec3::new(0.0, -3.0, 0.0));
+        assert!(cylinder.hit(&ray, 0.001, 1000.0).is_none());
+    }
+
+    #[test]
+    fn test_cuboid() {
+        let material = crate::material::Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let cuboid = Cuboid::new(
+            Point3::new(1.0, 2.0, 4.0),
+            Point3::new(-1.0, 0.0, 0.0),
+            &material,
+        );
+        let ray = Ray::new(Point3::new(0.5, 1.0, -2.0), Vec3::new(0.0, 0.0, 1.0));
+        let hit = cuboid.hit(&ray, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.t, 2.0);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, -1.0));
+        assert!(hit.front_face);
+        // On the z face, u runs along x and v along y
+        assert_relative_eq!(hit.u, 0.75);
+        assert_relative_eq!(hit.v, 0.5);
 
+        // From inside through the far face
+        let hit = cuboid.hit(&ray, 3.0, 1000.0).unwrap();
+        assert_relative_eq!(hit.t, 6.0);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, -1.0));
+        assert!(!hit.front_face);
+        assert_eq!(
+            cuboid
+                .crossings(&ray)
+                .unwrap()
+                .iter()
+                .map(|hit| (hit.t, hit.front_face))
+                .collect::<Vec<_>>(),
+            [(2.0, true), (6.0, false)]
+        );
 
+        // Parallel to a face, outside of the box
+        let beside = Ray::new(Point3::new(1.5, 1.0, -2.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(cuboid.hit(&beside, 0.001, 1000.0).is_none());
+        let diagonal = Ray::new(Point3::new(-3.0, 3.0, 2.0), Vec3::new(1.0, -1.0, 0.0));
+        let hit = cuboid.hit(&diagonal, 0.001, 1000.0).unwrap();
+        assert_relative_eq!(hit.t, 2.0);
+        assert_eq!(hit.normal, Vec3::new(-1.0, 0.0, 0.0));
     }
 }
@@ -0,0 +1,46 @@
+use crate::util::{Color, Float, Point3, RenderRng, Vec3};
+
+/// Light that is sampled explicitly at diffuse hits (next event estimation), in addition
+/// to being found by scattered rays
+pub trait Light: Send + Sync {
+    /// Picks a point on the light as seen from `p`. `None` if `p` receives no light.
+    fn sample(&self, p: &Point3, rng: &mut RenderRng) -> Option<LightSample>;
+}
+
+pub struct LightSample {
+    /// Unit vector from the shaded point towards the light
+    pub direction: Vec3,
+    /// Distance to the light, the shadow ray is tested up to there
+    pub distance: Float,
+    /// Incoming light on a surface facing the light, already divided by the sampling pdf.
+    /// Scaled by the cosine at the receiving surface and its BRDF.
+    pub irradiance: Color,
+}
+
+/// Directional light from an infinitely distant sun, without geometry. Scattered rays never
+/// hit it, so it only contributes through `sample` and casts hard shadows.
+pub struct SunLight {
+    /// Direction the light travels, e.g. (0, -1, 0) for the sun in the zenith
+    pub direction: Vec3,
+    /// Irradiance on a surface perpendicular to `direction`
+    pub irradiance: Color,
+}
+
+impl SunLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> Self {
+        SunLight {
+            direction: direction.normalize(),
+            irradiance,
+        }
+    }
+}
+
+impl Light for SunLight {
+    fn sample(&self, _p: &Point3, _rng: &mut RenderRng) -> Option<LightSample> {
+        Some(LightSample {
+            direction: -self.direction,
+            distance: Float::INFINITY,
+            irradiance: self.irradiance,
+        })
+    }
+}
@@ -11,48 +11,242 @@
 //!
 
 #![allow(dead_code, unused_imports)]
+#![allow(clippy::new_ret_no_self, clippy::wrong_self_convention)]
 
+mod bvh;
 mod camera;
+mod csg;
+mod diff;
+mod environment;
 mod gui;
 mod hittables;
+mod light;
 mod material;
+mod mesh;
 mod playground;
+mod sphere_list;
+mod texture;
 mod util;
 mod world;
 
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering::Relaxed;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
+use crate::bvh::{BvhOptions, BvhSplit};
 use crate::camera::Camera;
-use crate::hittables::{Hittable, Sphere};
-use crate::util::{random_unit_vector, AsRgb, Color, Point3, Ray, Vec3};
-use crate::world::World;
-use camera::CameraBuilder;
-use clap::Parser;
+use crate::csg::{Csg, CsgOp};
+use crate::environment::EnvironmentMap;
+use crate::hittables::{Cuboid, HitRecord, Hittable, Plane, Sphere, Torus};
+use crate::light::SunLight;
+use crate::sphere_list::SphereList;
+use crate::util::{consts, luminance, random_unit_vector, AsRgb, Color, Float, Point3, Ray, Vec3};
+use crate::world::{RayCounters, World};
+use camera::{ApertureShape, CameraBuilder, Projection};
+use clap::{Parser, ValueEnum};
 use eframe::epaint::{Color32, ColorImage};
 use hittables::Cylinder;
-use image::{ImageBuffer, Rgba, RgbaImage};
+use image::{GenericImage, ImageBuffer, Rgb, Rgb32FImage, Rgba, RgbaImage};
 use indicatif::ProgressBar;
-use material::{Dielectric, Lambertian, Metal};
+use log::{debug, info, warn, LevelFilter};
+use material::{BounceKind, Dielectric, DiffuseLight, DiffuseMode, Lambertian, Material, Metal};
 use rand::distributions::Uniform;
 use rand::prelude::Distribution;
-use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
-use util::{vec3_random, ProgressBarWrapper};
+use serde::Deserialize;
+use texture::{is_float_image, save_float_image, SolidColor};
+use util::{
+    derive_seed, encode_gamma2, vec3_random, PixelSampler, ProgressBarWrapper,
+    ReconstructionFilter, RenderRng, SampleEvent, SamplePattern, TimingSummary,
+};
 
 #[derive(Parser, Debug)]
 #[command(version)]
 pub struct Args {
     #[command(flatten)]
     raytrace_params: RaytraceParams,
+    /// `.hdr` and `.exr` store the linear values, without gamma and clamping
     #[arg(short, long, default_value = "output.png")]
     output_filename: String,
     #[arg(short, long, default_value_t = false)]
     gui: bool,
+    #[arg(long, value_enum, default_value_t = Scene::Cylinder)]
+    scene: Scene,
+    /// Point the camera at the center of the scene from a distance that shows all objects
+    #[arg(long, default_value_t = false)]
+    frame_scene: bool,
+    /// Test every ray against every object instead of building a bounding volume hierarchy
+    #[arg(long, default_value_t = false)]
+    no_bvh: bool,
+    /// With --no-bvh, skip objects whose bounding sphere the ray misses
+    #[arg(long, default_value_t = false)]
+    sphere_culling: bool,
+    #[arg(long, value_enum, default_value_t = BvhSplit::Sah)]
+    bvh_split: BvhSplit,
+    /// Seed for the random parts of the scene, e.g. the sphere layout of chapter13
+    #[arg(long, default_value_t = DEFAULT_SCENE_SEED)]
+    scene_seed: u64,
+    /// The small spheres of chapter13 cover the cells from -N to N in x and z
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_SCENE_GRID, value_parser = clap::value_parser!(i32).range(1..))]
+    scene_grid: i32,
+    /// Test the small spheres of chapter13 in batches, one `SphereList` per row of the grid,
+    /// instead of one by one in the BVH. Renders the same image.
+    #[arg(long, default_value_t = false)]
+    sphere_list: bool,
+    /// Number of render threads, defaults to the number of CPUs
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Log more, repeat for even more (-v: info, -vv: debug, -vvv: trace)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Use a polygonal aperture with this many blades instead of a round one
+    #[arg(long, value_parser = clap::value_parser!(u32).range(3..))]
+    aperture_blades: Option<u32>,
+    /// Render a stereo image with this inter-pupillary distance
+    #[arg(long)]
+    stereo: Option<Float>,
+    #[arg(long, value_enum, default_value_t = StereoMode::SideBySide)]
+    stereo_mode: StereoMode,
+    #[arg(long, value_enum, default_value_t = Projection::Perspective)]
+    projection: Projection,
+    /// Vertical field of view in degrees instead of the one of the scene, e.g. 180 for a
+    /// fisheye projection
+    #[arg(long)]
+    vfov: Option<Float>,
+    /// Compare two images instead of rendering: Prints error statistics and writes a heatmap
+    /// of the differences to the output file
+    #[arg(long, num_args = 2, value_names = ["A", "B"])]
+    diff: Option<Vec<PathBuf>>,
+    /// Run the renders listed in the JSON file FILE one after the other instead of rendering,
+    /// see `BatchJob` and the README for the format. A failed render does not stop the others.
+    #[arg(long, value_name = "FILE", conflicts_with = "gui")]
+    batch: Option<PathBuf>,
+    /// Continue the render saved with --save-accumulator, adding --samples-per-pixel samples
+    #[arg(long, value_name = "FILE")]
+    resume: Option<PathBuf>,
+    /// Save the linear sum of all samples, to continue later with --resume
+    #[arg(long, value_name = "FILE")]
+    save_accumulator: Option<PathBuf>,
+    /// Also write the linear average of the samples as raw little-endian f32 values, see
+    /// the README for the layout
+    #[arg(long, value_name = "FILE")]
+    raw_output: Option<PathBuf>,
+    /// Also write an 8-bit PNG next to a `.hdr` or `.exr` output (`render.exr` gets
+    /// `render.preview.png`) with --tone-map and --exposure, and next to --raw-output, encoded
+    /// like the main output
+    #[arg(long, default_value_t = false)]
+    preview: bool,
+    /// Tone mapping of the --preview of a `.hdr` or `.exr` output
+    #[arg(long, value_enum, default_value_t = ToneMap::Clamp)]
+    tone_map: ToneMap,
+    /// Brightness of the --preview of a `.hdr` or `.exr` output in stops, each doubles it
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    exposure: Float,
+    /// Render tile by tile with all samples, keeping finished tiles as PNGs in this directory
+    /// instead of float images of the whole frame in memory. For very large images.
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["resume", "save_accumulator", "raw_output"])]
+    tile_store: Option<PathBuf>,
+    /// Light the scene with a latitude-longitude image (e.g. `.hdr`) instead of the sky gradient
+    #[arg(long, value_name = "FILE")]
+    environment: Option<PathBuf>,
+    /// Render a turntable animation with this many frames, orbiting the camera once around
+    /// the look-at point. The frame number is appended to the output filename.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    frames: Option<u32>,
+    /// Number of animation frames rendered at the same time. Helps to keep all cores busy
+    /// when each frame is cheap, but keeps that many images in memory.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    frame_parallelism: u32,
+    /// Render one image for each of `steps` focus distances evenly spaced from start to end,
+    /// e.g. for focus stacking. The step number is appended to the output filename.
+    #[arg(long, value_name = "start,end,steps", value_parser = parse_focus_bracket,
+          conflicts_with = "frames")]
+    focus_bracket: Option<FocusBracket>,
+    /// Print the path of the ray through pixel x,y (from the top left) instead of rendering
+    #[arg(long, value_parser = parse_pixel)]
+    trace_pixel: Option<(u32, u32)>,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scene {
+    /// The final scene of "Ray Tracing in One Weekend" with lots of random spheres
+    Chapter13,
+    /// Like chapter13, but with the small spheres spread by Poisson-disk sampling, so that
+    /// none of them intersect
+    Chapter13Poisson,
+    /// Three spheres on a ground sphere
+    Tutorial,
+    Cylinder,
+    /// Spheres on the ground, lit by a low sun that casts hard shadows
+    Sun,
+    /// A small bright sphere above a floor in the dark, to check that the light on the
+    /// floor falls off with the square of the distance
+    Falloff,
+    /// Solids combined with constructive solid geometry: a box with a hole drilled through,
+    /// a glass cube with rounded edges and half a ring
+    Csg,
+}
+
+/// How the two eyes of a stereo render are put into one image
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StereoMode {
+    /// Left eye on the left half, right eye on the right half
+    SideBySide,
+    /// Red channel from the left eye, green and blue from the right eye
+    Anaglyph,
+}
+
+/// Debug shading that replaces the materials of the scene
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaterialOverride {
+    /// Every object is diffuse gray, to look at the geometry alone
+    Gray,
+    /// Surface normal of the first hit as color
+    Normal,
+    /// Texture coordinates of the first hit as red and green
+    Uv,
+}
+
+/// Maps linear values above 1 into the range of an 8-bit image
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    /// Cut off at 1, like the 8-bit output
+    Clamp,
+    /// x / (1 + x) per channel, keeps some detail in highlights at the cost of contrast
+    Reinhard,
+}
+
+impl ToneMap {
+    fn apply(self, value: Float) -> Float {
+        match self {
+            ToneMap::Clamp => value,
+            ToneMap::Reinhard => value / (1.0 + value),
+        }
+    }
+}
+
+/// How the color seen along a camera ray is computed
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Integrator {
+    /// Full light transport with bounces
+    Path,
+    /// Texture coordinates of the first hit as red and green, black where nothing is hit.
+    /// Shows the orientation of the UV mapping before a real texture is applied.
+    Uv,
+    /// Edges of the bounding boxes of all objects (triangles and quads: their own edges) as
+    /// lines of about a pixel. Edges behind the first surface are dimmed. Shows misplaced
+    /// and tiny objects.
+    Wireframe,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -61,16 +255,210 @@ pub struct RaytraceParams {
     #[arg(short, long = "width", default_value_t = 400)]
     pub image_width: u32,
     #[arg(short, long, default_value = "16:9", value_parser = parse_aspect_ratio)]
-    pub aspect_ratio: f64,
+    pub aspect_ratio: Float,
     #[arg(short, long, default_value_t = 10)]
     pub samples_per_pixel: u32,
     #[arg(short, long, default_value_t = 50)]
     pub max_depth: u32,
+    /// Maximum number of diffuse bounces, within --max-depth
+    #[arg(long)]
+    pub max_diffuse_depth: Option<u32>,
+    /// Maximum number of bounces off metals, within --max-depth
+    #[arg(long)]
+    pub max_glossy_depth: Option<u32>,
+    /// Maximum number of bounces at glass, within --max-depth. Glass needs many, because
+    /// every surface is hit twice.
+    #[arg(long)]
+    pub max_transmission_depth: Option<u32>,
+    /// Base seed of the per sample random number generators
+    #[arg(long, default_value_t = 232008239771)]
+    pub seed: u64,
+    /// Dither the 8 bit output to hide banding in smooth gradients
+    #[arg(long, default_value_t = false)]
+    pub dither: bool,
+    /// Print the number of rays and intersection tests after rendering
+    #[arg(long, default_value_t = false)]
+    pub stats: bool,
+    /// How the color of a camera ray is computed
+    #[arg(long, value_enum, default_value_t = Integrator::Path)]
+    pub integrator: Integrator,
+    /// Multiplies the light of the sky gradient or the environment map, e.g. below 1 to
+    /// keep it from washing out dim lights in the scene
+    #[arg(long, default_value_t = 1.0)]
+    pub background_intensity: Float,
+    /// Color of the sky gradient straight up, as linear r,g,b
+    #[arg(long, value_name = "r,g,b", default_value = "0.5,0.7,1", value_parser = parse_color)]
+    pub sky_top: Color,
+    /// Color of the sky gradient straight down, as linear r,g,b
+    #[arg(long, value_name = "r,g,b", default_value = "1,1,1", value_parser = parse_color)]
+    pub sky_bottom: Color,
+    /// Number of samples of each light at every diffuse hit. More samples reduce the noise
+    /// of the direct light at the cost of more shadow rays.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub light_samples: u32,
+    /// Replace all materials for debugging, the scene itself is not changed
+    #[arg(long, value_enum)]
+    pub override_material: Option<MaterialOverride>,
+    /// Supersampling factor: Render n×n subpixels per pixel and average them, which gives
+    /// clean edges independent of the samples per pixel
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub ssaa: u32,
+    /// Distribution of the sample positions within each pixel
+    #[arg(long, value_enum, default_value_t = SamplePattern::Uniform)]
+    pub sampler: SamplePattern,
+    /// Footprint and weighting of the samples of a pixel
+    #[arg(long, value_enum, default_value_t = ReconstructionFilter::Box)]
+    pub filter: ReconstructionFilter,
+    /// Shoot one ray through the center of each pixel, so every render gives the same values.
+    /// Forces a single sample per pixel.
+    #[arg(long, default_value_t = false)]
+    pub no_jitter: bool,
+    /// Scale samples down to at most this luminance, against fireflies: single very bright
+    /// samples of rare light paths, e.g. caustics. Biased, the image gets darker where light
+    /// is concentrated. Samples that are not finite (NaN) are always replaced by black.
+    #[arg(long, value_name = "max", value_parser = parse_max_luminance)]
+    pub clamp: Option<Float>,
+    /// Only render the region x,y,w,h (in pixels from the top left), the rest stays transparent
+    #[arg(long, value_parser = parse_crop)]
+    pub crop: Option<Crop>,
+    /// Edge length in pixels of the square tiles that are rendered in parallel. Small tiles
+    /// balance the load better, large ones have less scheduling overhead.
+    #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile_size: u32,
+    /// Save the image rendered so far every <secs> seconds (CLI only)
+    #[arg(long, value_name = "secs")]
+    pub autosave_interval: Option<u64>,
+    /// Overwrite the output file with the image so far after every k samples (default 1),
+    /// to watch it converge in a viewer that reloads on change. At most once per second.
+    /// (CLI only)
+    #[arg(long, value_name = "k", num_args = 0..=1, default_missing_value = "1",
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub progressive_output: Option<u32>,
+    /// Maximum number of live previews per second in the GUI. Each preview normalizes the
+    /// whole image while the render waits, so large images are previewed less often anyway.
+    #[arg(long, value_name = "fps", default_value_t = 10,
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub preview_fps: u32,
+    /// Where autosaves and progressive output are written, set from the output filename
+    #[arg(skip)]
+    pub autosave_path: Option<PathBuf>,
+    /// Height in pixels instead of the one derived from the width and the aspect ratio
+    #[arg(skip)]
+    pub exact_height: Option<u32>,
+}
+
+/// Region of the image, in pixels from the top left corner
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Crop {
+    fn contains(&self, x: u32, y: u32) -> bool {
+        self.x <= x && x < self.x + self.width && self.y <= y && y < self.y + self.height
+    }
+
+    fn scaled(&self, factor: u32) -> Crop {
+        Crop {
+            x: self.x * factor,
+            y: self.y * factor,
+            width: self.width * factor,
+            height: self.height * factor,
+        }
+    }
+}
+
+/// Rectangle of pixels that one thread renders from start to end, from the top left
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Tile {
+    /// Covers the image row by row with tiles of `size`×`size`, the last tiles of a row and
+    /// column are smaller if the size doesn't divide the image
+    fn grid(width: u32, height: u32, size: u32) -> impl Iterator<Item = Tile> {
+        let size = size.max(1);
+        (0..height).step_by(size as usize).flat_map(move |y| {
+            (0..width).step_by(size as usize).map(move |x| Tile {
+                x,
+                y,
+                width: size.min(width - x),
+                height: size.min(height - y),
+            })
+        })
+    }
+
+    fn overlaps(&self, crop: &Crop) -> bool {
+        self.x < crop.x + crop.width
+            && crop.x < self.x + self.width
+            && self.y < crop.y + crop.height
+            && crop.y < self.y + self.height
+    }
+}
+
+impl RaytraceParams {
+    pub fn image_height(&self) -> u32 {
+        self.exact_height
+            .unwrap_or((self.image_width as Float / self.aspect_ratio) as u32)
+    }
+
+    /// Limits not given separately are the overall `max_depth`
+    pub fn bounce_limits(&self) -> BounceLimits {
+        BounceLimits {
+            total: self.max_depth,
+            diffuse: self.max_diffuse_depth.unwrap_or(self.max_depth),
+            glossy: self.max_glossy_depth.unwrap_or(self.max_depth),
+            transmission: self.max_transmission_depth.unwrap_or(self.max_depth),
+        }
+    }
+
+    pub fn path_settings(&self) -> PathSettings {
+        PathSettings {
+            limits: self.bounce_limits(),
+            light_samples: self.light_samples,
+            material_override: self.override_material,
+            background_intensity: self.background_intensity,
+            sky: Sky {
+                top: self.sky_top,
+                bottom: self.sky_bottom,
+            },
+        }
+    }
+
+    /// Number of samples to render, more than one would only repeat the same rays without jitter
+    pub fn sample_count(&self) -> u32 {
+        if self.no_jitter {
+            1
+        } else {
+            self.samples_per_pixel
+        }
+    }
 }
 
-type F64RgbaImage = ImageBuffer<Rgba<f64>, Vec<f64>>;
+/// Start of files written by `SamplesAdder::save`, with a format version
+const ACCUMULATOR_MAGIC: &[u8; 8] = b"RTACC\x00\x00\x01";
+
+/// Start of files written by `SamplesAdder::save_raw`, with a format version
+const RAW_MAGIC: &[u8; 8] = b"RTRAW\x00\x00\x01";
+
+/// Linear color image, the precision follows `Float`
+type FloatRgbaImage = ImageBuffer<Rgba<Float>, Vec<Float>>;
+/// Sums the samples of every pixel. The reconstruction filter weights them, so RGB holds the
+/// weighted sum of the colors and the alpha channel the sum of the weights, which the average
+/// is divided by (`weighted_average`). With the box filter each sample has the weight 1 and the
+/// alpha channel counts the samples. A pixel without weight, e.g. outside the crop region, is
+/// transparent. Everything that reads `sum_img` has to divide by its alpha, not by
+/// `num_samples`, which is only the number of rendered passes.
+#[derive(Clone)]
 struct SamplesAdder {
-    sum_img: F64RgbaImage,
+    sum_img: FloatRgbaImage,
     num_samples: u32,
 }
 
@@ -82,28 +470,119 @@ impl SamplesAdder {
         }
     }
 
-    fn add_image(&mut self, step_img: &F64RgbaImage) {
-        let step_samples: &[f64] = step_img.as_flat_samples().samples;
-        let sum_samples: &mut [f64] = self.sum_img.as_flat_samples_mut().samples;
-        for (step_sample, sum_sample) in step_samples.iter().zip(sum_samples.iter_mut()) {
-            *sum_sample += *step_sample;
-        }
+    fn add_image(&mut self, step_img: &FloatRgbaImage) {
+        self.add_pixels(step_img);
         self.num_samples += 1;
     }
 
-    fn normalized(&self) -> RgbaImage {
-        let num_samples = self.num_samples as f64;
-        let mut img = RgbaImage::new(self.sum_img.width(), self.sum_img.height());
-        let sum_samples = self.sum_img.as_flat_samples().samples;
-        let img_samples = img.as_flat_samples_mut().samples;
-        for (sum_sample, img_sample) in sum_samples.iter().zip(img_samples.iter_mut()) {
-            *img_sample = ((*sum_sample / num_samples).sqrt().clamp(0.0, 0.999) * 256.0) as u8;
+    /// Adds all samples of `other`, which must have the same size
+    fn add_adder(&mut self, other: &SamplesAdder) {
+        self.add_pixels(&other.sum_img);
+        self.num_samples += other.num_samples;
+    }
+
+    fn add_pixels(&mut self, img: &FloatRgbaImage) {
+        let samples: &[Float] = img.as_flat_samples().samples;
+        let sum_samples: &mut [Float] = self.sum_img.as_flat_samples_mut().samples;
+        for (sample, sum_sample) in samples.iter().zip(sum_samples.iter_mut()) {
+            *sum_sample += *sample;
+        }
+    }
+
+    /// Writes the sum image and the number of samples, so that rendering can be resumed
+    /// later. The values are stored as little endian f64 whatever `Float` is. The alpha
+    /// channel is the weight sum, so the samples of a resumed render are weighted like the
+    /// saved ones only with the same --filter.
+    fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(ACCUMULATOR_MAGIC)?;
+        for value in [
+            self.sum_img.width(),
+            self.sum_img.height(),
+            self.num_samples,
+        ] {
+            file.write_all(&value.to_le_bytes())?;
+        }
+        for value in self.sum_img.as_flat_samples().samples {
+            // `Float` is f32 with the precision-f32 feature
+            #[allow(clippy::unnecessary_cast)]
+            file.write_all(&(*value as f64).to_le_bytes())?;
+        }
+        file.flush()
+    }
+
+    fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if magic != *ACCUMULATOR_MAGIC {
+            return Err(invalid("Not an accumulator file"));
+        }
+        let mut read_u32 = || -> io::Result<u32> {
+            let mut bytes = [0u8; 4];
+            file.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        };
+        let (width, height, num_samples) = (read_u32()?, read_u32()?, read_u32()?);
+        let mut adder = SamplesAdder::new(width, height);
+        adder.num_samples = num_samples;
+        for value in adder.sum_img.as_flat_samples_mut().samples.iter_mut() {
+            let mut bytes = [0u8; 8];
+            file.read_exact(&mut bytes)
+                .map_err(|_| invalid("Accumulator file is truncated"))?;
+            *value = f64::from_le_bytes(bytes) as Float;
+        }
+        Ok(adder)
+    }
+
+    /// Linear weighted average of the samples, without gamma
+    fn averaged(&self) -> FloatRgbaImage {
+        let mut img = self.sum_img.clone();
+        for pixel in img.pixels_mut() {
+            *pixel = weighted_average(pixel);
         }
         img
     }
 
-    fn normalized_colorimage(&self) -> ColorImage {
-        let num_samples = self.num_samples as f64;
+    /// `averaged()` without alpha, as f32 for saving. Values above 1 are kept.
+    fn hdr(&self) -> Rgb32FImage {
+        let img = self.averaged();
+        Rgb32FImage::from_fn(img.width(), img.height(), |x, y| {
+            let [r, g, b, _] = img.get_pixel(x, y).0;
+            // `Float` is f32 with the precision-f32 feature
+            #[allow(clippy::unnecessary_cast)]
+            Rgb([r as f32, g as f32, b as f32])
+        })
+    }
+
+    /// Writes `averaged()` for external tools: `RAW_MAGIC`, then width, height and the number
+    /// of channels (4, RGBA) as u32, then all values as f32, row by row from the top left.
+    /// All numbers are little-endian.
+    fn save_raw(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let img = self.averaged();
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(RAW_MAGIC)?;
+        for value in [img.width(), img.height(), 4] {
+            file.write_all(&value.to_le_bytes())?;
+        }
+        for value in img.as_flat_samples().samples {
+            // `Float` is f32 with the precision-f32 feature
+            #[allow(clippy::unnecessary_cast)]
+            file.write_all(&(*value as f32).to_le_bytes())?;
+        }
+        file.flush()
+    }
+
+    /// With `dither`, an ordered dither pattern hides the banding of smooth gradients
+    fn normalized(&self, dither: bool) -> RgbaImage {
+        RgbaImage::from_fn(self.sum_img.width(), self.sum_img.height(), |x, y| {
+            let sum_pixel = self.sum_img.get_pixel(x, y);
+            normalized_pixel(sum_pixel, dither, x as usize, y as usize)
+        })
+    }
+
+    fn normalized_colorimage(&self, dither: bool) -> ColorImage {
         let sum_samples = self.sum_img.as_flat_samples().samples;
         let size = [
             self.sum_img.width() as usize,
@@ -111,14 +590,26 @@ impl SamplesAdder {
         ];
         let mut img_pixels: Vec<Color32> = vec![Color32::from_gray(0); size[0] * size[1]];
 
-        for (sum_pixels, img_pixel) in sum_samples.chunks_exact(4).zip(img_pixels.iter_mut()) {
-            *img_pixel = Color32::from_rgba_unmultiplied(
-                ((sum_pixels[0] / num_samples).sqrt().clamp(0.0, 0.999) * 256.0) as u8,
-                ((sum_pixels[1] / num_samples).sqrt().clamp(0.0, 0.999) * 256.0) as u8,
-                ((sum_pixels[2] / num_samples).sqrt().clamp(0.0, 0.999) * 256.0) as u8,
-                255,
-            )
-        }
+        // Runs on one worker of the render, use the idle threads to finish it quickly
+        sum_samples
+            .par_chunks_exact(4)
+            .zip(img_pixels.par_iter_mut())
+            .enumerate()
+            .for_each(|(i, (sum_pixels, img_pixel))| {
+                let offset = if dither {
+                    bayer_offset(i % size[0], i / size[0])
+                } else {
+                    0.0
+                };
+                let [r, g, b, a] = weighted_average(&Rgba(sum_pixels.try_into().unwrap())).0;
+                // Transparent outside the crop region, like `normalized`
+                *img_pixel = Color32::from_rgba_unmultiplied(
+                    encode_gamma2(r, offset),
+                    encode_gamma2(g, offset),
+                    encode_gamma2(b, offset),
+                    encode_gamma2(a, 0.0),
+                )
+            });
         ColorImage {
             size,
             pixels: img_pixels,
@@ -126,47 +617,211 @@ impl SamplesAdder {
     }
 }
 
+/// Weighted average of the color and the coverage as alpha: transparent where no sample
+/// reached the pixel, like outside the crop region
+fn weighted_average(sum_pixel: &Rgba<Float>) -> Rgba<Float> {
+    let [r, g, b, weight] = sum_pixel.0;
+    if weight > 0.0 {
+        Rgba([r / weight, g / weight, b / weight, 1.0])
+    } else {
+        Rgba([0.0; 4])
+    }
+}
+
+/// Weighted average of the samples at pixel (x, y), gamma encoded
+fn normalized_pixel(sum_pixel: &Rgba<Float>, dither: bool, x: usize, y: usize) -> Rgba<u8> {
+    let offset = if dither { bayer_offset(x, y) } else { 0.0 };
+    let [r, g, b, a] = weighted_average(sum_pixel).0;
+    Rgba([
+        encode_gamma2(r, offset),
+        encode_gamma2(g, offset),
+        encode_gamma2(b, offset),
+        encode_gamma2(a, 0.0),
+    ])
+}
+
+/// Ordered dithering offset for pixel (x, y) in -0.5..0.5
+fn bayer_offset(x: usize, y: usize) -> Float {
+    const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+    (BAYER_4X4[y % 4][x % 4] as Float + 0.5) / 16.0 - 0.5
+}
+
 pub fn render_live(
     params: &RaytraceParams,
     world: &World,
     camera: &Camera,
-    progress: &Box<dyn ProgressBarWrapper>,
+    progress: &dyn ProgressBarWrapper,
     stop: Arc<AtomicBool>,
 ) -> RgbaImage {
-    progress.set_length(params.samples_per_pixel as u64);
+    let adder = SamplesAdder::new(params.image_width, params.image_height());
+    accumulate(params, world, camera, progress, stop, adder).normalized(params.dither)
+}
 
-    let image_height: u32 = (params.image_width as f64 / params.aspect_ratio) as u32;
-    let img: Mutex<SamplesAdder> = Mutex::new(SamplesAdder::new(params.image_width, image_height));
+/// Adds `params.sample_count()` samples to `adder`. The sample indices continue after the
+/// samples already in `adder`, so a resumed render gets new samples.
+fn accumulate(
+    params: &RaytraceParams,
+    world: &World,
+    camera: &Camera,
+    progress: &dyn ProgressBarWrapper,
+    stop: Arc<AtomicBool>,
+    adder: SamplesAdder,
+) -> SamplesAdder {
+    progress.set_length(params.sample_count() as u64);
+    let start = Instant::now();
 
-    (0..params.samples_per_pixel).into_par_iter().for_each(|s| {
-        if stop.load(Relaxed) {
-            return;
+    let (image_width, image_height) = adder.sum_img.dimensions();
+    let first_sample = adder.num_samples;
+    let sample_count = params.sample_count();
+    // Sample `first_sample + i` goes into slot `i % slots.len()`. Each slot adds its
+    // samples in index order and the slots are added up in slot order, so the rounding doesn't
+    // depend on the number of threads. A sample is split into tiles, so even a few slots keep
+    // all threads busy.
+    let slots: Vec<Mutex<SamplesAdder>> = (0..ACCUMULATOR_SLOTS.min(sample_count))
+        .map(|_| Mutex::new(SamplesAdder::new(image_width, image_height)))
+        .collect();
+    // Locks the slots one after the other, the other workers continue meanwhile
+    let snapshot = || {
+        let mut sum = adder.clone();
+        for slot in slots.iter() {
+            sum.add_adder(&slot.lock().unwrap());
         }
+        sum
+    };
+    let ray_counters: Mutex<RayCounters> = Mutex::new(RayCounters::default());
+    let sample_times = Mutex::new(vec![]);
+    // Time of the last autosave or progressive output and the number of samples it had
+    let last_save = Mutex::new((Instant::now(), first_sample));
+    let last_preview = Mutex::new(Instant::now());
+    let preview_cost = Mutex::new(Duration::ZERO);
+    let preview_interval = Duration::from_secs(1) / params.preview_fps;
+    let completed = AtomicU32::new(0);
 
-        let mut small_rng = SmallRng::seed_from_u64(232008239771 + s as u64);
-        let step_img = render_sample(params, world, camera, &mut small_rng, Arc::clone(&stop));
-
-        if stop.load(Relaxed) {
-            return;
-        }
+    slots.par_iter().enumerate().for_each(|(slot_index, slot)| {
+        let slot_samples =
+            (first_sample + slot_index as u32..first_sample + sample_count).step_by(slots.len());
+        for s in slot_samples {
+            if stop.load(Relaxed) {
+                return;
+            }
+            let sample_start = Instant::now();
+            let (step_img, step_counters) =
+                render_sample(params, world, camera, s, Arc::clone(&stop));
+            let duration = sample_start.elapsed();
+            if stop.load(Relaxed) {
+                return;
+            }
+            slot.lock().unwrap().add_image(&step_img);
+            *ray_counters.lock().unwrap() += step_counters;
+            sample_times.lock().unwrap().push(duration);
+            let completed = completed.fetch_add(1, Relaxed) + 1;
 
-        img.lock().unwrap().add_image(&step_img);
+            if let Some(path) = &params.autosave_path {
+                let save_due = {
+                    let mut last_save = last_save.lock().unwrap();
+                    let (last_time, last_samples) = *last_save;
+                    let num_samples = first_sample + completed;
+                    let autosave_due = params.autosave_interval.is_some_and(|interval| {
+                        last_time.elapsed() >= Duration::from_secs(interval)
+                    });
+                    let progressive_due = params.progressive_output.is_some_and(|every| {
+                        num_samples - last_samples >= every
+                            && last_time.elapsed() >= PROGRESSIVE_OUTPUT_INTERVAL
+                    });
+                    if autosave_due || progressive_due {
+                        *last_save = (Instant::now(), num_samples);
+                    }
+                    autosave_due || progressive_due
+                };
+                if save_due {
+                    match snapshot().normalized(params.dither).save(path) {
+                        Ok(()) => debug!("Autosaved to {}", path.display()),
+                        Err(e) => warn!("Autosave to {} failed: {}", path.display(), e),
+                    }
+                }
+            }
 
-        if stop.load(Relaxed) {
-            return;
+            // At high resolutions a preview is slow enough that it has to be spaced out
+            // further to stay a small part of the render time
+            let min_interval =
+                preview_interval.max(PREVIEW_COST_FACTOR * *preview_cost.lock().unwrap());
+            if is_due(&last_preview, min_interval) {
+                let preview_start = Instant::now();
+                progress.inc(1, Some(&|| snapshot().normalized_colorimage(params.dither)));
+                *preview_cost.lock().unwrap() = preview_start.elapsed();
+                *last_preview.lock().unwrap() = Instant::now();
+            } else {
+                progress.inc(1, None);
+            }
+            progress.on_sample_complete(&SampleEvent {
+                sample_index: s,
+                completed,
+                total: sample_count,
+                elapsed: start.elapsed(),
+            });
         }
-
-        progress.inc(1, &Box::new(|| img.lock().unwrap().normalized_colorimage()));
     });
+    let mut img = adder;
+    for slot in slots {
+        img.add_adder(&slot.into_inner().unwrap());
+    }
+    let ray_counters = ray_counters.into_inner().unwrap();
+    let sample_times = sample_times.into_inner().unwrap();
+    progress.on_render_stats(&ray_counters);
     progress.finish();
-    img.into_inner().unwrap().normalized()
+    let elapsed = start.elapsed();
+    let rays_per_second = ray_counters.rays as f64 / elapsed.as_secs_f64();
+    info!(
+        "Rendered {} samples at {}x{} in {:.2?} ({:.0} rays/s)",
+        img.num_samples, params.image_width, image_height, elapsed, rays_per_second
+    );
+    if ray_counters.clamped_samples > 0 {
+        info!(
+            "Clamped {} samples that were not finite or too bright",
+            ray_counters.clamped_samples
+        );
+    }
+    if params.stats {
+        println!(
+            "Rays: {}, intersection tests: {} ({:.1} per ray), {:.0} rays/s",
+            ray_counters.rays,
+            ray_counters.intersection_tests,
+            ray_counters.intersection_tests as f64 / ray_counters.rays as f64,
+            rays_per_second
+        );
+        if let Some(timing) = TimingSummary::new(&sample_times) {
+            println!("Time per sample: {}", timing);
+        }
+        println!("Clamped samples: {}", ray_counters.clamped_samples);
+    }
+    img
+}
+
+/// The time between two previews is at least this many times the time a preview takes
+const PREVIEW_COST_FACTOR: u32 = 20;
+/// Number of partial sums that `accumulate` adds samples to in parallel. Fixed, so that the
+/// output doesn't depend on the number of threads.
+const ACCUMULATOR_SLOTS: u32 = 8;
+/// Minimum time between two writes of --progressive-output, so fast samples don't thrash
+/// the disk and the image viewer
+const PROGRESSIVE_OUTPUT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Whether `interval` has passed since `last`, which is then reset to now. Only one of the
+/// threads that check at the same time gets `true`.
+fn is_due(last: &Mutex<Instant>, interval: Duration) -> bool {
+    let mut last = last.lock().unwrap();
+    let due = last.elapsed() >= interval;
+    if due {
+        *last = Instant::now();
+    }
+    due
 }
 
 pub fn render(
     params: &RaytraceParams,
     world: &World,
     camera: &Camera,
-    progress: &Box<dyn ProgressBarWrapper>,
+    progress: &dyn ProgressBarWrapper,
 ) -> RgbaImage {
     render_live(
         params,
@@ -177,100 +832,512 @@ pub fn render(
     )
 }
 
+/// Like `render`, but returns the linear average of the samples without gamma, clamping or
+/// quantization, e.g. for saving as `.exr` or `.hdr`
+pub fn render_hdr(
+    params: &RaytraceParams,
+    world: &World,
+    camera: &Camera,
+    progress: &dyn ProgressBarWrapper,
+) -> Rgb32FImage {
+    let adder = SamplesAdder::new(params.image_width, params.image_height());
+    let stop = Arc::new(AtomicBool::new(false));
+    accumulate(params, world, camera, progress, stop, adder).hdr()
+}
+
+/// Renders exactly `width`×`height` pixels, the camera gets the matching aspect ratio.
+/// `render` derives the height from the aspect ratio, which can be off by one.
+pub fn render_with_dimensions(
+    params: &RaytraceParams,
+    world: &World,
+    camera_builder: &CameraBuilder,
+    width: u32,
+    height: u32,
+    progress: &dyn ProgressBarWrapper,
+) -> RgbaImage {
+    let mut params = params.clone();
+    params.image_width = width;
+    params.exact_height = Some(height);
+    params.aspect_ratio = width as Float / height as Float;
+    let camera = camera_builder
+        .clone()
+        .aspect_ratio(params.aspect_ratio)
+        .build()
+        .expect("camera_builder is incomplete or invalid");
+    render(&params, world, &camera, progress)
+}
+
+/// Renders both eyes of a stereo camera and composes them into one image
+pub fn render_stereo(
+    params: &RaytraceParams,
+    world: &World,
+    camera: &Camera,
+    progress: &dyn ProgressBarWrapper,
+    mode: StereoMode,
+) -> RgbaImage {
+    let (left_camera, right_camera) = camera
+        .stereo_eyes()
+        .expect("Stereo rendering needs a stereo camera");
+    let left = render(params, world, &left_camera, progress);
+    let right = render(params, world, &right_camera, progress);
+
+    match mode {
+        StereoMode::SideBySide => {
+            let mut img = RgbaImage::new(left.width() * 2, left.height());
+            img.copy_from(&left, 0, 0).unwrap();
+            img.copy_from(&right, left.width(), 0).unwrap();
+            img
+        }
+        StereoMode::Anaglyph => {
+            let mut img = right;
+            for (pixel, left_pixel) in img.pixels_mut().zip(left.pixels()) {
+                pixel[0] = left_pixel[0];
+            }
+            img
+        }
+    }
+}
+
+/// Number of pixels between two checks of the stop flag. A pixel takes microseconds, so the
+/// flag is seen quickly, while the atomic loads stay negligible.
+const STOP_CHECK_INTERVAL: usize = 64;
+
+/// Renders sample `sample_index` of every pixel, the rows in parallel. Each pixel has its own
+/// random number generator derived from the seed, the sample index and its position, so the
+/// result neither depends on the number of threads nor on which pixels are rendered.
+/// Also returns the work done.
+/// The samples are splatted with `params.filter`, see `splat`.
+/// With supersampling the subpixels are averaged, the result always has the output size.
 pub fn render_sample(
     params: &RaytraceParams,
     world: &World,
     camera: &Camera,
-    rng: &mut SmallRng,
+    sample_index: u32,
     stop: Arc<AtomicBool>,
-) -> F64RgbaImage {
-    let image_height: u32 = (params.image_width as f64 / params.aspect_ratio) as u32;
-    let mut img: F64RgbaImage = ImageBuffer::new(params.image_width, image_height);
-    let rn_distr: Uniform<f64> = Uniform::new(0.0, 1.0);
-
-    for y in 0..image_height {
-        for x in 0..params.image_width {
-            let u = (x as f64 + rn_distr.sample(rng)) / (params.image_width - 1) as f64;
-            let v = (y as f64 + rn_distr.sample(rng)) / (image_height - 1) as f64;
-            let ray = camera.get_ray(u, v, rng);
-            let c = ray_color(&ray, &world, params.max_depth, rng);
-            img.put_pixel(x, image_height - 1 - y, c.as_f64_rgba()); // ImageBuffer uses inverse y axis direction
-        }
-        if stop.load(Relaxed) {
+) -> (FloatRgbaImage, RayCounters) {
+    let image_width = params.image_width * params.ssaa;
+    let image_height = params.image_height() * params.ssaa;
+    let crop = params.crop.map(|crop| crop.scaled(params.ssaa));
+    let tiles: Vec<Tile> = Tile::grid(image_width, image_height, params.tile_size)
+        .filter(|tile| crop.is_none_or(|crop| tile.overlaps(&crop)))
+        .collect();
+
+    let rendered_tiles: Vec<(Vec<PixelSample>, RayCounters)> = tiles
+        .par_iter()
+        .map(|tile| {
+            // A tile runs on one thread from start to end, so the thread local counters see
+            // all of its work
+            let counters_before = RayCounters::current();
+            let samples = render_tile(params, world, camera, sample_index, tile, &stop);
+            (samples, RayCounters::current().since(counters_before))
+        })
+        .collect();
+
+    let mut samples = vec![PixelSample::default(); image_width as usize * image_height as usize];
+    let mut ray_counters = RayCounters::default();
+    for (tile, (tile_samples, counters)) in tiles.iter().zip(rendered_tiles) {
+        ray_counters += counters;
+        for (tile_row, row_samples) in tile_samples.chunks_exact(tile.width as usize).enumerate() {
+            let start = (tile.y as usize + tile_row) * image_width as usize + tile.x as usize;
+            samples[start..start + row_samples.len()].copy_from_slice(row_samples);
+        }
+    }
+    let area = Tile {
+        x: 0,
+        y: 0,
+        width: image_width,
+        height: image_height,
+    };
+    let img = splat(&samples, &area, &area, crop, params.filter);
+
+    if params.ssaa > 1 {
+        (downsample(&img, params.ssaa), ray_counters)
+    } else {
+        (img, ray_counters)
+    }
+}
+
+/// One sample of a pixel, before the filter spreads it
+#[derive(Clone, Copy, Default)]
+struct PixelSample {
+    color: Color,
+    /// Position within the pixel, in 0..1 with y up
+    offset: (Float, Float),
+    /// Pixels outside the crop region or after a stop have no sample
+    rendered: bool,
+}
+
+/// Sample `sample_index` of the pixels in `tile`, row by row. The tile is given in pixels of
+/// the supersampled image.
+fn render_tile(
+    params: &RaytraceParams,
+    world: &World,
+    camera: &Camera,
+    sample_index: u32,
+    tile: &Tile,
+    stop: &AtomicBool,
+) -> Vec<PixelSample> {
+    let sample_seed = derive_seed(params.seed, sample_index as u64);
+    let image_width = params.image_width * params.ssaa;
+    let image_height = params.image_height() * params.ssaa;
+    let sampler = PixelSampler::new(params.sampler, params.seed);
+    let crop = params.crop.map(|crop| crop.scaled(params.ssaa));
+
+    let mut samples = vec![PixelSample::default(); tile.width as usize * tile.height as usize];
+    if stop.load(Relaxed) {
+        return samples;
+    }
+    for (i, sample) in samples.iter_mut().enumerate() {
+        // Cancel within large tiles too, the GUI restarts renders on every change
+        if i % STOP_CHECK_INTERVAL == 0 && i > 0 && stop.load(Relaxed) {
             break;
         }
+        let x = tile.x + i as u32 % tile.width;
+        let row = tile.y + i as u32 / tile.width;
+        // The camera still sees the full image, so a crop matches the full render
+        if crop.is_some_and(|crop| !crop.contains(x, row)) {
+            continue;
+        }
+        // ImageBuffer uses inverse y axis direction
+        let y = image_height - 1 - row;
+        // Seeded per pixel, so the result doesn't depend on the tiling
+        let pixel_seed = derive_seed(derive_seed(sample_seed, y as u64), x as u64);
+        let mut rng = RenderRng::seed_from_u64(pixel_seed);
+        let (dx, dy) = if params.no_jitter {
+            (0.5, 0.5)
+        } else {
+            sampler.next_pixel_offset((x, y), sample_index, &mut rng)
+        };
+        let u = (x as Float + dx) / (image_width - 1) as Float;
+        let v = (y as Float + dy) / (image_height - 1) as Float;
+        // Outside the image circle of a fisheye stays black
+        let mut c = match camera.get_ray(u, v, &mut rng) {
+            Some(ray) => integrate(params, camera, &ray, world, &mut rng),
+            None => Color::zeros(),
+        };
+        if let Some(guarded) = guard_sample(&c, params.clamp) {
+            RayCounters::count_clamped_sample();
+            c = guarded;
+        }
+        *sample = PixelSample {
+            color: c,
+            offset: (dx, dy),
+            rendered: true,
+        };
     }
+    samples
+}
 
+/// Adds every sample of `samples`, which covers `area` row by row, to the pixels of `target`
+/// within the radius of `filter`: its own pixel and, except for the box filter, the
+/// neighbors, also across tile borders. RGB is the sum of the weighted colors and the alpha
+/// channel the sum of the weights, see `SamplesAdder`. Pixels outside `crop` stay empty.
+/// The rows of `target` run in parallel, each pixel gets its samples in row order of the
+/// image, so the sums don't depend on the tiling. `area` must contain the samples within the
+/// margin of `target`, as far as they are in the image.
+fn splat(
+    samples: &[PixelSample],
+    area: &Tile,
+    target: &Tile,
+    crop: Option<Crop>,
+    filter: ReconstructionFilter,
+) -> FloatRgbaImage {
+    let margin = filter.margin();
+    let mut img: FloatRgbaImage = ImageBuffer::new(target.width, target.height);
+    let row_len = target.width as usize * 4;
+    (*img)
+        .par_chunks_exact_mut(row_len)
+        .enumerate()
+        .for_each(|(target_row, pixels)| {
+            let row = target.y + target_row as u32;
+            let source_rows = row.saturating_sub(margin).max(area.y)
+                ..(row + margin + 1).min(area.y + area.height);
+            for source_row in source_rows {
+                let first_x = target.x.saturating_sub(margin).max(area.x);
+                let end_x = (target.x + target.width + margin).min(area.x + area.width);
+                for source_x in first_x..end_x {
+                    let i = (source_row - area.y) as usize * area.width as usize
+                        + (source_x - area.x) as usize;
+                    let sample = &samples[i];
+                    if !sample.rendered {
+                        continue;
+                    }
+                    let (dx, dy) = sample.offset;
+                    // y is up within the pixel, but rows go down
+                    let weight_y = filter.weight(source_row as Float - row as Float + 0.5 - dy);
+                    if weight_y == 0.0 {
+                        continue;
+                    }
+                    let xs = source_x.saturating_sub(margin).max(target.x)
+                        ..(source_x + margin + 1).min(target.x + target.width);
+                    for x in xs {
+                        if crop.is_some_and(|crop| !crop.contains(x, row)) {
+                            continue;
+                        }
+                        let weight =
+                            weight_y * filter.weight(x as Float - source_x as Float + 0.5 - dx);
+                        if weight == 0.0 {
+                            continue;
+                        }
+                        let pixel = &mut pixels[(x - target.x) as usize * 4..][..4];
+                        for (p, c) in pixel.iter_mut().zip(sample.color.iter()) {
+                            *p += weight * c;
+                        }
+                        pixel[3] += weight;
+                    }
+                }
+            }
+        });
     img
 }
 
-fn scene_chapter13() -> (World, CameraBuilder) {
-    let mut world = World::new();
-    let mut small_rng = SmallRng::seed_from_u64(23428359242 as u64);
-    let distr_0_1: Uniform<f64> = Uniform::new(0.0, 1.0);
-    let distr_0p5_1: Uniform<f64> = Uniform::new(0.5, 1.0);
+/// The replacement of a sample that is not finite (black) or has a luminance above
+/// `max_luminance` (scaled down, keeping the hue). `None` if the sample is fine. A single NaN
+/// would stay in the sum of its pixel for the rest of the render.
+fn guard_sample(c: &Color, max_luminance: Option<Float>) -> Option<Color> {
+    if !c.iter().all(|v| v.is_finite()) {
+        return Some(Color::zeros());
+    }
+    let max_luminance = max_luminance?;
+    let l = luminance(c);
+    (l > max_luminance).then(|| c * (max_luminance / l))
+}
+
+/// Renders one tile at a time with all samples, instead of one sample at a time for the
+/// whole image. Each finished tile is written to `dir` as 8 bit PNG, and the image is only
+/// assembled from those at the end. Only a few tiles are kept as floats, which makes images
+/// possible that wouldn't fit into memory as `FloatRgbaImage`. Gives the same image as
+/// `render`.
+fn render_to_tile_store(
+    params: &RaytraceParams,
+    world: &World,
+    camera: &Camera,
+    progress: &dyn ProgressBarWrapper,
+    dir: &Path,
+) -> image::ImageResult<RgbaImage> {
+    let (width, height) = (params.image_width, params.image_height());
+    let tiles: Vec<Tile> = Tile::grid(width, height, params.tile_size)
+        .filter(|tile| params.crop.is_none_or(|crop| tile.overlaps(&crop)))
+        .collect();
+    std::fs::create_dir_all(dir)?;
+    progress.set_length(tiles.len() as u64);
+    let start = Instant::now();
+    let tile_path = |tile: &Tile| dir.join(format!("tile_{}_{}.png", tile.x, tile.y));
+
+    let crop = params.crop.map(|crop| crop.scaled(params.ssaa));
+    let stop = AtomicBool::new(false);
+    tiles.par_iter().try_for_each(|tile| {
+        let ssaa = params.ssaa;
+        let subpixels = Tile {
+            x: tile.x * ssaa,
+            y: tile.y * ssaa,
+            width: tile.width * ssaa,
+            height: tile.height * ssaa,
+        };
+        // The samples of the pixels around the tile spread into it too
+        let margin = params.filter.margin();
+        let (x, y) = (
+            subpixels.x.saturating_sub(margin),
+            subpixels.y.saturating_sub(margin),
+        );
+        let area = Tile {
+            x,
+            y,
+            width: (subpixels.x + subpixels.width + margin).min(width * ssaa) - x,
+            height: (subpixels.y + subpixels.height + margin).min(height * ssaa) - y,
+        };
+        // A `SamplesAdder::sum_img` of the tile, RGB weighted and the weight sum as alpha
+        let mut sum: FloatRgbaImage = ImageBuffer::new(tile.width, tile.height);
+        // Same samples and order of additions as `accumulate`, so the sums are identical
+        for sample_index in 0..params.sample_count() {
+            let samples = render_tile(params, world, camera, sample_index, &area, &stop);
+            let sample = splat(&samples, &area, &subpixels, crop, params.filter);
+            let sample = if ssaa > 1 {
+                downsample(&sample, ssaa)
+            } else {
+                sample
+            };
+            for (s, c) in sum.iter_mut().zip(sample.iter()) {
+                *s += c;
+            }
+        }
+        let img = RgbaImage::from_fn(tile.width, tile.height, |x, y| {
+            let (x_abs, y_abs) = ((tile.x + x) as usize, (tile.y + y) as usize);
+            normalized_pixel(sum.get_pixel(x, y), params.dither, x_abs, y_abs)
+        });
+        img.save(tile_path(tile))?;
+        progress.inc(1, None);
+        Ok::<_, image::ImageError>(())
+    })?;
 
+    let mut img = RgbaImage::new(width, height);
+    for tile in &tiles {
+        let tile_img = image::open(tile_path(tile))?.into_rgba8();
+        img.copy_from(&tile_img, tile.x, tile.y)?;
+    }
+    progress.finish();
+    info!(
+        "Rendered {} tiles of {} samples at {}x{} in {:.2?}",
+        tiles.len(),
+        params.sample_count(),
+        width,
+        height,
+        start.elapsed()
+    );
+    Ok(img)
+}
+
+/// Averages blocks of `factor`×`factor` pixels
+fn downsample(img: &FloatRgbaImage, factor: u32) -> FloatRgbaImage {
+    let scale = 1.0 / (factor * factor) as Float;
+    ImageBuffer::from_fn(img.width() / factor, img.height() / factor, |x, y| {
+        let mut sum = [0.0; 4];
+        for dy in 0..factor {
+            for dx in 0..factor {
+                let pixel = img.get_pixel(x * factor + dx, y * factor + dy);
+                for (s, c) in sum.iter_mut().zip(pixel.0) {
+                    *s += c;
+                }
+            }
+        }
+        Rgba(sum.map(|s| s * scale))
+    })
+}
+
+const DEFAULT_SCENE_SEED: u64 = 23428359242;
+const DEFAULT_SCENE_GRID: i32 = 11;
+
+impl Scene {
+    /// `grid` and `sphere_list` only apply to chapter13, see `scene_random`
+    fn build(self, seed: u64, grid: i32, sphere_list: bool) -> (World, CameraBuilder) {
+        match self {
+            Scene::Chapter13 => scene_random(grid, seed, sphere_list),
+            Scene::Chapter13Poisson => scene_chapter13_poisson(seed),
+            Scene::Tutorial => scene_tutorial(seed),
+            Scene::Cylinder => scene_cylinder(seed),
+            Scene::Sun => scene_sun(seed),
+            Scene::Falloff => scene_falloff(seed),
+            Scene::Csg => scene_csg(seed),
+        }
+    }
+}
+
+fn scene_chapter13(seed: u64) -> (World, CameraBuilder) {
+    scene_random(11, seed, false)
+}
+
+/// The scene of chapter13 with one small sphere in each cell of the grid from `-grid` to
+/// `grid`. The cells are generated in parallel, each with its own random numbers, so the
+/// scene doesn't depend on the number of threads. With `sphere_list`, each row of small
+/// spheres is one `SphereList`.
+fn scene_random(grid: i32, seed: u64, sphere_list: bool) -> (World, CameraBuilder) {
+    let mut world = World::new();
     let material_ground = Lambertian::new(Color::new(0.5, 0.5, 0.5));
     world.add(Sphere::new(0.0, -1000.0, 0.0, 1000.0, &material_ground));
 
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat = distr_0_1.sample(&mut small_rng);
-            let center = Point3::new(
-                a as f64 + 0.9 * distr_0_1.sample(&mut small_rng),
-                0.2,
-                b as f64 + 0.9 * distr_0_1.sample(&mut small_rng),
-            );
-
-            if (center - Point3::new(4.0, 0.2, 0.0)).magnitude() > 0.9 {
-                if choose_mat < 0.8 {
-                    // diffse
-                    let albedo: Color = vec3_random(&distr_0_1, &mut small_rng)
-                        .component_mul(&vec3_random(&distr_0_1, &mut small_rng));
-                    let sphere_material = Lambertian::new(albedo);
-                    world.add(Sphere::new(
-                        center.x,
-                        center.y,
-                        center.z,
+    let rows: Vec<Vec<Sphere>> = (-grid..grid)
+        .into_par_iter()
+        .map(|a| {
+            let row_seed = derive_seed(seed, a as u64);
+            (-grid..grid)
+                .filter_map(|b| {
+                    let mut small_rng = RenderRng::seed_from_u64(derive_seed(row_seed, b as u64));
+                    let distr_0_1: Uniform<Float> = Uniform::new(0.0, 1.0);
+                    let choose_mat = distr_0_1.sample(&mut small_rng);
+                    let center = Point3::new(
+                        a as Float + 0.9 * distr_0_1.sample(&mut small_rng),
                         0.2,
-                        &sphere_material,
-                    ));
-                } else if choose_mat < 0.95 {
-                    // metal
-                    let albedo: Color = vec3_random(&distr_0p5_1, &mut small_rng);
-                    let fuzz = distr_0_1.sample(&mut small_rng) / 2.0;
-                    let sphere_material = Metal::new(albedo, fuzz);
-                    world.add(Sphere::new(
-                        center.x,
-                        center.y,
-                        center.z,
-                        0.2,
-                        &sphere_material,
-                    ));
-                } else {
-                    // glass
-                    let sphere_material = Dielectric::new(1.5);
-                    world.add(Sphere::new(
-                        center.x,
-                        center.y,
-                        center.z,
-                        0.2,
-                        &sphere_material,
-                    ));
-                }
+                        b as Float + 0.9 * distr_0_1.sample(&mut small_rng),
+                    );
+                    ((center - Point3::new(4.0, 0.2, 0.0)).magnitude() > 0.9)
+                        .then(|| random_small_sphere(center, choose_mat, &mut small_rng))
+                })
+                .collect()
+        })
+        .collect();
+    // `collect` keeps the order of the cells
+    for row in rows {
+        if sphere_list {
+            // Smaller lists than a row are slower, the BVH is better at skipping spheres
+            world.add(SphereList::new(row));
+        } else {
+            for sphere in row {
+                world.add(Arc::new(sphere));
             }
         }
     }
 
+    add_chapter13_big_spheres(&mut world);
+    (world, chapter13_camera())
+}
+
+fn scene_chapter13_poisson(seed: u64) -> (World, CameraBuilder) {
+    let mut world = World::new();
+    let mut small_rng = RenderRng::seed_from_u64(seed);
+    let distr_0_1: Uniform<Float> = Uniform::new(0.0, 1.0);
+
+    let material_ground = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+    world.add(Sphere::new(0.0, -1000.0, 0.0, 1000.0, &material_ground));
+
+    // A spacing of 0.8 gives about as many spheres as the jittered grid of chapter13
+    for (x, z) in poisson_disk(22.0, 22.0, 0.8, &mut small_rng) {
+        let center = Point3::new(x - 11.0, 0.2, z - 11.0);
+        // Keep clear of the big spheres
+        let clear = [-4.0, 0.0, 4.0]
+            .iter()
+            .all(|big_x| (center - Point3::new(*big_x, 1.0, 0.0)).magnitude() > 1.25);
+        if clear {
+            let choose_mat = distr_0_1.sample(&mut small_rng);
+            world.add(Arc::new(random_small_sphere(
+                center,
+                choose_mat,
+                &mut small_rng,
+            )));
+        }
+    }
+
+    add_chapter13_big_spheres(&mut world);
+    (world, chapter13_camera())
+}
+
+/// Small sphere of chapter13: mostly diffuse, some metal and few glass
+fn random_small_sphere(center: Point3, choose_mat: Float, rng: &mut RenderRng) -> Sphere {
+    let distr_0_1: Uniform<Float> = Uniform::new(0.0, 1.0);
+    let distr_0p5_1: Uniform<Float> = Uniform::new(0.5, 1.0);
+    let sphere_material = if choose_mat < 0.8 {
+        // diffse
+        let albedo: Color =
+            vec3_random(&distr_0_1, rng).component_mul(&vec3_random(&distr_0_1, rng));
+        Lambertian::new(albedo)
+    } else if choose_mat < 0.95 {
+        // metal
+        let albedo: Color = vec3_random(&distr_0p5_1, rng);
+        let fuzz = distr_0_1.sample(rng) / 2.0;
+        Metal::new(albedo, fuzz)
+    } else {
+        // glass
+        Dielectric::new(1.5)
+    };
+    Sphere {
+        center,
+        radius: 0.2,
+        material: sphere_material,
+        invert_normals: false,
+    }
+}
+
+fn add_chapter13_big_spheres(world: &mut World) {
     let material1 = Dielectric::new(1.5);
     world.add(Sphere::new(0.0, 1.0, 0.0, 1.0, &material1));
 
     let material2 = Lambertian::new(Color::new(0.4, 0.2, 0.1));
     world.add(Sphere::new(-4.0, 1.0, 0.0, 1.0, &material2));
 
-    let material3 = Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
+    let material3 = Metal::with_fresnel(Color::new(0.7, 0.6, 0.5), 0.0);
     world.add(Sphere::new(4.0, 1.0, 0.0, 1.0, &material3));
+}
 
+fn chapter13_camera() -> CameraBuilder {
     let mut camera = CameraBuilder::new();
     camera
         .lookfrom(Point3::new(13.0, 2.0, 3.0))
@@ -280,10 +1347,68 @@ fn scene_chapter13() -> (World, CameraBuilder) {
         .aperture(0.1)
         .focus_dist(10.0);
 
-    (world, camera)
+    camera
+}
+
+/// Points in 0..width × 0..depth that are at least `min_distance` apart, filling the area
+/// evenly (Bridson's algorithm). A grid with at most one point per cell limits the distance
+/// checks to the neighboring cells.
+fn poisson_disk(
+    width: Float,
+    depth: Float,
+    min_distance: Float,
+    rng: &mut RenderRng,
+) -> Vec<(Float, Float)> {
+    // Candidates around each point before it is retired
+    const ATTEMPTS: usize = 30;
+    let cell = min_distance / consts::SQRT_2;
+    let columns = (width / cell).ceil() as usize;
+    let rows = (depth / cell).ceil() as usize;
+    let mut grid: Vec<Option<usize>> = vec![None; columns * rows];
+    let cell_of = |(x, z): (Float, Float)| ((x / cell) as usize, (z / cell) as usize);
+
+    let mut points = vec![(rng.gen::<Float>() * width, rng.gen::<Float>() * depth)];
+    let (column, row) = cell_of(points[0]);
+    grid[row * columns + column] = Some(0);
+    let mut active = vec![0];
+
+    while !active.is_empty() {
+        let active_index = rng.gen_range(0..active.len());
+        let (px, pz) = points[active[active_index]];
+        let mut found = false;
+        for _ in 0..ATTEMPTS {
+            let angle = rng.gen::<Float>() * 2.0 * consts::PI;
+            let radius = min_distance * (1.0 + rng.gen::<Float>());
+            let candidate = (px + radius * angle.cos(), pz + radius * angle.sin());
+            if !(0.0..width).contains(&candidate.0) || !(0.0..depth).contains(&candidate.1) {
+                continue;
+            }
+            let (column, row) = cell_of(candidate);
+            let too_close = (row.saturating_sub(2)..(row + 3).min(rows)).any(|r| {
+                (column.saturating_sub(2)..(column + 3).min(columns)).any(|c| {
+                    grid[r * columns + c].is_some_and(|i| {
+                        let (x, z) = points[i];
+                        (x - candidate.0).powi(2) + (z - candidate.1).powi(2)
+                            < min_distance * min_distance
+                    })
+                })
+            });
+            if !too_close {
+                grid[row * columns + column] = Some(points.len());
+                active.push(points.len());
+                points.push(candidate);
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            active.swap_remove(active_index);
+        }
+    }
+    points
 }
 
-fn scene_tutorial() -> (World, CameraBuilder) {
+fn scene_tutorial(_seed: u64) -> (World, CameraBuilder) {
     let material_ground = Lambertian::new(Color::new(0.8, 0.8, 0.0));
     let material_center = Lambertian::new(Color::new(0.1, 0.2, 0.5));
     let material_left = Dielectric::new(1.5);
@@ -293,7 +1418,7 @@ fn scene_tutorial() -> (World, CameraBuilder) {
     world.add(Sphere::new(0.0, -100.5, -1.0, 100.0, &material_ground));
     world.add(Sphere::new(0.0, 0.0, -1.0, 0.5, &material_center));
     world.add(Sphere::new(-1.0, 0.0, -1.0, 0.5, &material_left));
-    world.add(Sphere::new(-1.0, 0.0, -1.0, -0.45, &material_left));
+    world.add(Sphere::hollow(-1.0, 0.0, -1.0, 0.45, &material_left));
     world.add(Sphere::new(1.0, 0.0, -1.0, 0.5, &material_right));
 
     let mut camera = CameraBuilder::new();
@@ -309,7 +1434,7 @@ fn scene_tutorial() -> (World, CameraBuilder) {
 }
 
 #[allow(unused_variables)]
-fn scene_cylinder() -> (World, CameraBuilder) {
+fn scene_cylinder(_seed: u64) -> (World, CameraBuilder) {
     let material_ground = Lambertian::new(Color::new(0.8, 0.8, 0.0));
     let material_center = Lambertian::new(Color::new(0.1, 0.2, 0.5));
     let material_left = Dielectric::new(1.5);
@@ -320,7 +1445,7 @@ fn scene_cylinder() -> (World, CameraBuilder) {
     world.add(Sphere::new(0.0, -100.5, -1.0, 100.0, &material_ground));
     // world.add(Sphere::new(0.0, 0.0, -1.0, 0.5, &material_center));
     world.add(Sphere::new(-1.0, 0.0, -1.0, 0.5, &material_left));
-    world.add(Sphere::new(-1.0, 0.0, -1.0, -0.45, &material_left));
+    world.add(Sphere::hollow(-1.0, 0.0, -1.0, 0.45, &material_left));
     world.add(Sphere::new(1.0, 0.0, -1.0, 0.5, &material_right));
 
     world.add(Cylinder::new(
@@ -342,56 +1467,2337 @@ fn scene_cylinder() -> (World, CameraBuilder) {
     (world, camera)
 }
 
-fn ray_color(ray: &Ray, world: &World, depth: u32, rng: &mut SmallRng) -> Color {
-    if depth == 0 {
-        return Color::zeros();
-    }
+fn scene_sun(_seed: u64) -> (World, CameraBuilder) {
+    let material_ground = Lambertian::new(Color::new(0.6, 0.6, 0.6));
+    let material_red = Lambertian::new(Color::new(0.7, 0.1, 0.1));
+    let material_blue = Lambertian::new(Color::new(0.1, 0.2, 0.6));
+    let material_metal = Metal::new(Color::new(0.8, 0.8, 0.8), 0.1);
 
-    if let Some(hitrecord) = world.hit(ray, 0.001, 1000.) {
-        if let Some((attenuation, scatterray)) = hitrecord.material.scatter(ray, &hitrecord, rng) {
-            return attenuation.component_mul(&ray_color(&scatterray, world, depth - 1, rng));
-        } else {
-            return Color::zeros();
-        }
-    }
-    // Ray hits background
-    let unit_dir: Vec3 = ray.direction().normalize(); // .y Range: -1 to 1
-    let t = 0.5 * (unit_dir.y + 1.); // Range: 0 to 1
-    (1. - t) * Color::new(1., 1., 1.) + t * Color::new(0.5, 0.7, 1.0) // blend
-}
+    let mut world = World::new();
+    world.add(Sphere::new(0.0, -1000.0, 0.0, 1000.0, &material_ground));
+    world.add(Sphere::new(-1.5, 0.5, 0.0, 0.5, &material_red));
+    world.add(Sphere::new(0.0, 0.7, -0.5, 0.7, &material_metal));
+    world.add(Sphere::new(1.5, 0.4, 0.5, 0.4, &material_blue));
+    world.add_light(Arc::new(SunLight::new(
+        Vec3::new(-1.0, -0.7, -0.6),
+        Color::new(3.0, 2.8, 2.5),
+    )));
 
-fn parse_aspect_ratio<'a>(
-    aspect_ratio: &'a str,
-) -> Result<f64, Box<dyn Error + Send + Sync + 'static>> {
-    let err = "Aspect ratio format is: '<w>:<h>', e.g.: '16:9'";
-    let mut aspect_ratio = aspect_ratio.split(":");
-    let w: f64 = aspect_ratio.next().ok_or(err)?.parse().map_err(|_| err)?;
-    let h: f64 = aspect_ratio.next().ok_or(err)?.parse().map_err(|_| err)?;
-    Ok(w / h)
+    let mut camera = CameraBuilder::new();
+    camera
+        .lookfrom(Point3::new(0.0, 2.0, 6.0))
+        .lookat(Point3::new(0.0, 0.5, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .vfov(40.0)
+        .aperture(0.0)
+        .focus_dist(6.0);
+
+    (world, camera)
 }
 
-fn main() {
-    // playground::test_image();
-    // playground::test_vectormath();
-    // playground::test_ray_cylinder_math();
+/// Emissive sphere of radius 0.5 at a height of 2 above a gray floor, under a black sky.
+/// A sphere that is completely above the horizon lights the floor like a point light, so the
+/// floor directly below it is 8 times brighter than at a distance of 4 (`cos θ / d²`).
+fn scene_falloff(_seed: u64) -> (World, CameraBuilder) {
+    let material_floor = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+    let material_light = DiffuseLight::new(Color::new(1.0, 1.0, 1.0), 16.0);
 
-    let args = Args::parse();
+    let mut world = World::new();
+    world.add(Plane::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        &material_floor,
+    ));
+    world.add(Sphere::new(0.0, 2.0, 0.0, 0.5, &material_light));
+    let black = image::Rgb32FImage::new(1, 1);
+    world.set_environment(Arc::new(EnvironmentMap::from_image(&black)));
 
-    // World and Camera
-    let (world, mut camera_builder) = scene_cylinder();
-    camera_builder.aspect_ratio(args.raytrace_params.aspect_ratio);
+    let mut camera = CameraBuilder::new();
+    camera
+        .lookfrom(Point3::new(0.0, 4.0, 10.0))
+        .lookat(Point3::new(0.0, 0.5, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .vfov(50.0)
+        .aperture(0.0)
+        .focus_dist(10.0);
 
-    if args.gui {
-        crate::gui::run_gui(args.raytrace_params, world, camera_builder);
-    } else {
-        let progress: Box<dyn ProgressBarWrapper> = Box::new(ProgressBar::new(1));
-        let img = render(
-            &args.raytrace_params,
-            &world,
-            &camera_builder.build().unwrap(),
-            &progress,
-        );
-        img.save(args.output_filename)
-            .expect("Could not save file.");
+    (world, camera)
+}
+
+fn scene_csg(_seed: u64) -> (World, CameraBuilder) {
+    let material_ground = Lambertian::new(Color::new(0.6, 0.6, 0.6));
+    let material_red = Lambertian::new(Color::new(0.7, 0.1, 0.1));
+    let material_glass = Dielectric::new(1.5);
+    let material_gold = Metal::new(Color::new(0.8, 0.6, 0.2), 0.2);
+
+    let mut world = World::new();
+    world.add(Sphere::new(0.0, -1000.0, 0.0, 1000.0, &material_ground));
+
+    // Drilled through at an angle, so the hole can be seen from the camera
+    let block = Cuboid::new(
+        Point3::new(-2.4, 0.0, -0.6),
+        Point3::new(-1.2, 1.2, 0.6),
+        &material_red,
+    );
+    let drill = Cylinder::tapered(
+        Point3::new(-2.6, 0.2, 1.0),
+        Point3::new(-1.0, 1.0, -1.0),
+        0.3,
+        0.3,
+        &material_red,
+    );
+    world.add(Csg::new(CsgOp::Difference, &block, &drill));
+
+    let cube = Cuboid::new(
+        Point3::new(-0.5, 0.0, -0.5),
+        Point3::new(0.5, 1.0, 0.5),
+        &material_glass,
+    );
+    let ball = Sphere::new(0.0, 0.5, 0.0, 0.68, &material_glass);
+    world.add(Csg::new(CsgOp::Intersection, &cube, &ball));
+
+    // The upper half of the ring stands on the ground like an arch
+    let ring = Torus::new(
+        Point3::new(1.8, 0.0, 0.0),
+        Vec3::new(1.0, 0.0, 1.0),
+        0.5,
+        0.25,
+        &material_gold,
+    );
+    let lower = Cuboid::new(
+        Point3::new(1.0, -1.0, -1.0),
+        Point3::new(2.6, 0.0, 1.0),
+        &material_gold,
+    );
+    world.add(Csg::new(CsgOp::Difference, &ring, &lower));
+
+    let mut camera = CameraBuilder::new();
+    camera
+        .lookfrom(Point3::new(0.0, 2.5, 6.0))
+        .lookat(Point3::new(0.0, 0.5, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .vfov(40.0)
+        .aperture(0.0)
+        .focus_dist(6.0);
+
+    (world, camera)
+}
+
+/// Color seen along the camera ray `ray`, computed by `params.integrator`
+fn integrate(
+    params: &RaytraceParams,
+    camera: &Camera,
+    ray: &Ray,
+    world: &World,
+    rng: &mut RenderRng,
+) -> Color {
+    match params.integrator {
+        Integrator::Path => trace_path(ray, world, &params.path_settings(), rng, None),
+        Integrator::Uv => match world.hit(ray, 0.001, 1000.) {
+            Some(hit) => Color::new(hit.u, hit.v, 0.),
+            None => Color::zeros(),
+        },
+        // Lines about one pixel wide
+        Integrator::Wireframe => wireframe(ray, world, camera.pixel_angle(params.image_height())),
+    }
+}
+
+/// White if `ray` passes an edge of `world` at an angle below `width` (in radians), gray if
+/// that edge is behind the first surface along the ray, black otherwise
+fn wireframe(ray: &Ray, world: &World, width: Float) -> Color {
+    let direction = ray.direction().normalize();
+    let first_surface = world
+        .hit(ray, 0.001, 1000.)
+        .map_or(Float::INFINITY, |hit| hit.t * ray.direction().magnitude());
+    let mut color = Color::zeros();
+    for object in world.iter() {
+        for (a, b) in object.wireframe_edges() {
+            let Some((along, distance)) = ray_segment_distance(&ray.origin(), &direction, &a, &b)
+            else {
+                continue;
+            };
+            if distance < width * along {
+                let brightness = if along <= first_surface { 1.0 } else { 0.3 };
+                color = color.sup(&Color::repeat(brightness));
+            }
+        }
+    }
+    color
+}
+
+/// Closest approach of the ray from `origin` along the unit vector `direction` to the
+/// segment from `a` to `b`: The distance along the ray and the distance between the two.
+/// `None` if the closest point is behind the origin.
+fn ray_segment_distance(
+    origin: &Point3,
+    direction: &Vec3,
+    a: &Point3,
+    b: &Point3,
+) -> Option<(Float, Float)> {
+    let edge = b - a;
+    let edge_len_squared = edge.magnitude_squared();
+    let offset = origin - a;
+    let cos = direction.dot(&edge);
+    let denom = edge_len_squared - cos * cos;
+    // Parameter along the edge (0 at `a`, 1 at `b`) of the nearest points of the two lines.
+    // For parallel lines any point does, take `a`.
+    let s = if denom > 1e-12 * edge_len_squared {
+        (edge.dot(&offset) - cos * direction.dot(&offset)) / denom
+    } else {
+        0.0
+    };
+    let s = if edge_len_squared > 0.0 {
+        s.clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let point = a + s * edge;
+    let along = (point - origin).dot(direction);
+    if along <= 0.0 {
+        return None;
+    }
+    Some((along, (origin + along * direction - point).magnitude()))
+}
+
+/// Maximum number of surfaces a path hits, overall and per `BounceKind` of their materials.
+/// A path ends at the first surface that would exceed a limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BounceLimits {
+    pub total: u32,
+    pub diffuse: u32,
+    pub glossy: u32,
+    pub transmission: u32,
+}
+
+impl BounceLimits {
+    /// The same limit for all kinds
+    pub fn new(depth: u32) -> Self {
+        BounceLimits {
+            total: depth,
+            diffuse: depth,
+            glossy: depth,
+            transmission: depth,
+        }
+    }
+
+    fn get(&self, kind: BounceKind) -> u32 {
+        match kind {
+            BounceKind::Diffuse => self.diffuse,
+            BounceKind::Glossy => self.glossy,
+            BounceKind::Transmission => self.transmission,
+        }
+    }
+}
+
+/// Follows the path of `ray` through at most `limits.total` bounces. The attenuations along
+/// the path are multiplied into `throughput`, which weights the light found at each vertex.
+/// Takes one sample of each light at diffuse hits.
+fn ray_color(
+    ray: &Ray,
+    world: &World,
+    limits: BounceLimits,
+    material_override: Option<MaterialOverride>,
+    rng: &mut RenderRng,
+) -> Color {
+    let settings = PathSettings {
+        material_override,
+        ..PathSettings::new(limits)
+    };
+    trace_path(ray, world, &settings, rng, None)
+}
+
+/// Options of `trace_path` besides the ray
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathSettings {
+    pub limits: BounceLimits,
+    /// Samples of each light at diffuse hits
+    pub light_samples: u32,
+    pub material_override: Option<MaterialOverride>,
+    /// Scales the light of the sky or environment map that paths escape to
+    pub background_intensity: Float,
+    /// Used if the world has no environment map
+    pub sky: Sky,
+}
+
+/// Gradient from `bottom` straight down to `top` straight up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sky {
+    pub top: Color,
+    pub bottom: Color,
+}
+
+impl Default for Sky {
+    /// White to light blue
+    fn default() -> Self {
+        Sky {
+            top: Color::new(0.5, 0.7, 1.0),
+            bottom: Color::new(1., 1., 1.),
+        }
+    }
+}
+
+impl PathSettings {
+    /// One light sample, the scene's own materials and the background at full strength
+    pub fn new(limits: BounceLimits) -> Self {
+        PathSettings {
+            limits,
+            light_samples: 1,
+            material_override: None,
+            background_intensity: 1.0,
+            sky: Sky::default(),
+        }
+    }
+}
+
+/// One vertex of a traced path
+pub struct PathSegment {
+    pub ray: Ray,
+    /// `None` if the ray left the scene
+    pub hit: Option<HitRecord>,
+    /// Attenuation and scattered ray, `None` if the ray was absorbed or left the scene
+    pub scatter: Option<(Color, Ray)>,
+}
+
+impl fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = |v: &Vec3| format!("({:.4}, {:.4}, {:.4})", v.x, v.y, v.z);
+        write!(f, "ray {} -> {}", v(&self.ray.orig), v(&self.ray.dir))?;
+        let Some(hit) = &self.hit else {
+            return write!(f, ": background");
+        };
+        write!(
+            f,
+            ": hit at t = {:.4}, p = {}, normal = {}, {} face",
+            hit.t,
+            v(&hit.p),
+            v(&hit.normal),
+            if hit.front_face { "front" } else { "back" }
+        )?;
+        match &self.scatter {
+            Some((attenuation, scattered)) => write!(
+                f,
+                ", attenuation {}, scattered to {}",
+                v(attenuation),
+                v(&scattered.dir)
+            ),
+            None => write!(f, ", absorbed"),
+        }
+    }
+}
+
+/// Follows the path of the ray through the center of pixel (x, y), counted from the top left,
+/// with a fixed random number generator. Returns the color and every vertex of the path.
+pub fn trace_ray_debug(
+    params: &RaytraceParams,
+    camera: &Camera,
+    x: u32,
+    y: u32,
+    world: &World,
+) -> (Color, Vec<PathSegment>) {
+    let image_height = params.image_height();
+    let mut rng = RenderRng::seed_from_u64(params.seed);
+    let u = (x as Float + 0.5) / (params.image_width - 1) as Float;
+    let v = ((image_height - 1 - y) as Float + 0.5) / (image_height - 1) as Float;
+    let Some(ray) = camera.get_ray(u, v, &mut rng) else {
+        return (Color::zeros(), vec![]);
+    };
+    let mut path = vec![];
+    let color = trace_path(
+        &ray,
+        world,
+        &params.path_settings(),
+        &mut rng,
+        Some(&mut path),
+    );
+    (color, path)
+}
+
+/// `ray_color`, optionally recording each vertex of the path
+fn trace_path(
+    ray: &Ray,
+    world: &World,
+    settings: &PathSettings,
+    rng: &mut RenderRng,
+    mut path: Option<&mut Vec<PathSegment>>,
+) -> Color {
+    let PathSettings {
+        limits,
+        light_samples,
+        material_override,
+        background_intensity,
+        sky,
+    } = *settings;
+    let mut record = |ray: &Ray, hit: Option<&HitRecord>, scatter: &Option<(Color, Ray)>| {
+        if let Some(path) = path.as_deref_mut() {
+            path.push(PathSegment {
+                ray: *ray,
+                hit: hit.cloned(),
+                scatter: *scatter,
+            });
+        }
+    };
+    let mut ray = *ray;
+    let mut throughput = Color::new(1., 1., 1.);
+    let mut accumulated = Color::zeros();
+    let (mut diffuse, mut glossy, mut transmission) = (0, 0, 0);
+    for _ in 0..limits.total {
+        let Some(hitrecord) = world.hit(&ray, 0.001, 1000.) else {
+            record(&ray, None, &None);
+            let background = background_intensity * background(world, &sky, &ray);
+            return accumulated + throughput.component_mul(&background);
+        };
+        let material: &dyn Material = match material_override {
+            None => hitrecord.material.as_ref(),
+            Some(MaterialOverride::Gray) => {
+                static GRAY: OnceLock<Lambertian> = OnceLock::new();
+                GRAY.get_or_init(|| Lambertian {
+                    albedo: SolidColor::new(Color::new(0.5, 0.5, 0.5)),
+                    mode: DiffuseMode::Cosine,
+                })
+            }
+            Some(MaterialOverride::Normal) => {
+                record(&ray, Some(&hitrecord), &None);
+                return (hitrecord.normal + Vec3::new(1., 1., 1.)) / 2.0;
+            }
+            Some(MaterialOverride::Uv) => {
+                record(&ray, Some(&hitrecord), &None);
+                return Color::new(hitrecord.u, hitrecord.v, 0.);
+            }
+        };
+        accumulated += throughput.component_mul(&material.emitted(&hitrecord));
+        if let Some(albedo) = material.diffuse_albedo(&hitrecord) {
+            let direct = direct_light(world, &hitrecord, light_samples, rng);
+            accumulated += throughput.component_mul(&albedo.component_mul(&direct));
+        }
+        let kind = material.bounce_kind();
+        let bounces = match kind {
+            BounceKind::Diffuse => &mut diffuse,
+            BounceKind::Glossy => &mut glossy,
+            BounceKind::Transmission => &mut transmission,
+        };
+        if *bounces >= limits.get(kind) {
+            record(&ray, Some(&hitrecord), &None);
+            return accumulated;
+        }
+        *bounces += 1;
+        let scatter = material.scatter(&ray, &hitrecord, rng);
+        record(&ray, Some(&hitrecord), &scatter);
+        match scatter {
+            Some((attenuation, scatterray)) => {
+                throughput.component_mul_assign(&attenuation);
+                ray = scatterray;
+            }
+            None => return accumulated,
+        }
+    }
+    // Bounce limit reached
+    accumulated
+}
+
+/// Light arriving at a diffuse hit directly from the lights of `world`, weighted with the
+/// Lambertian BRDF (without albedo) and the cosine at the surface. Averages `samples`
+/// samples of each light.
+fn direct_light(world: &World, rec: &HitRecord, samples: u32, rng: &mut RenderRng) -> Color {
+    let mut direct = Color::zeros();
+    for light in world.lights() {
+        for _ in 0..samples {
+            let Some(sample) = light.sample(&rec.p, rng) else {
+                continue;
+            };
+            let cos_theta = sample.direction.dot(&rec.normal);
+            if cos_theta <= 0.0 {
+                continue;
+            }
+            let shadow_ray = rec.scattered(sample.direction);
+            if world.hit(&shadow_ray, 0.001, sample.distance).is_none() {
+                direct += sample.irradiance * cos_theta / consts::PI;
+            }
+        }
+    }
+    direct / samples as Float
+}
+
+/// Renders `frames` images with the camera orbiting once around its look-at point, up to
+/// `parallelism` frames at the same time. Each frame is saved as soon as it is done.
+fn render_turntable(
+    params: &RaytraceParams,
+    world: &World,
+    camera_builder: &CameraBuilder,
+    frames: u32,
+    parallelism: u32,
+    stereo_mode: StereoMode,
+    output_filename: &str,
+) -> image::ImageResult<()> {
+    let progress = ProgressBar::new(frames as u64);
+    let frame_indices: Vec<u32> = (0..frames).collect();
+    // One batch at a time bounds the number of frames in memory
+    for batch in frame_indices.chunks(parallelism as usize) {
+        batch.par_iter().try_for_each(|&frame| {
+            let camera = camera_builder
+                .clone()
+                .orbit(360.0 * frame as Float / frames as Float)
+                .build()
+                .expect("Orbiting keeps the camera valid");
+            let hidden = ProgressBar::hidden();
+            let img = if camera.stereo_eyes().is_some() {
+                render_stereo(params, world, &camera, &hidden, stereo_mode)
+            } else {
+                render(params, world, &camera, &hidden)
+            };
+            progress.inc(1);
+            img.save(frame_filename(output_filename, frame))
+        })?;
+    }
+    progress.finish();
+    Ok(())
+}
+
+/// Renders one image per focus distance of `bracket`, one after the other, and saves each
+/// as soon as it is done
+fn render_focus_bracket(
+    params: &RaytraceParams,
+    world: &World,
+    camera_builder: &CameraBuilder,
+    bracket: FocusBracket,
+    stereo_mode: StereoMode,
+    output_filename: &str,
+) -> image::ImageResult<()> {
+    let progress = ProgressBar::new(bracket.steps as u64);
+    for (step, focus_dist) in bracket.distances().enumerate() {
+        info!("Focus bracket step {}: focus distance {}", step, focus_dist);
+        let camera = camera_builder
+            .clone()
+            .focus_dist(focus_dist)
+            .build()
+            .expect("Focus bracket distances are positive");
+        let hidden = ProgressBar::hidden();
+        let img = if camera.stereo_eyes().is_some() {
+            render_stereo(params, world, &camera, &hidden, stereo_mode)
+        } else {
+            render(params, world, &camera, &hidden)
+        };
+        img.save(frame_filename(output_filename, step as u32))?;
+        progress.inc(1);
+    }
+    progress.finish();
+    Ok(())
+}
+
+/// `dir/name.png` becomes `dir/name_0007.png` for frame 7
+fn frame_filename(output_filename: &str, frame: u32) -> PathBuf {
+    let path = Path::new(output_filename);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy()),
+        None => format!("{}_{:04}", stem, frame),
+    };
+    path.with_file_name(name)
+}
+
+fn background(world: &World, sky: &Sky, ray: &Ray) -> Color {
+    if let Some(environment) = world.environment() {
+        return environment.eval(&ray.direction());
+    }
+    let unit_dir: Vec3 = ray.direction().normalize(); // .y Range: -1 to 1
+    let t = 0.5 * (unit_dir.y + 1.); // Range: 0 to 1
+    (1. - t) * sky.bottom + t * sky.top // blend
+}
+
+fn parse_aspect_ratio<'a>(
+    aspect_ratio: &'a str,
+) -> Result<Float, Box<dyn Error + Send + Sync + 'static>> {
+    let err = "Aspect ratio format is: '<w>:<h>', e.g.: '16:9'";
+    let mut aspect_ratio = aspect_ratio.split(":");
+    let w: Float = aspect_ratio.next().ok_or(err)?.parse().map_err(|_| err)?;
+    let h: Float = aspect_ratio.next().ok_or(err)?.parse().map_err(|_| err)?;
+    Ok(w / h)
+}
+
+fn parse_color(color: &str) -> Result<Color, Box<dyn Error + Send + Sync + 'static>> {
+    let err = "Color format is: 'r,g,b', e.g.: '1,0.5,0.2'";
+    let values = color
+        .split(',')
+        .map(|v| v.trim().parse::<Float>().map_err(|_| err))
+        .collect::<Result<Vec<_>, _>>()?;
+    match values.as_slice() {
+        [r, g, b] if values.iter().all(|v| *v >= 0.0) => Ok(Color::new(*r, *g, *b)),
+        [_, _, _] => Err("Color components must not be negative".into()),
+        _ => Err(err.into()),
+    }
+}
+
+/// Focus distances for `--focus-bracket`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FocusBracket {
+    pub start: Float,
+    pub end: Float,
+    pub steps: u32,
+}
+
+impl FocusBracket {
+    /// `steps` distances from `start` to `end`, both included
+    fn distances(&self) -> impl Iterator<Item = Float> + '_ {
+        (0..self.steps).map(|step| {
+            if self.steps == 1 {
+                self.start
+            } else {
+                self.start + (self.end - self.start) * step as Float / (self.steps - 1) as Float
+            }
+        })
+    }
+}
+
+fn parse_focus_bracket(
+    bracket: &str,
+) -> Result<FocusBracket, Box<dyn Error + Send + Sync + 'static>> {
+    let err = "Focus bracket format is: 'start,end,steps', e.g.: '2.5,4,7'";
+    let values: Vec<&str> = bracket.split(',').map(|v| v.trim()).collect();
+    let [start, end, steps] = values.as_slice() else {
+        return Err(err.into());
+    };
+    let start: Float = start.parse().map_err(|_| err)?;
+    let end: Float = end.parse().map_err(|_| err)?;
+    let steps: u32 = steps.parse().map_err(|_| err)?;
+    if start > 0.0 && end > 0.0 && steps > 0 {
+        Ok(FocusBracket { start, end, steps })
+    } else {
+        Err("Focus distances and the number of steps must be positive".into())
+    }
+}
+
+fn parse_crop(crop: &str) -> Result<Crop, Box<dyn Error + Send + Sync + 'static>> {
+    let err = "Crop format is: 'x,y,w,h', e.g.: '100,50,64,64'";
+    let values = crop
+        .split(',')
+        .map(|v| v.trim().parse::<u32>().map_err(|_| err))
+        .collect::<Result<Vec<_>, _>>()?;
+    match values.as_slice() {
+        [x, y, width, height] if *width > 0 && *height > 0 => Ok(Crop {
+            x: *x,
+            y: *y,
+            width: *width,
+            height: *height,
+        }),
+        _ => Err(err.into()),
+    }
+}
+
+/// A limit of 0 would make every sample black, a negative one flips bright samples negative
+fn parse_max_luminance(max: &str) -> Result<Float, Box<dyn Error + Send + Sync + 'static>> {
+    let max: Float = max.trim().parse().map_err(|_| "Not a number")?;
+    if max.is_finite() && max > 0.0 {
+        Ok(max)
+    } else {
+        Err("The maximum luminance must be positive and finite".into())
+    }
+}
+
+fn parse_pixel(pixel: &str) -> Result<(u32, u32), Box<dyn Error + Send + Sync + 'static>> {
+    let err = "Pixel format is: 'x,y', e.g.: '120,40'";
+    let (x, y) = pixel.split_once(',').ok_or(err)?;
+    Ok((
+        x.trim().parse().map_err(|_| err)?,
+        y.trim().parse().map_err(|_| err)?,
+    ))
+}
+
+/// One render of a `--batch` file, e.g.
+/// `{"scene": "chapter13", "output": "chapter 13.png", "params": {"width": 800, "dither": true}}`
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct BatchJob {
+    scene: Scene,
+    /// `.hdr` and `.exr` store the linear values, like `--output-filename`
+    output: PathBuf,
+    #[serde(default = "default_scene_seed")]
+    scene_seed: u64,
+    /// Like `--scene-grid`, only for chapter13
+    #[serde(default = "default_scene_grid")]
+    scene_grid: i32,
+    /// Like `--sphere-list`, only for chapter13
+    #[serde(default)]
+    sphere_list: bool,
+    /// Like `--environment`, an image that lights the scene instead of the sky gradient
+    #[serde(default)]
+    environment: Option<PathBuf>,
+    /// Options of `RaytraceParams` by their long name, e.g. `"samples-per-pixel": 64`. `true`
+    /// sets a flag. Missing options have their command line default.
+    #[serde(default)]
+    params: BTreeMap<String, serde_json::Value>,
+}
+
+fn default_scene_seed() -> u64 {
+    DEFAULT_SCENE_SEED
+}
+
+fn default_scene_grid() -> i32 {
+    DEFAULT_SCENE_GRID
+}
+
+impl BatchJob {
+    /// Parses `params` like a command line, with the same defaults and checks
+    fn raytrace_params(&self) -> Result<RaytraceParams, String> {
+        let mut args = vec!["raytracer".to_owned()];
+        for (name, value) in self.params.iter() {
+            match value {
+                serde_json::Value::Bool(true) => args.push(format!("--{}", name)),
+                serde_json::Value::Bool(false) => {}
+                serde_json::Value::Number(number) => args.push(format!("--{}={}", name, number)),
+                serde_json::Value::String(string) => args.push(format!("--{}={}", name, string)),
+                _ => return Err(format!("{} has to be a number, string or bool", name)),
+            }
+        }
+        let mut params = RaytraceParams::try_parse_from(args)
+            .map_err(|e| e.to_string().trim_end().to_owned())?;
+        if params.autosave_interval.is_some() || params.progressive_output.is_some() {
+            params.autosave_path = Some(self.output.clone());
+        }
+        Ok(params)
+    }
+
+    fn run(&self) -> Result<(), String> {
+        let params = self.raytrace_params()?;
+        let (mut world, mut camera_builder) =
+            self.scene
+                .build(self.scene_seed, self.scene_grid, self.sphere_list);
+        if let Some(path) = &self.environment {
+            let environment = EnvironmentMap::load(path)
+                .map_err(|e| format!("Could not load {}: {}", path.display(), e))?;
+            world.set_environment(Arc::new(environment));
+        }
+        world.build_bvh();
+        camera_builder.aspect_ratio(params.aspect_ratio);
+        let camera = camera_builder.build().map_err(|e| e.to_string())?;
+        let progress = ProgressBar::new(1);
+        if is_float_image(&self.output) {
+            let img = render_hdr(&params, &world, &camera, &progress);
+            save_float_image(&img, &self.output).map_err(|e| e.to_string())
+        } else {
+            let img = render(&params, &world, &camera, &progress);
+            img.save(&self.output).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// A JSON list of `BatchJob`s
+fn parse_batch(content: &str) -> Result<Vec<BatchJob>, String> {
+    serde_json::from_str(content).map_err(|e| e.to_string())
+}
+
+/// Renders the jobs one after the other. A job that fails, even with a panic, is reported and
+/// the others continue. Returns whether all jobs succeeded.
+fn run_batch(jobs: &[BatchJob]) -> bool {
+    let mut failed = 0;
+    for (i, job) in jobs.iter().enumerate() {
+        println!(
+            "[{}/{}] {:?} to {}",
+            i + 1,
+            jobs.len(),
+            job.scene,
+            job.output.display()
+        );
+        let start = Instant::now();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job.run()))
+            .unwrap_or_else(|panic| {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                Err(format!("Panicked: {}", message))
+            });
+        match result {
+            Ok(()) => println!("[{}/{}] Done in {:.2?}", i + 1, jobs.len(), start.elapsed()),
+            Err(e) => {
+                failed += 1;
+                println!("[{}/{}] Failed: {}", i + 1, jobs.len(), e);
+            }
+        }
+    }
+    println!("{} of {} jobs succeeded", jobs.len() - failed, jobs.len());
+    failed == 0
+}
+
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    // Dependencies (eframe, winit, ...) are only logged if they warn
+    env_logger::Builder::new()
+        .filter_level(LevelFilter::Warn)
+        .filter_module("raytracer", level)
+        .parse_default_env()
+        .init();
+}
+
+fn main() {
+    // playground::test_image();
+    // playground::test_vectormath();
+    // playground::test_ray_cylinder_math();
+
+    let args = Args::parse();
+    init_logging(args.verbose);
+
+    if let Some(files) = &args.diff {
+        match diff::diff_files(&files[0], &files[1]) {
+            Ok((heatmap, stats)) => {
+                println!("{}", stats);
+                heatmap
+                    .save(&args.output_filename)
+                    .expect("Could not save file.");
+            }
+            Err(e) => {
+                eprintln!("Could not compare the images: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Err(e) = check_float_output(&args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Could not set up the render threads.");
+    }
+
+    if let Some(path) = &args.batch {
+        let jobs = match std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| parse_batch(&content))
+        {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        if !run_batch(&jobs) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // World and Camera
+    let (mut world, mut camera_builder) =
+        args.scene
+            .build(args.scene_seed, args.scene_grid, args.sphere_list);
+    if let Some(path) = &args.environment {
+        match EnvironmentMap::load(path) {
+            Ok(environment) => world.set_environment(Arc::new(environment)),
+            Err(e) => {
+                eprintln!("Could not load {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    info!("Scene: {}", world.stats());
+    if !args.no_bvh {
+        world.build_bvh_with_options(BvhOptions {
+            split: args.bvh_split,
+            ..BvhOptions::default()
+        });
+    } else if args.sphere_culling {
+        world.enable_sphere_culling();
+    }
+    camera_builder.aspect_ratio(args.raytrace_params.aspect_ratio);
+    if let Some(vfov) = args.vfov {
+        camera_builder.vfov(vfov);
+    }
+    if args.frame_scene {
+        camera_builder.frame_scene(&world);
+    }
+    if let Some(blades) = args.aperture_blades {
+        camera_builder.aperture_shape(ApertureShape::Polygon(blades));
+    }
+    camera_builder.projection(args.projection);
+    if let Some(ipd) = args.stereo {
+        camera_builder.stereo(ipd);
+    }
+    let camera = match camera_builder.build() {
+        Ok(camera) => camera,
+        Err(e) => {
+            eprintln!("Invalid camera: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut params = args.raytrace_params;
+    if (params.autosave_interval.is_some() || params.progressive_output.is_some())
+        && !args.gui
+        && args.frames.is_none()
+        && args.focus_bracket.is_none()
+    {
+        params.autosave_path = Some(PathBuf::from(&args.output_filename));
+    }
+
+    if let Some((x, y)) = args.trace_pixel {
+        let (color, path) = trace_ray_debug(&params, &camera, x, y, &world);
+        println!(
+            "Pixel ({}, {}): color ({:.4}, {:.4}, {:.4})",
+            x, y, color.x, color.y, color.z
+        );
+        for (i, segment) in path.iter().enumerate() {
+            println!("{:3}: {}", i, segment);
+        }
+        return;
+    }
+
+    if args.gui {
+        crate::gui::run_gui(params, world, camera_builder, args.output_filename.into());
+    } else if let Some(frames) = args.frames {
+        render_turntable(
+            &params,
+            &world,
+            &camera_builder,
+            frames,
+            args.frame_parallelism,
+            args.stereo_mode,
+            &args.output_filename,
+        )
+        .expect("Could not save file.");
+    } else if let Some(bracket) = args.focus_bracket {
+        render_focus_bracket(
+            &params,
+            &world,
+            &camera_builder,
+            bracket,
+            args.stereo_mode,
+            &args.output_filename,
+        )
+        .expect("Could not save file.");
+    } else {
+        let progress = ProgressBar::new(1);
+        let output = Path::new(&args.output_filename);
+        let hdr_output = is_float_image(output);
+        let float_preview = args.preview.then_some((args.tone_map, args.exposure));
+        let img = if let Some(dir) = &args.tile_store {
+            if camera.stereo_eyes().is_some() {
+                eprintln!("--tile-store does not support stereo renders");
+                std::process::exit(1);
+            }
+            render_to_tile_store(&params, &world, &camera, &progress, dir)
+                .expect("Could not render to the tile store.")
+        } else if args.resume.is_some()
+            || args.save_accumulator.is_some()
+            || args.raw_output.is_some()
+        {
+            if camera.stereo_eyes().is_some() {
+                eprintln!(
+                    "--resume, --save-accumulator and --raw-output do not support stereo renders"
+                );
+                std::process::exit(1);
+            }
+            let adder = match &args.resume {
+                Some(path) => match SamplesAdder::load(path) {
+                    Ok(adder)
+                        if adder.sum_img.dimensions()
+                            == (params.image_width, params.image_height()) =>
+                    {
+                        adder
+                    }
+                    Ok(adder) => {
+                        eprintln!(
+                            "{} has {}x{} pixels, but the render is {}x{}",
+                            path.display(),
+                            adder.sum_img.width(),
+                            adder.sum_img.height(),
+                            params.image_width,
+                            params.image_height()
+                        );
+                        std::process::exit(1);
+                    }
+                    Err(e) => {
+                        eprintln!("Could not resume from {}: {}", path.display(), e);
+                        std::process::exit(1);
+                    }
+                },
+                None => SamplesAdder::new(params.image_width, params.image_height()),
+            };
+            let stop = Arc::new(AtomicBool::new(false));
+            let adder = accumulate(&params, &world, &camera, &progress, stop, adder);
+            if let Some(path) = &args.save_accumulator {
+                adder.save(path).expect("Could not save the accumulator.");
+            }
+            if let Some(path) = &args.raw_output {
+                adder
+                    .save_raw(path)
+                    .expect("Could not save the raw output.");
+                if args.preview {
+                    adder
+                        .normalized(params.dither)
+                        .save(preview_path(path))
+                        .expect("Could not save the preview.");
+                }
+            }
+            if hdr_output {
+                save_float_output(&adder.hdr(), output, float_preview, params.dither)
+                    .expect("Could not save file.");
+                return;
+            }
+            adder.normalized(params.dither)
+        } else if hdr_output {
+            let img = render_hdr(&params, &world, &camera, &progress);
+            save_float_output(&img, output, float_preview, params.dither)
+                .expect("Could not save file.");
+            return;
+        } else if camera.stereo_eyes().is_some() {
+            render_stereo(&params, &world, &camera, &progress, args.stereo_mode)
+        } else {
+            render(&params, &world, &camera, &progress)
+        };
+        img.save(args.output_filename)
+            .expect("Could not save file.");
+    }
+}
+
+/// Only single mono images are rendered as floats, the other modes save 8-bit images. Checked
+/// before rendering instead of failing at the first save.
+fn check_float_output(args: &Args) -> Result<(), String> {
+    if args.gui || !is_float_image(Path::new(&args.output_filename)) {
+        return Ok(());
+    }
+    let unsupported = [
+        ("--frames", args.frames.is_some()),
+        ("--focus-bracket", args.focus_bracket.is_some()),
+        ("--tile-store", args.tile_store.is_some()),
+        ("Stereo renders", args.stereo.is_some()),
+    ];
+    match unsupported.iter().find(|(_, used)| *used) {
+        Some((mode, _)) => Err(format!("{} can not be saved as .hdr or .exr", mode)),
+        None => Ok(()),
+    }
+}
+
+/// Saves `img` as `.hdr` or `.exr` and, with `preview` (tone mapping and exposure), a tone
+/// mapped PNG next to it
+fn save_float_output(
+    img: &Rgb32FImage,
+    path: &Path,
+    preview: Option<(ToneMap, Float)>,
+    dither: bool,
+) -> image::ImageResult<()> {
+    save_float_image(img, path)?;
+    if let Some((tone_map, exposure)) = preview {
+        tone_mapped(img, tone_map, exposure, dither).save(preview_path(path))?;
+    }
+    Ok(())
+}
+
+/// `img` scaled by 2^`exposure`, tone mapped and gamma encoded like the 8-bit output
+fn tone_mapped(img: &Rgb32FImage, tone_map: ToneMap, exposure: Float, dither: bool) -> RgbaImage {
+    let scale = exposure.exp2();
+    RgbaImage::from_fn(img.width(), img.height(), |x, y| {
+        let offset = if dither {
+            bayer_offset(x as usize, y as usize)
+        } else {
+            0.0
+        };
+        // `Float` is f32 with the precision-f32 feature
+        #[allow(clippy::unnecessary_cast)]
+        let [r, g, b] = img
+            .get_pixel(x, y)
+            .0
+            .map(|c| encode_gamma2(tone_map.apply(c as Float * scale), offset));
+        Rgba([r, g, b, 255])
+    })
+}
+
+/// `render.raw` -> `render.preview.png`
+fn preview_path(path: &Path) -> PathBuf {
+    path.with_extension("preview.png")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittables::Triangle;
+    use approx::assert_relative_eq;
+    use std::collections::HashSet;
+
+    /// The former recursive implementation of `ray_color`
+    fn ray_color_recursive(ray: &Ray, world: &World, depth: u32, rng: &mut RenderRng) -> Color {
+        if depth == 0 {
+            return Color::zeros();
+        }
+        if let Some(hitrecord) = world.hit(ray, 0.001, 1000.) {
+            if let Some((attenuation, scatterray)) =
+                hitrecord.material.scatter(ray, &hitrecord, rng)
+            {
+                return attenuation.component_mul(&ray_color_recursive(
+                    &scatterray,
+                    world,
+                    depth - 1,
+                    rng,
+                ));
+            } else {
+                return Color::zeros();
+            }
+        }
+        background(world, &Sky::default(), ray)
+    }
+
+    #[test]
+    fn test_iterative_ray_color_matches_recursive() {
+        let (mut world, mut camera_builder) = scene_chapter13(1);
+        world.build_bvh();
+        let camera = camera_builder.aspect_ratio(1.5).build().unwrap();
+        let mut rng = RenderRng::seed_from_u64(3);
+        for i in 0..500 {
+            let ray = camera.get_ray(rng.gen(), rng.gen(), &mut rng).unwrap();
+            let depth = i % 12;
+            let seed = rng.gen();
+            let iterative = ray_color(
+                &ray,
+                &world,
+                BounceLimits::new(depth),
+                None,
+                &mut RenderRng::seed_from_u64(seed),
+            );
+            let recursive =
+                ray_color_recursive(&ray, &world, depth, &mut RenderRng::seed_from_u64(seed));
+            assert!(
+                (iterative - recursive).amax() < 1e-5,
+                "{} != {}",
+                iterative,
+                recursive
+            );
+        }
+    }
+
+    #[test]
+    fn test_crop_matches_full_render() {
+        let (mut world, mut camera_builder) = scene_tutorial(0);
+        world.build_bvh();
+        let camera = camera_builder.aspect_ratio(2.0).build().unwrap();
+        let args = ["raytracer", "-i", "40", "-a", "2:1", "-s", "1"];
+        let full_params = RaytraceParams::parse_from(args);
+        let crop_params = RaytraceParams::parse_from(args.iter().chain(&["--crop", "5,3,20,10"]));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (full, _) = render_sample(&full_params, &world, &camera, 7, Arc::clone(&stop));
+        let (cropped, _) = render_sample(&crop_params, &world, &camera, 7, stop);
+
+        assert_eq!(full.dimensions(), cropped.dimensions());
+        for (x, y, pixel) in cropped.enumerate_pixels() {
+            if (5..25).contains(&x) && (3..13).contains(&y) {
+                assert_eq!(pixel, full.get_pixel(x, y));
+            } else {
+                assert_eq!(*pixel, Rgba([0.0; 4]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_tile_grid_covers_the_image_once() {
+        for (width, height, size) in [(40, 20, 32), (40, 20, 7), (5, 3, 1), (10, 10, 100)] {
+            let mut covered = vec![0; (width * height) as usize];
+            for tile in Tile::grid(width, height, size) {
+                assert!(tile.width >= 1 && tile.width <= size && tile.height <= size);
+                for y in tile.y..tile.y + tile.height {
+                    for x in tile.x..tile.x + tile.width {
+                        covered[(y * width + x) as usize] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&c| c == 1));
+        }
+    }
+
+    #[test]
+    fn test_tile_size_does_not_change_the_image() {
+        let (mut world, mut camera_builder) = scene_tutorial(0);
+        world.build_bvh();
+        let camera = camera_builder.aspect_ratio(2.0).build().unwrap();
+        let render = |tile_size: &str, crop: &[&str]| {
+            let args = [
+                "raytracer",
+                "-i",
+                "40",
+                "-a",
+                "2:1",
+                "-s",
+                "1",
+                "--tile-size",
+                tile_size,
+            ];
+            let params = RaytraceParams::parse_from(args.iter().chain(crop));
+            render_sample(
+                &params,
+                &world,
+                &camera,
+                3,
+                Arc::new(AtomicBool::new(false)),
+            )
+        };
+        let (reference, reference_counters) = render("32", &[]);
+        for tile_size in ["1", "7", "1000"] {
+            let (img, counters) = render(tile_size, &[]);
+            assert_eq!(img, reference);
+            assert_eq!(counters, reference_counters);
+        }
+        let crop = ["--crop", "5,3,20,10"];
+        assert_eq!(render("7", &crop).0, render("32", &crop).0);
+        // Samples splat across the tile borders
+        let tent = ["--filter", "tent"];
+        assert_eq!(render("7", &tent).0, render("32", &tent).0);
+    }
+
+    #[test]
+    fn test_splat_weights() {
+        // 4×3 pixels, a single sample in pixel (1, 1), left of its center and above
+        let mut samples = vec![PixelSample::default(); 12];
+        samples[5] = PixelSample {
+            color: Color::new(1.0, 2.0, 4.0),
+            offset: (0.25, 0.75),
+            rendered: true,
+        };
+        let area = Tile {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 3,
+        };
+        let weights = |img: &FloatRgbaImage| img.pixels().map(|p| p[3]).collect::<Vec<_>>();
+
+        let boxed = splat(&samples, &area, &area, None, ReconstructionFilter::Box);
+        assert_eq!(*boxed.get_pixel(1, 1), Rgba([1.0, 2.0, 4.0, 1.0]));
+        assert_eq!(weights(&boxed).iter().sum::<Float>(), 1.0);
+
+        let tent = splat(&samples, &area, &area, None, ReconstructionFilter::Tent);
+        #[rustfmt::skip]
+        let expected = [
+            0.0625, 0.1875, 0.0, 0.0,
+            0.1875, 0.5625, 0.0, 0.0,
+            0.0, 0.0, 0.0, 0.0,
+        ];
+        assert_eq!(weights(&tent), expected);
+        assert_eq!(*tent.get_pixel(0, 0), Rgba([0.0625, 0.125, 0.25, 0.0625]));
+        assert_eq!(
+            weighted_average(tent.get_pixel(1, 0)),
+            Rgba([1.0, 2.0, 4.0, 1.0])
+        );
+        assert_eq!(weighted_average(tent.get_pixel(3, 2)), Rgba([0.0; 4]));
+
+        // Only the pixels of another tile, the sample still reaches its left column
+        let right = Tile {
+            x: 2,
+            y: 0,
+            width: 2,
+            height: 3,
+        };
+        let gaussian = splat(
+            &samples,
+            &area,
+            &right,
+            None,
+            ReconstructionFilter::Gaussian,
+        );
+        assert_eq!(gaussian.dimensions(), (2, 3));
+        assert!(gaussian.get_pixel(0, 1)[3] > 0.0);
+        assert_eq!(gaussian.get_pixel(1, 1)[3], 0.0);
+
+        // Nothing is spread out of the crop region
+        let crop = Crop {
+            x: 1,
+            y: 0,
+            width: 3,
+            height: 3,
+        };
+        let cropped = splat(
+            &samples,
+            &area,
+            &area,
+            Some(crop),
+            ReconstructionFilter::Tent,
+        );
+        assert_eq!(cropped.get_pixel(0, 1)[3], 0.0);
+        assert_eq!(cropped.get_pixel(1, 1)[3], 0.5625);
+    }
+
+    #[test]
+    fn test_tile_store_matches_render() {
+        let (mut world, mut camera_builder) = scene_tutorial(0);
+        world.build_bvh();
+        let camera = camera_builder.aspect_ratio(2.0).build().unwrap();
+        let dir = std::env::temp_dir().join("raytracer_test_tile_store");
+        for extra in [
+            &[][..],
+            &["--ssaa", "2", "--crop", "5,3,20,10"],
+            &["--filter", "gaussian", "--crop", "5,3,20,10"],
+        ] {
+            let args = [
+                "raytracer",
+                "-i",
+                "30",
+                "-a",
+                "2:1",
+                "-s",
+                "3",
+                "--dither",
+                "--tile-size",
+                "8",
+            ];
+            let params = RaytraceParams::parse_from(args.iter().chain(extra));
+            let hidden = ProgressBar::hidden();
+            let expected = render(&params, &world, &camera, &hidden);
+            let tiled = render_to_tile_store(&params, &world, &camera, &hidden, &dir).unwrap();
+            assert_eq!(tiled, expected);
+        }
+        // 4×2 tiles of 8×8 pixels for 30×15
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 8);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_render_hdr_keeps_values_above_one() {
+        let mut world = World::new();
+        let light = DiffuseLight::new(Color::new(1.0, 0.5, 0.25), 4.0);
+        world.add(Sphere::new(0.0, 0.0, 0.0, 100.0, &light));
+        let (_, mut camera_builder) = scene_tutorial(0);
+        let camera = camera_builder.aspect_ratio(2.0).build().unwrap();
+        let params = RaytraceParams::parse_from(["raytracer", "-i", "8", "-a", "2:1", "-s", "2"]);
+        let hidden = ProgressBar::hidden();
+
+        let img = render_hdr(&params, &world, &camera, &hidden);
+        assert_eq!(img.dimensions(), (8, 4));
+        assert!(img.pixels().all(|p| p.0 == [4.0, 2.0, 1.0]));
+        // The display path clamps the same pixels
+        let ldr = render(&params, &world, &camera, &hidden);
+        assert!(ldr.pixels().all(|p| p.0 == [255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn test_sun_light_casts_hard_shadows() {
+        let albedo = Color::new(0.5, 0.5, 0.5);
+        let material = Lambertian::new(albedo);
+        let mut world = World::new();
+        world.add(Sphere::new(0.0, -1000.0, 0.0, 1000.0, &material));
+        world.add(Sphere::new(0.0, 2.0, 0.0, 1.0, &material));
+        let irradiance = Color::new(2.0, 2.0, 2.0);
+        world.add_light(Arc::new(SunLight::new(
+            Vec3::new(0.0, -1.0, 0.0),
+            irradiance,
+        )));
+
+        // With a single bounce only the direct sun light reaches the camera
+        let color = |x: Float| {
+            let ray = Ray::new(Point3::new(x, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+            ray_color(
+                &ray,
+                &world,
+                BounceLimits::new(1),
+                None,
+                &mut RenderRng::seed_from_u64(1),
+            )
+        };
+        assert!((color(3.0) - albedo.component_mul(&irradiance) / consts::PI).amax() < 1e-4);
+        // Hits the top of the sphere, which is lit too
+        assert!(color(0.0).amax() > 0.0);
+
+        // Below the sphere
+        let ray = Ray::new(Point3::new(0.5, 0.5, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let shadowed = ray_color(
+            &ray,
+            &world,
+            BounceLimits::new(1),
+            None,
+            &mut RenderRng::seed_from_u64(1),
+        );
+        assert_eq!(shadowed, Color::zeros());
+    }
+
+    #[test]
+    fn test_falloff_scene_follows_inverse_square_law() {
+        let (world, _) = scene_falloff(0);
+        let mut rng = RenderRng::seed_from_u64(1);
+        // Average floor brightness at `x`, lit only by the sphere found by the single bounce
+        let mut floor = |x: Float| {
+            let ray = Ray::new(Point3::new(x, 0.1, 0.0), Vec3::new(0.0, -1.0, 0.0));
+            let samples = 100_000;
+            let sum: Color = (0..samples)
+                .map(|_| ray_color(&ray, &world, BounceLimits::new(2), None, &mut rng))
+                .sum();
+            sum.x / samples as Float
+        };
+
+        // Directly below the light (d = 2, cos θ = 1) and at a distance of d = 4 (cos θ = 0.5)
+        let below = floor(0.0);
+        let far = floor(2.0 * (3.0 as Float).sqrt());
+        // albedo * intensity * (radius / d)² * cos θ
+        assert_relative_eq!(below, 0.5 * 16.0 * 0.0625, max_relative = 0.1);
+        assert_relative_eq!(below / far, 8.0, max_relative = 0.15);
+    }
+
+    #[test]
+    fn test_background_intensity() {
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let mut world = World::new();
+        world.add(Sphere::new(0.0, 0.0, 0.0, 1.0, &material));
+        let color = |ray: &Ray, background_intensity| {
+            let settings = PathSettings {
+                background_intensity,
+                ..PathSettings::new(BounceLimits::new(10))
+            };
+            trace_path(
+                ray,
+                &world,
+                &settings,
+                &mut RenderRng::seed_from_u64(1),
+                None,
+            )
+        };
+
+        // A miss sees the scaled sky, and so does a path that escapes after bouncing
+        let miss = Ray::new(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let sky = background(&world, &Sky::default(), &miss);
+        assert_relative_eq!(color(&miss, 0.25), 0.25 * sky);
+        let hit = Ray::new(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert_relative_eq!(color(&hit, 0.25), 0.25 * color(&hit, 1.0), epsilon = 1e-9);
+        assert_eq!(color(&hit, 0.0), Color::zeros());
+    }
+
+    #[test]
+    fn test_sky_colors() {
+        let params = RaytraceParams::parse_from(["raytracer"]);
+        assert_eq!(params.path_settings().sky, Sky::default());
+        let params = RaytraceParams::parse_from([
+            "raytracer",
+            "--sky-top",
+            "0.2, 0.1,0.4",
+            "--sky-bottom",
+            "1,0.5,0",
+        ]);
+        let settings = params.path_settings();
+        let world = World::new();
+        let color = |dir: Vec3| {
+            let ray = Ray::new(Point3::zeros(), dir);
+            trace_path(
+                &ray,
+                &world,
+                &settings,
+                &mut RenderRng::seed_from_u64(1),
+                None,
+            )
+        };
+        assert_relative_eq!(color(Vec3::new(0.0, 1.0, 0.0)), Color::new(0.2, 0.1, 0.4));
+        assert_relative_eq!(color(Vec3::new(0.0, -2.0, 0.0)), Color::new(1.0, 0.5, 0.0));
+        assert_relative_eq!(color(Vec3::new(1.0, 0.0, 0.0)), Color::new(0.6, 0.3, 0.2));
+
+        for invalid in ["1,1", "1,1,1,1", "1,-1,1", "red"] {
+            assert!(parse_color(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_light_samples_are_averaged() {
+        let albedo = Color::new(0.5, 0.5, 0.5);
+        let material = Lambertian::new(albedo);
+        let mut world = World::new();
+        world.add(Sphere::new(0.0, -1000.0, 0.0, 1000.0, &material));
+        world.add_light(Arc::new(SunLight::new(
+            Vec3::new(-1.0, -2.0, 0.0),
+            Color::new(2.0, 2.0, 2.0),
+        )));
+
+        let ray = Ray::new(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let color = |light_samples| {
+            let before = RayCounters::current();
+            let settings = PathSettings {
+                light_samples,
+                ..PathSettings::new(BounceLimits::new(1))
+            };
+            let color = trace_path(
+                &ray,
+                &world,
+                &settings,
+                &mut RenderRng::seed_from_u64(1),
+                None,
+            );
+            (color, RayCounters::current().since(before).rays)
+        };
+        let (one, one_rays) = color(1);
+        let (four, four_rays) = color(4);
+        assert_relative_eq!(one, four, epsilon = 1e-6);
+        // The camera ray and one shadow ray per light sample
+        assert_eq!((one_rays, four_rays), (2, 5));
+    }
+
+    #[test]
+    fn test_bounce_limits_per_kind() {
+        // Inside a closed sphere every path bounces until a limit is reached
+        let path_length = |material: &Arc<dyn Material>, args: &[&str]| {
+            let mut world = World::new();
+            world.add(Sphere::new(0.0, 0.0, 0.0, 10.0, material));
+            let params = RaytraceParams::parse_from(["raytracer"].iter().chain(args));
+            let ray = Ray::new(Point3::zeros(), Vec3::new(0.0, 0.0, -1.0));
+            let mut path = vec![];
+            let limits = params.bounce_limits();
+            let mut rng = RenderRng::seed_from_u64(1);
+            let settings = PathSettings::new(limits);
+            trace_path(&ray, &world, &settings, &mut rng, Some(&mut path));
+            path.len()
+        };
+        let diffuse = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let mirror = Metal::new(Color::new(0.9, 0.9, 0.9), 0.0);
+
+        // The last surface is still hit, but doesn't scatter
+        assert_eq!(path_length(&diffuse, &["--max-diffuse-depth", "3"]), 4);
+        assert_eq!(path_length(&mirror, &["--max-diffuse-depth", "3"]), 50);
+        assert_eq!(path_length(&mirror, &["--max-glossy-depth", "7"]), 8);
+        assert_eq!(
+            path_length(&mirror, &["-m", "5", "--max-glossy-depth", "7"]),
+            5
+        );
+    }
+
+    #[test]
+    fn test_trace_ray_debug() {
+        let (mut world, mut camera_builder) = scene_tutorial(0);
+        world.build_bvh();
+        let camera = camera_builder.aspect_ratio(2.0).build().unwrap();
+        let params = RaytraceParams::parse_from(["raytracer", "-i", "40", "-a", "2:1"]);
+
+        // The center pixel looks at the blue diffuse sphere
+        let (color, path) = trace_ray_debug(&params, &camera, 20, 10, &world);
+        let first_hit = path[0].hit.as_ref().unwrap();
+        assert!((first_hit.p - Point3::new(0.0, 0.0, -0.5)).magnitude() < 0.2);
+        assert!(first_hit.front_face);
+        assert!(path.len() <= params.max_depth as usize);
+        for pair in path.windows(2) {
+            assert_eq!(pair[0].scatter.unwrap().1.dir, pair[1].ray.dir);
+        }
+        assert!(path.last().unwrap().scatter.is_none());
+
+        // Same result as the renderer with the same random numbers
+        let mut rng = RenderRng::seed_from_u64(params.seed);
+        let ray = camera.get_ray(20.5 / 39.0, 9.5 / 19.0, &mut rng).unwrap();
+        assert_eq!(
+            color,
+            ray_color(&ray, &world, params.bounce_limits(), None, &mut rng)
+        );
+        assert_eq!(path[0].ray.dir, ray.dir);
+    }
+
+    #[test]
+    fn test_preview_path() {
+        assert_eq!(
+            preview_path(Path::new("out/render.raw")),
+            Path::new("out/render.preview.png")
+        );
+        assert_eq!(
+            preview_path(Path::new("render")),
+            Path::new("render.preview.png")
+        );
+    }
+
+    #[test]
+    fn test_float_output_preview() {
+        let dir = std::env::temp_dir().join("raytracer_test_float_output_preview");
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("render.exr");
+        let args = Args::parse_from([
+            "raytracer",
+            "-o",
+            output.to_str().unwrap(),
+            "--preview",
+            "--tone-map",
+            "reinhard",
+            "--exposure",
+            "1",
+        ]);
+        assert!(args.preview);
+        let preview = Some((args.tone_map, args.exposure));
+        let img = Rgb32FImage::from_fn(2, 1, |x, _| Rgb([0.5 * x as f32, 0.25, 4.0]));
+        save_float_output(&img, &output, preview, false).unwrap();
+
+        assert!(image::open(&output).is_ok());
+        let preview = image::open(dir.join("render.preview.png"))
+            .unwrap()
+            .into_rgba8();
+        // Doubled by the exposure, then 0 -> 0, 0.5 -> 1/3, 1 -> 1/2 and 8 -> 8/9
+        assert_eq!(*preview.get_pixel(0, 0), Rgba([0, 147, 240, 255]));
+        assert_eq!(*preview.get_pixel(1, 0), Rgba([180, 147, 240, 255]));
+
+        // Without --preview only the float image is written
+        std::fs::remove_file(dir.join("render.preview.png")).unwrap();
+        save_float_output(&img, &output, None, false).unwrap();
+        assert!(!dir.join("render.preview.png").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_float_output() {
+        let check = |output: &str, extra: &[&str]| {
+            let args = Args::parse_from(["raytracer", "-o", output].iter().chain(extra));
+            check_float_output(&args)
+        };
+        assert_eq!(check("x.exr", &[]), Ok(()));
+        assert_eq!(
+            check("x.exr", &["--frames", "2"]),
+            Err("--frames can not be saved as .hdr or .exr".to_owned())
+        );
+        assert!(check("x.hdr", &["--focus-bracket", "2,4,2"]).is_err());
+        assert!(check("x.exr", &["--tile-store", "tiles"]).is_err());
+        assert!(check("x.exr", &["--stereo", "0.1"]).is_err());
+        assert_eq!(check("x.png", &["--frames", "2"]), Ok(()));
+    }
+
+    #[test]
+    fn test_raw_output_layout() {
+        let mut adder = SamplesAdder::new(2, 1);
+        let step =
+            FloatRgbaImage::from_raw(2, 1, vec![0.5, 1.0, 2.0, 1.0, 0.0, 0.25, 4.0, 1.0]).unwrap();
+        adder.add_image(&step);
+        adder.add_image(&step);
+        let path = std::env::temp_dir().join(format!("raytracer_raw_{}.raw", std::process::id()));
+        adder.save_raw(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&bytes[..8], RAW_MAGIC);
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        assert_eq!((u32_at(8), u32_at(12), u32_at(16)), (2, 1, 4));
+        let values: Vec<f32> = bytes[20..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, [0.5, 1.0, 2.0, 1.0, 0.0, 0.25, 4.0, 1.0]);
+    }
+
+    #[test]
+    fn test_resumed_render_matches_single_render() {
+        let (mut world, mut camera_builder) = scene_tutorial(0);
+        world.build_bvh();
+        let camera = camera_builder.aspect_ratio(2.0).build().unwrap();
+        let params = |spp: &str| {
+            RaytraceParams::parse_from(["raytracer", "-i", "16", "-a", "2:1", "-s", spp])
+        };
+        let render = |params: &RaytraceParams, adder: SamplesAdder| {
+            let stop = Arc::new(AtomicBool::new(false));
+            accumulate(params, &world, &camera, &ProgressBar::hidden(), stop, adder)
+        };
+
+        let single = render(&params("4"), SamplesAdder::new(16, 8));
+
+        let first = render(&params("2"), SamplesAdder::new(16, 8));
+        let path = std::env::temp_dir().join(format!("raytracer-test-{}.acc", std::process::id()));
+        first.save(&path).unwrap();
+        let loaded = SamplesAdder::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.num_samples, 2);
+        assert_eq!(loaded.sum_img, first.sum_img);
+        let resumed = render(&params("2"), loaded);
+
+        assert_eq!(resumed.num_samples, 4);
+        for (a, b) in resumed.sum_img.iter().zip(single.sum_img.iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_filters_soften_environment_disk() {
+        // Bright disk with a radius of 3° straight ahead (-z), black elsewhere
+        let (width, height) = (1024, 512);
+        let disk = image::Rgb32FImage::from_fn(width, height, |x, y| {
+            let phi = ((x as Float + 0.5) / width as Float - 0.5) * 2.0 * consts::PI;
+            let theta = (y as Float + 0.5) / height as Float * consts::PI;
+            let cos_angle = theta.sin() * phi.cos();
+            let value = if cos_angle > (3.0 as Float).to_radians().cos() {
+                1.0
+            } else {
+                0.0
+            };
+            image::Rgb([value, value, value])
+        });
+        let mut world = World::new();
+        world.set_environment(Arc::new(EnvironmentMap::from_image(&disk)));
+        // 0.5° per pixel, the disk is 12 pixels wide
+        let camera = CameraBuilder::new()
+            .lookfrom(Point3::zeros())
+            .lookat(Point3::new(0.0, 0.0, -1.0))
+            .vup(Vec3::new(0.0, 1.0, 0.0))
+            .vfov(15.5)
+            .aspect_ratio(1.0)
+            .aperture(0.0)
+            .focus_dist(1.0)
+            .build()
+            .unwrap();
+        let render = |filter: &str| {
+            let params = RaytraceParams::parse_from([
+                "raytracer",
+                "-i",
+                "32",
+                "-a",
+                "1:1",
+                "-s",
+                "64",
+                "--filter",
+                filter,
+            ]);
+            let stop = Arc::new(AtomicBool::new(false));
+            let adder = SamplesAdder::new(32, 32);
+            let adder = accumulate(
+                &params,
+                &world,
+                &camera,
+                &ProgressBar::hidden(),
+                stop,
+                adder,
+            );
+            adder.averaged().pixels().map(|p| p[0]).collect::<Vec<_>>()
+        };
+        let boxed = render("box");
+        let total = |img: &[Float]| img.iter().sum::<Float>();
+        let lit = |img: &[Float]| img.iter().filter(|v| **v > 0.0).count();
+        let partial = |img: &[Float]| img.iter().filter(|v| **v > 0.0 && **v < 1.0).count();
+        assert_eq!(boxed[0], 0.0);
+
+        let tent = render("tent");
+        let gaussian = render("gaussian");
+        for soft in [&tent, &gaussian] {
+            // The same amount of light, but spread over more pixels
+            assert!((total(&boxed) - total(soft)).abs() < 0.05 * total(&boxed));
+            assert!(lit(soft) > lit(&boxed));
+            assert!(partial(soft) > partial(&boxed) * 3 / 2);
+            // Far from the disk stays black
+            assert_eq!(soft[0], 0.0);
+        }
+        // The wider support of the Gaussian softens the edge even more
+        assert!(lit(&gaussian) > lit(&tent));
+    }
+
+    #[test]
+    fn test_light_intensity_scales_illumination() {
+        let floor_color = |intensity: Float| {
+            let mut world = World::new();
+            let black = image::Rgb32FImage::new(1, 1);
+            world.set_environment(Arc::new(EnvironmentMap::from_image(&black)));
+            let dim = Lambertian::new(Color::new(0.3, 0.3, 0.3));
+            world.add(Sphere::new(0.0, -1000.0, 0.0, 1000.0, &dim));
+            let light = DiffuseLight::new(Color::new(1.0, 0.9, 0.8), intensity);
+            world.add(Sphere::new(0.0, 1.5, 0.0, 1.0, &light));
+            let mut rng = RenderRng::seed_from_u64(4);
+            let ray = Ray::new(Point3::new(0.0, 1.0, 5.0), Vec3::new(0.0, -1.0, -5.0));
+            let n = 20000;
+            let sum: Color = (0..n)
+                .map(|_| ray_color(&ray, &world, BounceLimits::new(10), None, &mut rng))
+                .sum();
+            sum / n as Float
+        };
+        // Only the light brightens the scene under a black sky, so the floor follows it linearly
+        // and goes above 1, the tone mapper decides later what that looks like
+        let normal = floor_color(1.0);
+        let bright = floor_color(15.0);
+        assert!(bright.x > 1.0);
+        assert_relative_eq!(bright, 15.0 * normal, max_relative = 1e-4);
+    }
+
+    #[test]
+    fn test_guard_sample() {
+        assert_eq!(guard_sample(&Color::new(0.5, 2.0, 0.1), None), None);
+        assert_eq!(guard_sample(&Color::new(0.5, 2.0, 0.1), Some(5.0)), None);
+        for broken in [Float::NAN, Float::INFINITY, Float::NEG_INFINITY] {
+            let c = Color::new(0.5, broken, 0.1);
+            assert_eq!(guard_sample(&c, None), Some(Color::zeros()));
+            assert_eq!(guard_sample(&c, Some(5.0)), Some(Color::zeros()));
+        }
+        // Keeps the hue
+        let clamped = guard_sample(&Color::new(30.0, 20.0, 10.0), Some(2.0)).unwrap();
+        assert_relative_eq!(luminance(&clamped), 2.0, max_relative = 1e-6);
+        assert_relative_eq!(clamped * 10.0 / clamped.z, Color::new(30.0, 20.0, 10.0));
+    }
+
+    #[test]
+    fn test_clamp_must_be_positive() {
+        let clamp = |value: &str| {
+            RaytraceParams::try_parse_from(["raytracer", "--clamp", value]).map(|p| p.clamp)
+        };
+        assert_eq!(clamp("4").unwrap(), Some(4.0));
+        assert_eq!(clamp("0.5").unwrap(), Some(0.5));
+        for invalid in ["0", "-1", "inf", "NaN", "bright"] {
+            assert!(clamp(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_nan_samples_keep_the_image_finite() {
+        let mut world = World::new();
+        let black = image::Rgb32FImage::new(1, 1);
+        world.set_environment(Arc::new(EnvironmentMap::from_image(&black)));
+        let broken = DiffuseLight::new(Color::new(1.0, Float::NAN, 1.0), 1.0);
+        world.add(Sphere::new(0.0, 0.0, -1.0, 0.5, &broken));
+        let bright = DiffuseLight::new(Color::new(1.0, 1.0, 1.0), 100.0);
+        world.add(Sphere::new(1.0, 0.0, -1.0, 0.5, &bright));
+        let (_, mut camera_builder) = scene_tutorial(0);
+        let camera = camera_builder.aspect_ratio(2.0).build().unwrap();
+        let params =
+            RaytraceParams::parse_from(["raytracer", "-i", "16", "-a", "2:1", "--clamp", "4"]);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut adder = SamplesAdder::new(16, 8);
+        let mut clamped = 0;
+        for sample_index in 0..4 {
+            let (img, counters) =
+                render_sample(&params, &world, &camera, sample_index, stop.clone());
+            clamped += counters.clamped_samples;
+            for p in img.pixels() {
+                let c = Color::new(p[0], p[1], p[2]);
+                assert!(c.iter().all(|v| v.is_finite()), "{}", c);
+                assert!(luminance(&c) <= 4.0 + 1e-4, "{}", c);
+            }
+            adder.add_image(&img);
+        }
+        assert!(clamped > 0);
+        assert!(adder.sum_img.iter().all(|v| v.is_finite()));
+        // The light still shows, only dimmed
+        assert!(adder.averaged().pixels().any(|p| p[0] > 1.0));
+    }
+
+    #[test]
+    fn test_focus_bracket() {
+        let bracket = parse_focus_bracket("2, 4,5").unwrap();
+        let distances: Vec<Float> = bracket.distances().collect();
+        assert_eq!(distances, [2.0, 2.5, 3.0, 3.5, 4.0]);
+        // Descending and a single step work too
+        let distances: Vec<Float> = parse_focus_bracket("3,1,3").unwrap().distances().collect();
+        assert_eq!(distances, [3.0, 2.0, 1.0]);
+        let distances: Vec<Float> = parse_focus_bracket("3,1,1").unwrap().distances().collect();
+        assert_eq!(distances, [3.0]);
+
+        for invalid in ["1,2", "1,2,0", "0,2,3", "1,2,3,4", "a,2,3"] {
+            assert!(parse_focus_bracket(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_frame_filename() {
+        assert_eq!(
+            frame_filename("out/render.png", 7),
+            PathBuf::from("out/render_0007.png")
+        );
+        assert_eq!(frame_filename("render", 12), PathBuf::from("render_0012"));
+    }
+
+    #[test]
+    fn test_wireframe_integrator() {
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let mut world = World::new();
+        world.add(Sphere::new(0.0, 0.0, 0.0, 1.0, &material));
+        let origin = Point3::new(0.0, 0.0, 5.0);
+        let towards = |p: Point3| Ray::new(origin, p - origin);
+
+        // The front edges of the box around the sphere are visible
+        let front_corner = Point3::new(1.0, 1.0, 1.0);
+        assert_eq!(
+            wireframe(&towards(front_corner), &world, 0.01),
+            Color::repeat(1.0)
+        );
+        let front_edge = Point3::new(0.3, -1.0, 1.0);
+        assert_eq!(
+            wireframe(&towards(front_edge), &world, 0.01),
+            Color::repeat(1.0)
+        );
+        // Not next to them, or when the line is thinner than the distance to it
+        let beside = Point3::new(0.3, -0.9, 1.0);
+        assert_eq!(wireframe(&towards(beside), &world, 0.01), Color::zeros());
+        assert_eq!(
+            wireframe(&towards(front_edge * 0.99), &world, 1e-4),
+            Color::zeros()
+        );
+
+        // A back edge is dimmed once a blocker is in front of it
+        let back_corner = Point3::new(1.0, 1.0, -1.0);
+        assert_eq!(
+            wireframe(&towards(back_corner), &world, 0.01),
+            Color::repeat(1.0)
+        );
+        world.add(Sphere::new(0.5, 0.5, 2.0, 0.3, &material));
+        assert_eq!(
+            wireframe(&towards(back_corner), &world, 0.01),
+            Color::repeat(0.3)
+        );
+
+        // Triangles show their own edges instead of the box
+        let mut world = World::new();
+        let (v0, v1, v2) = (
+            Point3::new(-1.0, -1.0, 0.0),
+            Point3::new(1.0, -1.0, 0.0),
+            Point3::new(-1.0, 1.0, 0.0),
+        );
+        world.add(Triangle::new(v0, v1, v2, &material));
+        let hypotenuse = Point3::new(0.0, 0.0, 0.0);
+        assert_eq!(
+            wireframe(&towards(hypotenuse), &world, 0.01),
+            Color::repeat(1.0)
+        );
+        let box_corner = Point3::new(1.0, 1.0, 0.0);
+        assert_eq!(
+            wireframe(&towards(box_corner), &world, 0.01),
+            Color::zeros()
+        );
+    }
+
+    #[test]
+    fn test_uv_integrator() {
+        let mut world = World::new();
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        world.add(Sphere::new(0.0, 0.0, 0.0, 1.0, &material));
+        let params = RaytraceParams::parse_from(["raytracer", "--integrator", "uv"]);
+        let camera = scene_tutorial(0).1.aspect_ratio(1.5).build().unwrap();
+        let mut rng = RenderRng::seed_from_u64(1);
+
+        // Hit from +x, where the sphere's UV mapping puts u = 0.5 and the equator at v = 0.5
+        let ray = Ray::new(Point3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let color = integrate(&params, &camera, &ray, &world, &mut rng);
+        assert_relative_eq!(color, Color::new(0.5, 0.5, 0.0), epsilon = 1e-6);
+        // From the top: v = 1
+        let ray = Ray::new(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert_relative_eq!(
+            integrate(&params, &camera, &ray, &world, &mut rng).y,
+            1.0,
+            epsilon = 1e-6
+        );
+        // Misses are black instead of the sky
+        let ray = Ray::new(Point3::new(5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(
+            integrate(&params, &camera, &ray, &world, &mut rng),
+            Color::zeros()
+        );
+    }
+
+    #[test]
+    fn test_poisson_disk() {
+        let mut rng = RenderRng::seed_from_u64(5);
+        let points = poisson_disk(10.0, 6.0, 0.5, &mut rng);
+        for (i, a) in points.iter().enumerate() {
+            assert!((0.0..10.0).contains(&a.0) && (0.0..6.0).contains(&a.1));
+            for b in &points[i + 1..] {
+                assert!((a.0 - b.0).hypot(a.1 - b.1) >= 0.5);
+            }
+        }
+        // Densely filled: a hexagonal packing would hold about 277 points
+        assert!(points.len() > 150, "only {} points", points.len());
+    }
+
+    #[test]
+    fn test_render_with_dimensions() {
+        let (world, camera_builder) = scene_tutorial(0);
+        let params =
+            RaytraceParams::parse_from(["raytracer", "-s", "1", "-i", "55", "-a", "55:27"]);
+        // 55 / (55 / 27) is slightly below 27 in floating point
+        assert_eq!(params.image_height(), 26);
+        let hidden = ProgressBar::hidden();
+        let img = render_with_dimensions(&params, &world, &camera_builder, 55, 27, &hidden);
+        assert_eq!(img.dimensions(), (55, 27));
+        let img = render_with_dimensions(&params, &world, &camera_builder, 7, 7, &hidden);
+        assert_eq!(img.dimensions(), (7, 7));
+    }
+
+    #[test]
+    fn test_progressive_output_arg() {
+        let parse = |args: &[&str]| {
+            RaytraceParams::try_parse_from(["raytracer"].iter().chain(args))
+                .map(|params| params.progressive_output)
+        };
+        assert_eq!(parse(&[]).unwrap(), None);
+        assert_eq!(parse(&["--progressive-output"]).unwrap(), Some(1));
+        assert_eq!(parse(&["--progressive-output", "8"]).unwrap(), Some(8));
+        assert!(parse(&["--progressive-output", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_no_jitter() {
+        let (world, mut camera_builder) = scene_tutorial(0);
+        let camera = camera_builder.aspect_ratio(2.0).build().unwrap();
+        let params = RaytraceParams::parse_from([
+            "raytracer",
+            "-i",
+            "16",
+            "-a",
+            "2:1",
+            "-s",
+            "10",
+            "--integrator",
+            "uv",
+        ]);
+        let stop = Arc::new(AtomicBool::new(false));
+        let (a, _) = render_sample(&params, &world, &camera, 0, stop.clone());
+        let (b, _) = render_sample(&params, &world, &camera, 1, stop.clone());
+        assert_ne!(a, b);
+
+        let params = RaytraceParams {
+            no_jitter: true,
+            ..params
+        };
+        assert_eq!(params.sample_count(), 1);
+        let (a, _) = render_sample(&params, &world, &camera, 0, stop.clone());
+        let (b, _) = render_sample(&params, &world, &camera, 1, stop);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_sample_events() {
+        struct Recorder(Mutex<Vec<SampleEvent>>);
+        impl ProgressBarWrapper for Recorder {
+            fn set_length(&self, _len: u64) {}
+            fn inc(&self, _delta: u64, _get_immediate_image: Option<&dyn Fn() -> ColorImage>) {}
+            fn finish(&self) {}
+            fn on_sample_complete(&self, event: &SampleEvent) {
+                self.0.lock().unwrap().push(*event);
+            }
+        }
+
+        let (world, mut camera_builder) = scene_tutorial(0);
+        let camera = camera_builder.aspect_ratio(2.0).build().unwrap();
+        let params = RaytraceParams::parse_from(["raytracer", "-i", "8", "-a", "2:1", "-s", "3"]);
+        let recorder = Recorder(Mutex::new(vec![]));
+        let stop = Arc::new(AtomicBool::new(false));
+        let resumed = SamplesAdder::new(8, 4);
+        let resumed = accumulate(&params, &world, &camera, &recorder, stop.clone(), resumed);
+        accumulate(&params, &world, &camera, &recorder, stop, resumed);
+
+        let events = recorder.0.into_inner().unwrap();
+        assert_eq!(events.len(), 6);
+        let mut indices: Vec<u32> = events.iter().map(|e| e.sample_index).collect();
+        indices.sort();
+        assert_eq!(indices, vec![0, 1, 2, 3, 4, 5]);
+        let mut completed: Vec<u32> = events.iter().map(|e| e.completed).collect();
+        completed.sort();
+        assert_eq!(completed, vec![1, 1, 2, 2, 3, 3]);
+        assert!(events.iter().all(|e| e.total == 3));
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| e.progress_fraction())
+                .fold(0.0, f32::max),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_preview_fps() {
+        let (world, mut camera_builder) = scene_tutorial(0);
+        let camera = camera_builder.aspect_ratio(2.0).build().unwrap();
+        let small = ["raytracer", "-i", "16", "-a", "2:1"];
+
+        // Far more samples than fit in the time, each of them much shorter than the interval
+        let params = RaytraceParams::parse_from(small.iter().chain(&[
+            "-s",
+            "100000000",
+            "--preview-fps",
+            "5",
+        ]));
+        let recorder = PreviewRecorder::default();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopper = {
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_secs(2));
+                stop.store(true, Relaxed);
+            })
+        };
+        let start = Instant::now();
+        render_live(&params, &world, &camera, &recorder, stop);
+        let elapsed = start.elapsed();
+        stopper.join().unwrap();
+        let previews = recorder.previews.into_inner().unwrap();
+        // The first preview is due after 200ms, the next ones at least 200ms later
+        let max_previews = (elapsed.as_secs_f64() * 5.0) as usize;
+        assert!(
+            previews.len() <= max_previews,
+            "{} previews in {:?}",
+            previews.len(),
+            elapsed
+        );
+        assert!(
+            previews.len() >= max_previews / 2,
+            "{} previews in {:?}",
+            previews.len(),
+            elapsed
+        );
+        for gap in previews.windows(2).map(|w| w[1] - w[0]) {
+            assert!(
+                gap >= Duration::from_millis(190),
+                "Previews {:?} apart",
+                gap
+            );
+        }
+
+        // Previews don't change the image
+        let args: Vec<_> = small.iter().chain(&["-s", "20"]).collect();
+        let throttled = RaytraceParams::parse_from(args.iter().chain(&[&"--preview-fps", &"1"]));
+        let unthrottled = RaytraceParams::parse_from(args);
+        let stop = Arc::new(AtomicBool::new(false));
+        assert_eq!(
+            render_live(
+                &throttled,
+                &world,
+                &camera,
+                &PreviewRecorder::default(),
+                stop
+            ),
+            render(&unthrottled, &world, &camera, &PreviewRecorder::default())
+        );
+    }
+
+    #[test]
+    fn test_downsample_averages_blocks() {
+        let img: FloatRgbaImage =
+            ImageBuffer::from_fn(6, 4, |x, y| Rgba([x as Float, y as Float, 0.5, 1.0]));
+        let small = downsample(&img, 2);
+        assert_eq!(small.dimensions(), (3, 2));
+        assert_eq!(*small.get_pixel(0, 0), Rgba([0.5, 0.5, 0.5, 1.0]));
+        assert_eq!(*small.get_pixel(2, 1), Rgba([4.5, 2.5, 0.5, 1.0]));
+        assert_eq!(downsample(&img, 1), img);
+    }
+
+    #[test]
+    fn test_dither_breaks_up_banding() {
+        // Gradient from a quarter to a quarter and two quantization steps (after gamma)
+        let (width, height) = (256, 16);
+        let mut adder = SamplesAdder::new(width, height);
+        for (x, _y, pixel) in adder.sum_img.enumerate_pixels_mut() {
+            let encoded = 0.25 + 2.0 / 255.0 * x as Float / width as Float;
+            let linear = encoded * encoded;
+            *pixel = Rgba([linear, linear, linear, 1.0]);
+        }
+        adder.num_samples = 1;
+
+        let unique_values = |img: &RgbaImage| {
+            img.pixels()
+                .map(|p| (p[0], p[1], p[2], p[3]))
+                .collect::<HashSet<_>>()
+                .len()
+        };
+        let plain = adder.normalized(false);
+        let dithered = adder.normalized(true);
+        assert!(unique_values(&dithered) > unique_values(&plain));
+        assert!(dithered.pixels().all(|p| p[3] == 255));
+
+        // Dithering must not shift the average brightness
+        let mean = |img: &RgbaImage| {
+            img.pixels().map(|p| p[0] as Float).sum::<Float>() / (width * height) as Float
+        };
+        assert!((mean(&plain) - mean(&dithered)).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_colorimage_matches_normalized() {
+        let mut adder = SamplesAdder::new(7, 3);
+        // The last column is outside of a crop region
+        let step = FloatRgbaImage::from_fn(7, 3, |x, y| match x {
+            6 => Rgba([0.0; 4]),
+            _ => Rgba([x as Float / 7.0, y as Float / 3.0, 0.5, 1.0]),
+        });
+        adder.add_image(&step);
+        for dither in [false, true] {
+            let rgba = adder.normalized(dither);
+            let colorimage = adder.normalized_colorimage(dither);
+            assert_eq!(colorimage.size, [7, 3]);
+            for (p, c) in rgba.pixels().zip(&colorimage.pixels) {
+                assert_eq!(p.0, c.to_array());
+            }
+            assert_eq!(rgba.get_pixel(6, 1).0, [0; 4]);
+        }
+    }
+
+    #[test]
+    fn test_batch_file() {
+        let content = r#"[
+            {"scene": "sun", "output": "renders/low sun.png"},
+            {
+                "scene": "chapter13-poisson",
+                "output": "poisson.exr",
+                "scene_seed": 7,
+                "scene_grid": 3,
+                "sphere_list": true,
+                "environment": "sky.hdr",
+                "params": {"samples-per-pixel": 4, "dither": true, "sky-top": "0,0,1"}
+            }
+        ]"#;
+        let jobs = parse_batch(content).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].scene, Scene::Sun);
+        assert_eq!(jobs[0].output, PathBuf::from("renders/low sun.png"));
+        assert_eq!(jobs[0].scene_seed, DEFAULT_SCENE_SEED);
+        assert_eq!(jobs[0].scene_grid, DEFAULT_SCENE_GRID);
+        assert!(!jobs[0].sphere_list);
+        assert_eq!(jobs[0].environment, None);
+        let defaults = jobs[0].raytrace_params().unwrap();
+        assert_eq!(defaults.samples_per_pixel, 10);
+        assert!(!defaults.dither);
+
+        assert_eq!(jobs[1].scene, Scene::Chapter13Poisson);
+        assert_eq!(jobs[1].scene_seed, 7);
+        assert_eq!(jobs[1].scene_grid, 3);
+        assert!(jobs[1].sphere_list);
+        assert_eq!(jobs[1].environment, Some(PathBuf::from("sky.hdr")));
+        let params = jobs[1].raytrace_params().unwrap();
+        assert_eq!(params.samples_per_pixel, 4);
+        assert!(params.dither);
+        assert_eq!(params.sky_top, Color::new(0.0, 0.0, 1.0));
+
+        // All scenes have the same name as on the command line
+        for scene in Scene::value_variants() {
+            let name = scene.to_possible_value().unwrap().get_name().to_owned();
+            let job = format!(r#"[{{"scene": "{}", "output": "a.png"}}]"#, name);
+            assert_eq!(parse_batch(&job).unwrap()[0].scene, *scene);
+        }
+
+        let params_error = |params: &str| {
+            let job = format!(
+                r#"[{{"scene": "sun", "output": "a.png", "params": {}}}]"#,
+                params
+            );
+            parse_batch(&job).unwrap()[0].raytrace_params().is_err()
+        };
+        assert!(params_error(r#"{"no-such-option": 1}"#));
+        assert!(params_error(r#"{"width": "wide"}"#));
+        assert!(params_error(r#"{"width": [1, 2]}"#));
+        assert!(parse_batch(r#"[{"scene": "nonexistent", "output": "a.png"}]"#).is_err());
+        assert!(parse_batch(r#"[{"scene": "sun", "output": "a.png", "typo": 1}]"#).is_err());
+    }
+
+    #[test]
+    fn test_batch_continues_after_failed_job() {
+        let dir = std::env::temp_dir().join(format!("raytracer_batch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let job = |output: PathBuf, params: &str| {
+            format!(
+                r#"{{"scene": "tutorial", "output": {}, "params": {}}}"#,
+                serde_json::to_string(&output).unwrap(),
+                params
+            )
+        };
+        let small = r#"{"width": 16, "samples-per-pixel": 1}"#;
+        let missing_environment = format!(
+            r#"{{"scene": "tutorial", "output": {}, "environment": {}}}"#,
+            serde_json::to_string(&dir.join("no environment.png")).unwrap(),
+            serde_json::to_string(&dir.join("missing.hdr")).unwrap()
+        );
+        let content = format!(
+            "[{}, {}, {}, {}]",
+            job(dir.join("invalid.png"), r#"{"width": -1}"#),
+            job(dir.join("missing dir").join("a.png"), small),
+            missing_environment,
+            job(dir.join("with space.png"), small)
+        );
+        let jobs = parse_batch(&content).unwrap();
+        assert!(!run_batch(&jobs));
+        assert!(!dir.join("invalid.png").exists());
+        assert!(!dir.join("no environment.png").exists());
+        let img = image::open(dir.join("with space.png")).unwrap();
+        assert_eq!(img.width(), 16);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Takes every preview that is offered, like the GUI
+    #[derive(Default)]
+    struct PreviewRecorder {
+        previews: Mutex<Vec<Instant>>,
+    }
+
+    impl ProgressBarWrapper for PreviewRecorder {
+        fn set_length(&self, _len: u64) {}
+
+        fn inc(&self, _delta: u64, get_immediate_image: Option<&dyn Fn() -> ColorImage>) {
+            if let Some(get_immediate_image) = get_immediate_image {
+                get_immediate_image();
+                self.previews.lock().unwrap().push(Instant::now());
+            }
+        }
+
+        fn finish(&self) {}
+    }
+
+    /// Benchmark, run with `cargo test --release accumulate_previews -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_accumulate_previews() {
+        let (mut world, mut camera_builder) = scene_random(11, DEFAULT_SCENE_SEED, false);
+        world.build_bvh();
+        let camera = camera_builder.aspect_ratio(16.0 / 9.0).build().unwrap();
+        let params = RaytraceParams::parse_from(["raytracer", "-i", "400", "-s", "64"]);
+        for threads in [1, 4, 8] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let recorder = PreviewRecorder::default();
+            let start = Instant::now();
+            pool.install(|| {
+                let adder = SamplesAdder::new(params.image_width, params.image_height());
+                let stop = Arc::new(AtomicBool::new(false));
+                accumulate(&params, &world, &camera, &recorder, stop, adder)
+            });
+            let elapsed = start.elapsed();
+            let previews = recorder.previews.into_inner().unwrap();
+            let gaps: Vec<Duration> = previews.windows(2).map(|w| w[1] - w[0]).collect();
+            println!(
+                "{} threads: {:.2?}, {} previews, {:.0?} to {:.0?} apart",
+                threads,
+                elapsed,
+                previews.len(),
+                gaps.iter().min().copied().unwrap_or_default(),
+                gaps.iter().max().copied().unwrap_or_default(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_scene_random_independent_of_thread_count() {
+        let describe = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let (world, _) = pool.install(|| scene_random(6, 7, false));
+            world
+                .iter()
+                .map(|object| object.describe())
+                .collect::<Vec<_>>()
+        };
+        let single_threaded = describe(1);
+        assert_eq!(single_threaded, describe(4));
+        // Ground, at most one sphere per cell and the three big spheres
+        assert!(single_threaded.len() > 1 + 12 * 12 - 3);
+        assert!(single_threaded.len() <= 1 + 12 * 12 + 3);
+        // Another seed gives another scene
+        let (other_seed, _) = scene_random(6, 8, false);
+        assert!(other_seed
+            .iter()
+            .zip(&single_threaded)
+            .any(|(object, description)| object.describe() != *description));
+    }
+
+    #[test]
+    fn test_sphere_list_renders_the_same() {
+        let params = RaytraceParams::parse_from(["raytracer", "-i", "40", "-s", "2"]);
+        let render_scene = |sphere_list| {
+            let (mut world, mut camera_builder) = scene_random(3, 7, sphere_list);
+            world.build_bvh();
+            let camera = camera_builder
+                .aspect_ratio(params.aspect_ratio)
+                .build()
+                .unwrap();
+            (
+                world.len(),
+                render(&params, &world, &camera, &ProgressBar::hidden()),
+            )
+        };
+        let (num_objects, single) = render_scene(false);
+        let (num_lists, batched) = render_scene(true);
+        // Ground, a list per row and the big spheres
+        assert_eq!(num_lists, 1 + 6 + 3);
+        assert!(num_objects > num_lists);
+        assert_eq!(single, batched);
     }
 }
@@ -1,101 +1,746 @@
 use std::ops::Neg;
 use std::sync::Arc;
 
-use crate::hittables::HitRecord;
+use crate::hittables::{perpendicular_basis, HitRecord};
+use crate::texture::{SolidColor, Texture};
 use crate::util::{
-    near_zero, random_in_unit_sphere, random_unit_vector, reflect, refract, Color, Ray,
+    consts, random_cosine_direction, random_in_unit_sphere, random_unit_vector, reflect, refract,
+    Color, Float, Onb, Ray, RenderRng, Vec3,
 };
 use rand::distributions::Uniform;
 use rand::prelude::Distribution;
-use rand::rngs::SmallRng;
+use rand::Rng;
 
 pub trait Material: Send + Sync {
     /// First return parameter is attenuation
-    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut SmallRng) -> Option<(Color, Ray)>;
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut RenderRng) -> Option<(Color, Ray)>;
+
+    /// Light emitted at the hit point, black for all non-emissive materials
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::zeros()
+    }
+
+    /// Albedo of an ideal diffuse surface, which receives direct light from the lights of
+    /// the world. `None` for all other materials.
+    fn diffuse_albedo(&self, _rec: &HitRecord) -> Option<Color> {
+        None
+    }
+
+    /// Which bounce limit a scattering at this material counts against
+    fn bounce_kind(&self) -> BounceKind {
+        BounceKind::Diffuse
+    }
+}
+
+/// Kinds of scattering with separate bounce limits, see `RaytraceParams::bounce_limits`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BounceKind {
+    Diffuse,
+    /// Mirror like reflection off metals
+    Glossy,
+    /// Refraction and reflection at glass
+    Transmission,
+}
+
+/// How `Lambertian` picks the directions of the scattered rays. Both converge to the same
+/// image, they only differ in noise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DiffuseMode {
+    /// Proportional to the cosine, like the BRDF times the cosine, so the attenuation is
+    /// just the albedo
+    #[default]
+    Cosine,
+    /// Uniform over the hemisphere, weighted with cos/π over the density 1/2π. Wastes
+    /// samples at grazing angles.
+    UniformHemisphere,
 }
 
 pub struct Lambertian {
-    pub albedo: Color,
+    pub albedo: Arc<dyn Texture>,
+    pub mode: DiffuseMode,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Arc<dyn Material> {
-        Arc::new(Lambertian { albedo })
+        Lambertian::with_mode(albedo, DiffuseMode::Cosine)
+    }
+
+    pub fn with_mode(albedo: Color, mode: DiffuseMode) -> Arc<dyn Material> {
+        Arc::new(Lambertian {
+            albedo: SolidColor::new(albedo),
+            mode,
+        })
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Arc<dyn Material> {
+        Arc::new(Lambertian {
+            albedo,
+            mode: DiffuseMode::Cosine,
+        })
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, rec: &HitRecord, rng: &mut SmallRng) -> Option<(Color, Ray)> {
-        let mut scatter_direction = rec.normal + random_unit_vector(rng);
-
-        if near_zero(&scatter_direction) {
-            scatter_direction = rec.normal;
+    fn scatter(&self, _ray: &Ray, rec: &HitRecord, rng: &mut RenderRng) -> Option<(Color, Ray)> {
+        let albedo = self.albedo.value(rec.u, rec.v, &rec.p);
+        match self.mode {
+            DiffuseMode::Cosine => {
+                let scatter_direction =
+                    Onb::from_w(&rec.normal).local(random_cosine_direction(rng));
+                Some((albedo, rec.scattered(scatter_direction)))
+            }
+            DiffuseMode::UniformHemisphere => {
+                let mut scatter_direction = random_unit_vector(rng);
+                let mut cosine = scatter_direction.dot(&rec.normal);
+                if cosine < 0.0 {
+                    scatter_direction = -scatter_direction;
+                    cosine = -cosine;
+                }
+                Some((albedo * 2.0 * cosine, rec.scattered(scatter_direction)))
+            }
         }
+    }
 
-        Some((self.albedo, Ray::new(rec.p, scatter_direction)))
+    fn diffuse_albedo(&self, rec: &HitRecord) -> Option<Color> {
+        Some(self.albedo.value(rec.u, rec.v, &rec.p))
     }
 }
 
 pub struct Metal {
     pub albedo: Color,
-    pub fuzz: f64,
+    pub fuzz: Float,
+    /// Brighten towards white at grazing angles (Schlick's approximation).
+    /// Without it the albedo is used for all angles.
+    pub fresnel: bool,
 }
 
 impl Metal {
-    pub fn new(albedo: Color, fuzz: f64) -> Arc<dyn Material> {
-        Arc::new(Metal { albedo, fuzz })
+    pub fn new(albedo: Color, fuzz: Float) -> Arc<dyn Material> {
+        Arc::new(Metal {
+            albedo,
+            fuzz,
+            fresnel: false,
+        })
+    }
+
+    pub fn with_fresnel(albedo: Color, fuzz: Float) -> Arc<dyn Material> {
+        Arc::new(Metal {
+            albedo,
+            fuzz,
+            fresnel: true,
+        })
+    }
+
+    /// Albedo at normal incidence, white at grazing incidence
+    fn reflectance(&self, cosine: Float) -> Color {
+        self.albedo + (Color::repeat(1.0) - self.albedo) * (1.0 - cosine).powi(5)
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut SmallRng) -> Option<(Color, Ray)> {
-        let reflected = reflect(&ray.direction().normalize(), &rec.normal);
-        if reflected.dot(&rec.normal) > 0. {
-            let scattered = Ray::new(rec.p, reflected + self.fuzz * random_in_unit_sphere(rng));
-            Some((self.albedo, scattered))
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut RenderRng) -> Option<(Color, Ray)> {
+        let unit_direction = ray.direction().normalize();
+        let reflected = reflect(&unit_direction, &rec.normal);
+        let scattered = rec.scattered(reflected + self.fuzz * random_in_unit_sphere(rng));
+        // The fuzz can tilt the reflection below the surface, those rays are absorbed
+        if scattered.direction().dot(&rec.normal) > 0. {
+            let attenuation = if self.fresnel {
+                let cos_theta = unit_direction.neg().dot(&rec.normal).clamp(0.0, 1.0);
+                self.reflectance(cos_theta)
+            } else {
+                self.albedo
+            };
+            Some((attenuation, scattered))
         } else {
             None
         }
     }
+
+    fn bounce_kind(&self) -> BounceKind {
+        BounceKind::Glossy
+    }
+}
+
+/// Microfacet metal with the GGX distribution, rougher along the surface tangent than across
+/// it (or the other way round), like brushed metal. The highlight stretches in the rougher
+/// direction. Equal roughnesses give an isotropic rough metal.
+pub struct AnisotropicMetal {
+    pub albedo: Color,
+    /// GGX alpha along the tangent (direction of increasing u), in 0..1
+    pub roughness_u: Float,
+    /// GGX alpha across the tangent
+    pub roughness_v: Float,
+}
+
+impl AnisotropicMetal {
+    pub fn new(albedo: Color, roughness_u: Float, roughness_v: Float) -> Arc<dyn Material> {
+        // Perfectly smooth microfacets have an infinitely narrow distribution
+        let min_roughness = 1e-3;
+        Arc::new(AnisotropicMetal {
+            albedo,
+            roughness_u: roughness_u.max(min_roughness),
+            roughness_v: roughness_v.max(min_roughness),
+        })
+    }
+
+    /// Smith masking of the direction `w` in the local frame (tangent, bitangent, normal)
+    fn g1(&self, w: &Vec3) -> Float {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+        let tan2 = (1.0 - cos2) / cos2;
+        let alpha2 = (w.x * self.roughness_u).powi(2) + (w.y * self.roughness_v).powi(2);
+        let alpha2 = alpha2 / (w.x * w.x + w.y * w.y).max(1e-12);
+        2.0 / (1.0 + (1.0 + alpha2 * tan2).sqrt())
+    }
+}
+
+impl Material for AnisotropicMetal {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut RenderRng) -> Option<(Color, Ray)> {
+        // Local frame with the tangent as x and the normal as z
+        let n = rec.normal;
+        let (t, b) = match rec.tangent {
+            Some(tangent) => {
+                let t = (tangent - n * n.dot(&tangent)).normalize();
+                (t, n.cross(&t))
+            }
+            None => perpendicular_basis(&n),
+        };
+        let to_local = |w: &Vec3| Vec3::new(w.dot(&t), w.dot(&b), w.dot(&n));
+        let wi = to_local(&-ray.direction().normalize());
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        // Sample the microfacet normal proportional to D(h) cos(h) by stretching the slopes
+        // of the unit roughness distribution
+        let distr = Uniform::new(0.0, 1.0);
+        let xi: Float = distr.sample(rng);
+        let phi = 2.0 * consts::PI * distr.sample(rng);
+        let slope = (xi / (1.0 - xi)).sqrt();
+        let h = Vec3::new(
+            -slope * phi.cos() * self.roughness_u,
+            -slope * phi.sin() * self.roughness_v,
+            1.0,
+        )
+        .normalize();
+        let wo = reflect(&-wi, &h);
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        // f * cos(wo) / pdf(wo) with pdf(wo) = D(h) cos(h) / (4 |wo · h|)
+        let wo_dot_h = wo.dot(&h).abs();
+        let weight = self.g1(&wi) * self.g1(&wo) * wo_dot_h / (wi.z * h.z);
+        let fresnel = self.albedo + (Color::repeat(1.0) - self.albedo) * (1.0 - wo_dot_h).powi(5);
+        let direction = wo.x * t + wo.y * b + wo.z * n;
+        Some((fresnel * weight, rec.scattered(direction)))
+    }
+
+    fn bounce_kind(&self) -> BounceKind {
+        BounceKind::Glossy
+    }
 }
 
 pub struct Dielectric {
-    pub ir: f64,
+    pub ir: Float,
 }
 
 impl Dielectric {
-    pub fn new(ir: f64) -> Arc<dyn Material> {
+    pub fn new(ir: Float) -> Arc<dyn Material> {
         Arc::new(Dielectric { ir })
     }
 
-    fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
-        let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
+    /// Schlick's approximation of the reflectance when going from a medium with index `eta_i`
+    /// into one with `eta_t`, at the cosine of the angle of incidence. Coming from the denser
+    /// medium, the approximation needs the cosine of the transmitted ray, and beyond the
+    /// critical angle everything is reflected.
+    fn reflectance(cosine: Float, eta_i: Float, eta_t: Float) -> Float {
+        let r0 = (eta_i - eta_t) / (eta_i + eta_t);
         let r0sq = r0 * r0;
-        r0sq + (1.0 - r0) * (1.0 - cosine).powf(5.0)
+        let cosine = if eta_i > eta_t {
+            let sin_t_sq = (eta_i / eta_t).powi(2) * (1.0 - cosine * cosine);
+            if sin_t_sq >= 1.0 {
+                return 1.0;
+            }
+            (1.0 - sin_t_sq).sqrt()
+        } else {
+            cosine
+        };
+        r0sq + (1.0 - r0sq) * (1.0 - cosine).powi(5)
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut SmallRng) -> Option<(Color, Ray)> {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut RenderRng) -> Option<(Color, Ray)> {
         let attenuation = Color::new(1.0, 1.0, 1.0);
-        let refaction_ratio = if rec.front_face {
-            1.0 / self.ir
+        let (eta_i, eta_t) = if rec.front_face {
+            (1.0, self.ir)
         } else {
-            self.ir
+            (self.ir, 1.0)
         };
+        let refaction_ratio = eta_i / eta_t;
         let unit_direction = ray.direction().normalize();
         let cos_theta = unit_direction.neg().dot(&rec.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-        let cannot_refract = refaction_ratio * sin_theta > 1.0;
 
-        let dist: Uniform<f64> = Uniform::new(0.0, 1.0);
-        let direction = if cannot_refract
-            || Dielectric::reflectance(cos_theta, refaction_ratio) > dist.sample(rng)
-        {
+        let dist: Uniform<Float> = Uniform::new(0.0, 1.0);
+        let direction = match refract(&unit_direction, &rec.normal, refaction_ratio) {
+            Some(refracted)
+                if Dielectric::reflectance(cos_theta, eta_i, eta_t) <= dist.sample(rng) =>
+            {
+                refracted
+            }
+            // Total internal reflection or Fresnel reflection
+            _ => reflect(&unit_direction, &rec.normal),
+        };
+        Some((attenuation, rec.scattered(direction)))
+    }
+
+    fn bounce_kind(&self) -> BounceKind {
+        BounceKind::Transmission
+    }
+}
+
+/// Thin slab of glass, e.g. a window pane or a soap bubble. Both interfaces are handled in
+/// a single scatter event: The ray is either reflected or passes straight through, the
+/// refraction on entering and leaving cancels out and the lateral offset is neglected.
+pub struct ThinDielectric {
+    pub ir: Float,
+}
+
+impl ThinDielectric {
+    pub fn new(ir: Float) -> Arc<dyn Material> {
+        Arc::new(ThinDielectric { ir })
+    }
+
+    /// Fraction of light reflected by the slab, including all internal bounces:
+    /// R + T²R / (1 - R²) = 2R / (1 + R) with the single interface reflectance R
+    fn reflectance(&self, cosine: Float) -> Float {
+        let r0 = (1.0 - self.ir) / (1.0 + self.ir);
+        let r0sq = r0 * r0;
+        let r = r0sq + (1.0 - r0sq) * (1.0 - cosine).powi(5);
+        2.0 * r / (1.0 + r)
+    }
+}
+
+impl Material for ThinDielectric {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut RenderRng) -> Option<(Color, Ray)> {
+        let attenuation = Color::new(1.0, 1.0, 1.0);
+        let unit_direction = ray.direction().normalize();
+        let cos_theta = unit_direction.neg().dot(&rec.normal).clamp(0.0, 1.0);
+
+        let dist: Uniform<Float> = Uniform::new(0.0, 1.0);
+        let direction = if self.reflectance(cos_theta) > dist.sample(rng) {
             reflect(&unit_direction, &rec.normal)
         } else {
-            refract(&unit_direction, &rec.normal, refaction_ratio)
+            unit_direction
+        };
+        Some((attenuation, rec.scattered(direction)))
+    }
+
+    fn bounce_kind(&self) -> BounceKind {
+        BounceKind::Transmission
+    }
+}
+
+/// Scatters into a uniformly random direction, regardless of the incoming ray.
+/// Phase function of volumes like fog, usable on surfaces as a crude glowing fog.
+pub struct Isotropic {
+    pub albedo: Color,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Arc<dyn Material> {
+        Arc::new(Isotropic { albedo })
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, _ray: &Ray, rec: &HitRecord, rng: &mut RenderRng) -> Option<(Color, Ray)> {
+        Some((self.albedo, rec.scattered(random_unit_vector(rng))))
+    }
+}
+
+/// Emits light and absorbs everything that hits it
+pub struct DiffuseLight {
+    pub color: Color,
+    /// Multiplies `color`. Real lights are much brighter than white surfaces, values well
+    /// above 1 are expected and only matter before tone mapping.
+    pub intensity: Float,
+}
+
+impl DiffuseLight {
+    pub fn new(color: Color, intensity: Float) -> Arc<dyn Material> {
+        Arc::new(DiffuseLight { color, intensity })
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _ray: &Ray, _rec: &HitRecord, _rng: &mut RenderRng) -> Option<(Color, Ray)> {
+        None
+    }
+
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        self.color * self.intensity
+    }
+}
+
+/// Blend of two materials: Each scattering is done by `a` with probability `factor` and by
+/// `b` otherwise, e.g. a diffuse surface with a metallic coat
+pub struct MixMaterial {
+    pub a: Arc<dyn Material>,
+    pub b: Arc<dyn Material>,
+    /// Probability of `a`, in 0..1
+    pub factor: Float,
+}
+
+impl MixMaterial {
+    pub fn new(a: &Arc<dyn Material>, b: &Arc<dyn Material>, factor: Float) -> Arc<dyn Material> {
+        Arc::new(MixMaterial {
+            a: a.clone(),
+            b: b.clone(),
+            factor: factor.clamp(0.0, 1.0),
+        })
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut RenderRng) -> Option<(Color, Ray)> {
+        if rng.gen::<Float>() < self.factor {
+            self.a.scatter(ray, rec, rng)
+        } else {
+            self.b.scatter(ray, rec, rng)
+        }
+    }
+
+    /// Emission and direct light are not sampled, but blended with the expected weights
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.factor * self.a.emitted(rec) + (1.0 - self.factor) * self.b.emitted(rec)
+    }
+
+    fn diffuse_albedo(&self, rec: &HitRecord) -> Option<Color> {
+        match (self.a.diffuse_albedo(rec), self.b.diffuse_albedo(rec)) {
+            (None, None) => None,
+            (a, b) => Some(
+                self.factor * a.unwrap_or_default() + (1.0 - self.factor) * b.unwrap_or_default(),
+            ),
+        }
+    }
+
+    /// The kind of the more likely material, the choice is only made in `scatter`
+    fn bounce_kind(&self) -> BounceKind {
+        if self.factor >= 0.5 {
+            self.a.bounce_kind()
+        } else {
+            self.b.bounce_kind()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{Point3, Vec3};
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_mix_material_delegates_by_factor() {
+        let red = Lambertian::new(Color::new(1.0, 0.0, 0.0));
+        let green = Lambertian::new(Color::new(0.0, 1.0, 0.0));
+        let material = MixMaterial::new(&red, &green, 0.3);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = HitRecord::new(Point3::zeros(), &normal, &material, 1.0, &ray);
+        let mut rng = RenderRng::seed_from_u64(5);
+
+        let n = 100_000;
+        let mut num_red = 0;
+        for _ in 0..n {
+            let (attenuation, _) = material.scatter(&ray, &rec, &mut rng).unwrap();
+            if attenuation.x == 1.0 {
+                num_red += 1;
+            }
+        }
+        let ratio = num_red as Float / n as Float;
+        assert!((ratio - 0.3).abs() < 0.01, "{}", ratio);
+
+        // The direct light sees the blended albedo
+        let albedo = material.diffuse_albedo(&rec).unwrap();
+        assert!((albedo - Color::new(0.3, 0.7, 0.0)).amax() < 1e-6);
+        let metal = Metal::new(Color::new(1.0, 1.0, 1.0), 0.0);
+        let coated = MixMaterial::new(&metal, &red, 0.6);
+        assert_eq!(coated.bounce_kind(), BounceKind::Glossy);
+        let albedo = coated.diffuse_albedo(&rec).unwrap();
+        assert!((albedo - Color::new(0.4, 0.0, 0.0)).amax() < 1e-6);
+    }
+
+    #[test]
+    fn test_fuzzy_metal_never_scatters_into_the_surface() {
+        let material = Metal::new(Color::new(0.8, 0.8, 0.8), 0.9);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let mut rng = RenderRng::seed_from_u64(1);
+        let mut scattered_rays = 0;
+        // Grazing incidence, where the fuzz most often pushes the ray below the surface
+        for dir in [
+            Vec3::new(1.0, 0.0, -0.05),
+            Vec3::new(1.0, 0.3, -0.5),
+            Vec3::new(0.0, 0.0, -1.0),
+        ] {
+            let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), dir);
+            let rec = HitRecord::new(Point3::zeros(), &normal, &material, 1.0, &ray);
+            for _ in 0..10_000 {
+                if let Some((_, scattered)) = material.scatter(&ray, &rec, &mut rng) {
+                    assert!(scattered.direction().dot(&normal) > 0.0);
+                    scattered_rays += 1;
+                }
+            }
+        }
+        assert!(scattered_rays > 15_000);
+    }
+
+    #[test]
+    fn test_fresnel_metal_reflects_more_at_grazing_angles() {
+        let albedo = Color::new(0.9, 0.6, 0.3);
+        let material = Metal::with_fresnel(albedo, 0.0);
+        let plain = Metal::new(albedo, 0.0);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let mut rng = RenderRng::seed_from_u64(2);
+        let attenuation = |material: &Arc<dyn Material>, degrees: Float, rng: &mut RenderRng| {
+            let angle = degrees.to_radians();
+            let ray = Ray::new(
+                Point3::new(0.0, 0.0, 1.0),
+                Vec3::new(angle.sin(), 0.0, -angle.cos()),
+            );
+            let rec = HitRecord::new(Point3::zeros(), &normal, material, 1.0, &ray);
+            material.scatter(&ray, &rec, rng).unwrap().0
+        };
+
+        // Schlick's approximation with the albedo as reflectance at normal incidence
+        assert!((attenuation(&material, 0.0, &mut rng) - albedo).amax() < 1e-6);
+        let schlick_60 = albedo + (Color::repeat(1.0) - albedo) / 32.0;
+        assert!((attenuation(&material, 60.0, &mut rng) - schlick_60).amax() < 1e-5);
+
+        let mut previous = albedo;
+        for degrees in [30.0, 60.0, 75.0, 85.0, 89.0] {
+            let reflectance = attenuation(&material, degrees, &mut rng);
+            assert!(
+                reflectance.iter().zip(previous.iter()).all(|(r, p)| r > p),
+                "{:?} at {} degrees",
+                reflectance,
+                degrees
+            );
+            assert!(reflectance.iter().all(|r| *r <= 1.0));
+            assert_eq!(attenuation(&plain, degrees, &mut rng), albedo);
+            previous = reflectance;
+        }
+        // Close to white at grazing incidence
+        assert!(previous.iter().all(|r| *r > 0.9), "{:?}", previous);
+    }
+
+    #[test]
+    fn test_anisotropic_metal_stretches_along_tangent() {
+        let material = AnisotropicMetal::new(Color::new(0.9, 0.9, 0.9), 0.4, 0.05);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = HitRecord::new(Point3::zeros(), &normal, &material, 1.0, &ray)
+            .with_tangent(Vec3::new(1.0, 0.0, 0.0));
+        let mut rng = RenderRng::seed_from_u64(3);
+        let n = 20_000;
+        let (mut spread_u, mut spread_v, mut energy) = (0.0, 0.0, 0.0);
+        for _ in 0..n {
+            if let Some((attenuation, scattered)) = material.scatter(&ray, &rec, &mut rng) {
+                let dir = scattered.direction().normalize();
+                assert!(dir.z > 0.0);
+                assert!(attenuation.iter().all(|c| c.is_finite() && *c >= 0.0));
+                spread_u += dir.x * dir.x;
+                spread_v += dir.y * dir.y;
+                energy += attenuation.x;
+            }
+        }
+        // The reflections spread far more along the tangent than across it
+        assert!(spread_u > 10.0 * spread_v);
+        // Some light is lost to masking, but none is created
+        let albedo = energy / n as Float;
+        assert!(0.7 < albedo && albedo < 1.0, "albedo {}", albedo);
+    }
+
+    #[test]
+    fn test_thin_dielectric_reflects_or_passes_straight() {
+        let material = ThinDielectric::new(1.5);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -2.0));
+        let rec = HitRecord::new(
+            Point3::zeros(),
+            &Vec3::new(0.0, 0.0, 1.0),
+            &material,
+            1.0,
+            &ray,
+        );
+        let mut rng = RenderRng::seed_from_u64(1);
+
+        let n = 100_000;
+        let mut reflected = 0;
+        for _ in 0..n {
+            let (_, scattered) = material.scatter(&ray, &rec, &mut rng).unwrap();
+            let d = scattered.direction().normalize();
+            if d == Vec3::new(0.0, 0.0, 1.0) {
+                reflected += 1;
+            } else {
+                assert_eq!(d, Vec3::new(0.0, 0.0, -1.0));
+            }
+        }
+        // R = 0.04 per interface at normal incidence, 2R / (1 + R) for the slab
+        let expected = 2.0 * 0.04 / 1.04;
+        assert!((reflected as Float / n as Float - expected).abs() < 0.005);
+    }
+
+    #[test]
+    fn test_isotropic_scatters_uniformly() {
+        let material = Isotropic::new(Color::new(0.5, 0.5, 0.5));
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = HitRecord::new(
+            Point3::zeros(),
+            &Vec3::new(0.0, 0.0, 1.0),
+            &material,
+            1.0,
+            &ray,
+        );
+        let mut rng = RenderRng::seed_from_u64(1);
+
+        let n = 100_000;
+        let mut sum = Vec3::zeros();
+        let mut sum_sq = Vec3::zeros();
+        let mut octants = [0u32; 8];
+        for _ in 0..n {
+            let (attenuation, scattered) = material.scatter(&ray, &rec, &mut rng).unwrap();
+            assert_eq!(attenuation, Color::new(0.5, 0.5, 0.5));
+            let d = scattered.direction().normalize();
+            sum += d;
+            sum_sq += d.component_mul(&d);
+            let octant =
+                (d.x > 0.0) as usize | ((d.y > 0.0) as usize) << 1 | ((d.z > 0.0) as usize) << 2;
+            octants[octant] += 1;
+        }
+
+        // Uniform on the sphere: no preferred direction and E[x²] = E[y²] = E[z²] = 1/3
+        let mean = sum / n as Float;
+        let mean_sq = sum_sq / n as Float;
+        for i in 0..3 {
+            assert!(mean[i].abs() < 0.01, "mean {:?}", mean);
+            assert!(
+                (mean_sq[i] - 1.0 / 3.0).abs() < 0.01,
+                "mean_sq {:?}",
+                mean_sq
+            );
+        }
+        for count in octants {
+            assert!(
+                (count as Float / n as Float - 0.125).abs() < 0.005,
+                "{:?}",
+                octants
+            );
+        }
+    }
+
+    /// Unpolarized reflectance from the full Fresnel equations
+    fn fresnel(cos_i: Float, eta_i: Float, eta_t: Float) -> Float {
+        let sin_t = eta_i / eta_t * (1.0 - cos_i * cos_i).sqrt();
+        if sin_t >= 1.0 {
+            return 1.0;
+        }
+        let cos_t = (1.0 - sin_t * sin_t).sqrt();
+        let rs = ((eta_i * cos_i - eta_t * cos_t) / (eta_i * cos_i + eta_t * cos_t)).powi(2);
+        let rp = ((eta_i * cos_t - eta_t * cos_i) / (eta_i * cos_t + eta_t * cos_i)).powi(2);
+        (rs + rp) / 2.0
+    }
+
+    #[test]
+    fn test_dielectric_reflectance_matches_fresnel() {
+        // Air to glass, and glass to air up to just below the critical angle of 41.8°
+        for (eta_i, eta_t, angles) in [
+            (1.0, 1.5, [0.0, 30.0, 60.0, 75.0]),
+            (1.5, 1.0, [0.0, 20.0, 38.0, 41.0]),
+        ] {
+            for angle in angles {
+                let cosine = (angle as Float).to_radians().cos();
+                let schlick = Dielectric::reflectance(cosine, eta_i, eta_t);
+                let exact = fresnel(cosine, eta_i, eta_t);
+                assert!(
+                    (schlick - exact).abs() < 0.025,
+                    "{} -> {} at {}°: {} vs {}",
+                    eta_i,
+                    eta_t,
+                    angle,
+                    schlick,
+                    exact
+                );
+            }
+        }
+        // Total internal reflection
+        let cosine = (45.0 as Float).to_radians().cos();
+        assert_eq!(Dielectric::reflectance(cosine, 1.5, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_diffuse_modes_converge_to_the_same_color() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let albedo = Color::new(0.8, 0.5, 0.2);
+        // Mean and variance of the light gathered from a sky that is brightest at the zenith
+        let estimate = |mode| {
+            let material = Lambertian::with_mode(albedo, mode);
+            let rec = HitRecord::new(Point3::zeros(), &normal, &material, 1.0, &ray);
+            let mut rng = RenderRng::seed_from_u64(5);
+            let n = 200_000;
+            let (mut sum, mut sum_sq) = (Color::zeros(), 0.0);
+            for _ in 0..n {
+                let (attenuation, scattered) = material.scatter(&ray, &rec, &mut rng).unwrap();
+                let radiance = scattered.direction().normalize().z;
+                sum += attenuation * radiance;
+                sum_sq += (attenuation.x * radiance).powi(2);
+            }
+            let mean = sum / n as Float;
+            (mean, sum_sq / n as Float - mean.x * mean.x)
+        };
+        let (cosine, cosine_variance) = estimate(DiffuseMode::Cosine);
+        let (uniform, uniform_variance) = estimate(DiffuseMode::UniformHemisphere);
+        // The integral of cos²/π over the hemisphere is 2/3
+        let expected = albedo * 2.0 / 3.0;
+        for (a, b) in [(cosine, expected), (uniform, expected)] {
+            assert!((a - b).abs().max() < 0.01, "{:?} vs {:?}", a, b);
+        }
+        assert!(uniform_variance > cosine_variance);
+    }
+
+    #[test]
+    fn test_no_acne_far_from_the_origin() {
+        // Far enough out that the spacing of the floating point numbers exceeds the t_min of
+        // `World::hit`
+        let x = if cfg!(feature = "precision-f32") {
+            1e5
+        } else {
+            1e14
         };
-        Some((attenuation, Ray::new(rec.p, direction)))
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let mut world = crate::world::World::new();
+        world.add(crate::hittables::Sphere::new(x, 0.0, 0.0, 100.0, &material));
+        let mut rng = RenderRng::seed_from_u64(4);
+        let mut self_hits = 0;
+        for _ in 0..1000 {
+            // From outside towards random points on the sphere
+            let normal = random_unit_vector(&mut rng);
+            let target = Point3::new(x, 0.0, 0.0) + normal * 100.0;
+            let origin = target + (normal + 0.5 * random_unit_vector(&mut rng)) * 50.0;
+            let ray = Ray::new(origin, target - origin);
+            let Some(rec) = world.hit(&ray, 0.001, Float::INFINITY) else {
+                continue;
+            };
+            let (_, scattered) = material.scatter(&ray, &rec, &mut rng).unwrap();
+            // A convex object can't be hit again by a ray leaving it
+            if world.hit(&scattered, 0.001, Float::INFINITY).is_some() {
+                self_hits += 1;
+            }
+        }
+        assert_eq!(self_hits, 0);
     }
 }
@@ -0,0 +1,442 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::hittables::{Hittable, SmoothTriangle, Triangle};
+use crate::material::Material;
+use crate::util::{Float, Point3, Vec3};
+
+type MeshResult<T> = Result<T, Box<dyn Error + Send + Sync + 'static>>;
+
+/// Loads the faces of an ASCII or binary (little or big endian) PLY file as triangles.
+/// Polygons are triangulated as fans around their first vertex.
+pub fn load_ply(
+    path: impl AsRef<Path>,
+    material: &Arc<dyn Material>,
+) -> MeshResult<Vec<Arc<dyn Hittable>>> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mesh = read_ply(BufReader::new(file)).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(mesh.triangles(material))
+}
+
+/// Vertices and polygons of a mesh file
+struct Mesh {
+    positions: Vec<Point3>,
+    /// Per vertex normals, empty if the file has none
+    normals: Vec<Vec3>,
+    faces: Vec<Vec<usize>>,
+}
+
+impl Mesh {
+    /// `SmoothTriangle`s if the mesh has vertex normals, flat `Triangle`s otherwise
+    fn triangles(&self, material: &Arc<dyn Material>) -> Vec<Arc<dyn Hittable>> {
+        let smooth = self.normals.len() == self.positions.len();
+        let mut triangles = vec![];
+        for face in self.faces.iter() {
+            for i in 1..face.len().saturating_sub(1) {
+                let corners = [face[0], face[i], face[i + 1]];
+                let vertices = corners.map(|c| self.positions[c]);
+                triangles.push(if smooth {
+                    SmoothTriangle::new(vertices, corners.map(|c| self.normals[c]), material)
+                } else {
+                    let [v0, v1, v2] = vertices;
+                    Triangle::new(v0, v1, v2, material)
+                });
+            }
+        }
+        triangles
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PlyScalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyScalar {
+    fn parse(name: &str) -> MeshResult<Self> {
+        Ok(match name {
+            "char" | "int8" => PlyScalar::I8,
+            "uchar" | "uint8" => PlyScalar::U8,
+            "short" | "int16" => PlyScalar::I16,
+            "ushort" | "uint16" => PlyScalar::U16,
+            "int" | "int32" => PlyScalar::I32,
+            "uint" | "uint32" => PlyScalar::U32,
+            "float" | "float32" => PlyScalar::F32,
+            "double" | "float64" => PlyScalar::F64,
+            _ => return Err(format!("Unknown PLY property type '{}'", name).into()),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyScalar::I8 | PlyScalar::U8 => 1,
+            PlyScalar::I16 | PlyScalar::U16 => 2,
+            PlyScalar::I32 | PlyScalar::U32 | PlyScalar::F32 => 4,
+            PlyScalar::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PlyProperty {
+    Scalar(String, PlyScalar),
+    /// Name, type of the length, type of the items
+    List(String, PlyScalar, PlyScalar),
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+/// Reads the values of the body, one scalar at a time
+struct PlyValues<R> {
+    reader: R,
+    format: PlyFormat,
+    /// Remaining tokens of the current line (ASCII only)
+    tokens: std::vec::IntoIter<String>,
+}
+
+impl<R: BufRead> PlyValues<R> {
+    fn next(&mut self, scalar: PlyScalar) -> MeshResult<f64> {
+        match self.format {
+            PlyFormat::Ascii => {
+                let token = loop {
+                    if let Some(token) = self.tokens.next() {
+                        break token;
+                    }
+                    let mut line = String::new();
+                    if self.reader.read_line(&mut line)? == 0 {
+                        return Err("Unexpected end of PLY file".into());
+                    }
+                    self.tokens = line
+                        .split_whitespace()
+                        .map(str::to_owned)
+                        .collect::<Vec<_>>()
+                        .into_iter();
+                };
+                token
+                    .parse()
+                    .map_err(|_| format!("Invalid number '{}' in PLY file", token).into())
+            }
+            PlyFormat::BinaryLittleEndian | PlyFormat::BinaryBigEndian => {
+                let mut buf = [0u8; 8];
+                let n = scalar.size();
+                self.reader
+                    .read_exact(&mut buf[..n])
+                    .map_err(|_| "Unexpected end of PLY file")?;
+                if self.format == PlyFormat::BinaryBigEndian {
+                    buf[..n].reverse();
+                }
+                let [b0, b1, b2, b3, ..] = buf;
+                Ok(match scalar {
+                    PlyScalar::I8 => b0 as i8 as f64,
+                    PlyScalar::U8 => b0 as f64,
+                    PlyScalar::I16 => i16::from_le_bytes([b0, b1]) as f64,
+                    PlyScalar::U16 => u16::from_le_bytes([b0, b1]) as f64,
+                    PlyScalar::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    PlyScalar::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    PlyScalar::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    PlyScalar::F64 => f64::from_le_bytes(buf),
+                })
+            }
+        }
+    }
+}
+
+fn read_ply<R: BufRead>(mut reader: R) -> MeshResult<Mesh> {
+    let mut line = String::new();
+    let mut next_line = |reader: &mut R| -> MeshResult<String> {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err("Unexpected end of PLY header".into());
+        }
+        Ok(line.trim().to_owned())
+    };
+
+    if next_line(&mut reader)? != "ply" {
+        return Err("Not a PLY file".into());
+    }
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    loop {
+        let line = next_line(&mut reader)?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", f, _version] => {
+                format = Some(match *f {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(format!("Unknown PLY format '{}'", f).into()),
+                })
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("Invalid element count '{}'", count))?,
+                properties: vec![],
+            }),
+            ["property", "list", len_type, item_type, name] => elements
+                .last_mut()
+                .ok_or("PLY property before the first element")?
+                .properties
+                .push(PlyProperty::List(
+                    name.to_string(),
+                    PlyScalar::parse(len_type)?,
+                    PlyScalar::parse(item_type)?,
+                )),
+            ["property", scalar_type, name] => elements
+                .last_mut()
+                .ok_or("PLY property before the first element")?
+                .properties
+                .push(PlyProperty::Scalar(
+                    name.to_string(),
+                    PlyScalar::parse(scalar_type)?,
+                )),
+            ["end_header"] => break,
+            _ => return Err(format!("Unsupported PLY header line '{}'", line).into()),
+        }
+    }
+    let format = format.ok_or("PLY header without format")?;
+    check_layout(&elements)?;
+
+    let mut values = PlyValues {
+        reader,
+        format,
+        tokens: vec![].into_iter(),
+    };
+    let mut mesh = Mesh {
+        positions: vec![],
+        normals: vec![],
+        faces: vec![],
+    };
+    for element in elements.iter() {
+        for _ in 0..element.count {
+            let mut scalars: Vec<(&str, f64)> = vec![];
+            let mut lists: Vec<(&str, Vec<f64>)> = vec![];
+            for property in element.properties.iter() {
+                match property {
+                    PlyProperty::Scalar(name, scalar) => {
+                        scalars.push((name, values.next(*scalar)?));
+                    }
+                    PlyProperty::List(name, len_type, item_type) => {
+                        let len = values.next(*len_type)? as usize;
+                        let items = (0..len)
+                            .map(|_| values.next(*item_type))
+                            .collect::<MeshResult<Vec<_>>>()?;
+                        lists.push((name, items));
+                    }
+                }
+            }
+            let get = |key: &str| {
+                scalars
+                    .iter()
+                    .find(|(name, _)| *name == key)
+                    .map(|(_, value)| *value as Float)
+            };
+            match element.name.as_str() {
+                "vertex" => {
+                    mesh.positions.push(Point3::new(
+                        get("x").unwrap(),
+                        get("y").unwrap(),
+                        get("z").unwrap(),
+                    ));
+                    if let (Some(nx), Some(ny), Some(nz)) = (get("nx"), get("ny"), get("nz")) {
+                        mesh.normals.push(Vec3::new(nx, ny, nz));
+                    }
+                }
+                "face" => {
+                    let (_, indices) = lists
+                        .into_iter()
+                        .find(|(name, _)| is_face_indices(name))
+                        .unwrap();
+                    mesh.faces
+                        .push(indices.into_iter().map(|i| i as usize).collect());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let num_vertices = mesh.positions.len();
+    if let Some(i) = mesh.faces.iter().flatten().find(|i| **i >= num_vertices) {
+        return Err(format!(
+            "Face references vertex {}, but there are only {}",
+            i, num_vertices
+        )
+        .into());
+    }
+    Ok(mesh)
+}
+
+fn is_face_indices(name: &str) -> bool {
+    name == "vertex_indices" || name == "vertex_index"
+}
+
+/// Makes sure the vertex and face elements have the properties `read_ply` relies on
+fn check_layout(elements: &[PlyElement]) -> MeshResult<()> {
+    let find_scalar = |element: &PlyElement, key: &str| {
+        element.properties.iter().find_map(|p| match p {
+            PlyProperty::Scalar(name, _) if name == key => Some(Ok(())),
+            PlyProperty::List(name, _, _) if name == key => Some(Err(format!(
+                "Unsupported PLY layout: vertex property '{}' is a list",
+                key
+            ))),
+            _ => None,
+        })
+    };
+    for element in elements {
+        match element.name.as_str() {
+            "vertex" => {
+                for key in ["x", "y", "z"] {
+                    find_scalar(element, key).ok_or_else(|| {
+                        format!("Unsupported PLY layout: vertex without '{}'", key)
+                    })??;
+                }
+            }
+            "face" => {
+                let has_indices = element
+                    .properties
+                    .iter()
+                    .any(|p| matches!(p, PlyProperty::List(name, _, _) if is_face_indices(name)));
+                if !has_indices {
+                    return Err("Unsupported PLY layout: face without a vertex_indices list".into());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::util::{Color, Ray};
+
+    const HEADER_END: &str = "element vertex 4
+property float x
+property float y
+property float z
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+
+    /// Unit square in the xy plane as a single quad
+    fn binary_quad(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian {
+            "binary_big_endian"
+        } else {
+            "binary_little_endian"
+        };
+        let mut data = format!("ply\nformat {} 1.0\n{}", format, HEADER_END).into_bytes();
+        let vertices: [[f32; 3]; 4] = [[0., 0., 0.], [1., 0., 0.], [1., 1., 0.], [0., 1., 0.]];
+        for v in vertices.iter().flatten() {
+            data.extend(if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            });
+        }
+        data.push(4);
+        for i in 0..4i32 {
+            data.extend(if big_endian {
+                i.to_be_bytes()
+            } else {
+                i.to_le_bytes()
+            });
+        }
+        data
+    }
+
+    #[test]
+    fn test_read_ply_formats() {
+        let ascii = format!(
+            "ply\nformat ascii 1.0\ncomment unit square\n{}0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n",
+            HEADER_END
+        );
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        for data in [ascii.into_bytes(), binary_quad(false), binary_quad(true)] {
+            let mesh = read_ply(data.as_slice()).unwrap();
+            assert_eq!(mesh.positions[2], Point3::new(1.0, 1.0, 0.0));
+            assert_eq!(mesh.faces, vec![vec![0, 1, 2, 3]]);
+
+            let triangles = mesh.triangles(&material);
+            assert_eq!(triangles.len(), 2);
+            let ray = Ray::new(Point3::new(0.2, 0.7, 1.0), Vec3::new(0.0, 0.0, -1.0));
+            assert!(triangles.iter().any(|t| t.hit(&ray, 0.001, 10.0).is_some()));
+        }
+    }
+
+    #[test]
+    fn test_read_ply_vertex_normals() {
+        let data = "ply
+format ascii 1.0
+element vertex 3
+property double x
+property double y
+property double z
+property float nx
+property float ny
+property float nz
+element face 1
+property list uchar uint vertex_index
+end_header
+0 0 0 0 0 1
+1 0 0 0 0 1
+0 1 0 0 1 1
+3 0 1 2
+";
+        let mesh = read_ply(data.as_bytes()).unwrap();
+        assert_eq!(mesh.normals.len(), 3);
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let triangles = mesh.triangles(&material);
+        assert_eq!(triangles[0].name(), "SmoothTriangle");
+    }
+
+    #[test]
+    fn test_read_ply_errors() {
+        let no_faces = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int other\nend_header\n0 0 0\n0\n";
+        let err = read_ply(no_faces.as_bytes()).err().unwrap();
+        assert!(err.to_string().contains("vertex_indices"), "{}", err);
+
+        let list_x = "ply\nformat ascii 1.0\nelement vertex 1\nproperty list uchar float x\nproperty float y\nproperty float z\nend_header\n1 0 0 0\n";
+        let err = read_ply(list_x.as_bytes()).err().unwrap();
+        assert!(err.to_string().contains("list"), "{}", err);
+
+        let truncated = &binary_quad(false)[..binary_quad(false).len() - 3];
+        let err = read_ply(truncated).err().unwrap();
+        assert!(err.to_string().contains("end of PLY"), "{}", err);
+
+        let bad_index = format!(
+            "ply\nformat ascii 1.0\n{}0 0 0\n1 0 0\n1 1 0\n0 1 0\n3 0 1 7\n",
+            HEADER_END
+        );
+        assert!(read_ply(bad_index.as_bytes()).is_err());
+    }
+}
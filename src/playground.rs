@@ -4,7 +4,7 @@ use image::{ImageBuffer, RgbImage};
 use indicatif::ProgressBar;
 use nalgebra::Matrix3;
 
-use crate::util::{Vec3, AsRgb, Color, Ray, Point3};
+use crate::util::{Vec3, AsRgb, Color, Float, Ray, Point3};
 
 const IMAGE_WIDTH: u32 = 256;
 const IMAGE_HEIGHT: u32 = 256;
@@ -17,8 +17,8 @@ pub fn test_image() {
     for y in 0..img.height() {
         for x in 0..img.width() {
             let c = Color::new(
-                x as f64 / (IMAGE_WIDTH - 1) as f64,
-                y as f64 / (IMAGE_HEIGHT - 1) as f64,
+                x as Float / (IMAGE_WIDTH - 1) as Float,
+                y as Float / (IMAGE_HEIGHT - 1) as Float,
                 0.25,
             );
 
@@ -72,7 +72,11 @@ pub fn test_ray_cylinder_math() {
 
     let m = Matrix3::from_columns(&[ray.direction(), n, ray2.direction()]);
     // t = M^-1 * (A - K)
-    let t = m.try_inverse().unwrap() * (ray2.origin() - ray.origin());
+    let Some(m_inv) = m.try_inverse() else {
+        println!("Lines are parallel, there is no unique nearest point");
+        return;
+    };
+    let t = m_inv * (ray2.origin() - ray.origin());
 
     // Calculate G=P(t2)
     let t2 = -t.z;
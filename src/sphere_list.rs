@@ -0,0 +1,284 @@
+use std::sync::Arc;
+
+use crate::bvh::Aabb;
+use crate::hittables::{HitRecord, Hittable, Sphere};
+use crate::util::{Float, Ray};
+
+/// Spheres tested together, one per SIMD lane
+const LANES: usize = 8;
+
+/// Centers and squared radii of `LANES` spheres, one array per coordinate so the compiler
+/// vectorizes the intersection test. Unused lanes have a NaN radius and are never hit.
+struct SphereBlock {
+    x: [Float; LANES],
+    y: [Float; LANES],
+    z: [Float; LANES],
+    radius_squared: [Float; LANES],
+}
+
+/// Many spheres that are tested against a ray in batches of `LANES`, instead of one virtual
+/// `hit` call per sphere. Only the closest sphere computes the full `HitRecord`, with
+/// `Sphere::hit`, so hits are the same as with the spheres on their own.
+pub struct SphereList {
+    blocks: Vec<SphereBlock>,
+    spheres: Vec<Sphere>,
+    bbox: Option<Aabb>,
+}
+
+impl SphereList {
+    pub fn new(spheres: Vec<Sphere>) -> Arc<dyn Hittable> {
+        let blocks = spheres
+            .chunks(LANES)
+            .map(|chunk| {
+                let mut block = SphereBlock {
+                    x: [0.0; LANES],
+                    y: [0.0; LANES],
+                    z: [0.0; LANES],
+                    radius_squared: [Float::NAN; LANES],
+                };
+                for (i, sphere) in chunk.iter().enumerate() {
+                    block.x[i] = sphere.center.x;
+                    block.y[i] = sphere.center.y;
+                    block.z[i] = sphere.center.z;
+                    block.radius_squared[i] = sphere.radius * sphere.radius;
+                }
+                block
+            })
+            .collect();
+        let bbox = spheres
+            .iter()
+            .filter_map(|sphere| sphere.bounding_box())
+            .reduce(|a, b| a.surrounding(&b));
+        Arc::new(SphereList {
+            blocks,
+            spheres,
+            bbox,
+        })
+    }
+}
+
+impl Hittable for SphereList {
+    fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        let (origin, direction) = (r.origin(), r.direction());
+        let a = direction.magnitude_squared();
+        let mut closest = t_max;
+        let mut closest_t = Float::INFINITY;
+        let mut closest_index = None;
+
+        for (block_index, block) in self.blocks.iter().enumerate() {
+            // Same quadratic as `Sphere::hit`, without branches
+            let mut half_b = [0.0; LANES];
+            let mut discriminant = [0.0; LANES];
+            for i in 0..LANES {
+                let ocx = origin.x - block.x[i];
+                let ocy = origin.y - block.y[i];
+                let ocz = origin.z - block.z[i];
+                half_b[i] = ocx * direction.x + ocy * direction.y + ocz * direction.z;
+                let c = ocx * ocx + ocy * ocy + ocz * ocz - block.radius_squared[i];
+                discriminant[i] = half_b[i] * half_b[i] - a * c;
+            }
+            // Most blocks are missed entirely
+            if !discriminant.iter().any(|&d| d >= 0.0) {
+                continue;
+            }
+            // Misses are infinite. NaN roots of misses fail all comparisons.
+            let mut t = [Float::INFINITY; LANES];
+            for i in 0..LANES {
+                let sqrtd = discriminant[i].sqrt();
+                let near = (-half_b[i] - sqrtd) / a;
+                let far = (-half_b[i] + sqrtd) / a;
+                t[i] = if t_min <= near && near <= closest {
+                    near
+                } else if t_min <= far && far <= closest {
+                    far
+                } else {
+                    Float::INFINITY
+                };
+            }
+            for (i, &t) in t.iter().enumerate() {
+                if t < closest_t {
+                    closest_t = t;
+                    closest_index = Some(block_index * LANES + i);
+                }
+            }
+            closest = closest.min(closest_t);
+        }
+
+        self.spheres[closest_index?].hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+
+    fn describe(&self) -> String {
+        format!("SphereList of {} spheres", self.spheres.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material};
+    use crate::util::{random_in_unit_sphere, Color, Point3, Vec3};
+    use approx::assert_relative_eq;
+    use rand::distributions::{Distribution, Uniform};
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+    use std::time::Instant;
+
+    /// Spheres of the chapter13 scene: the ground, a jittered grid of small ones and three
+    /// big ones
+    fn chapter13_spheres() -> Vec<Sphere> {
+        let material: Arc<dyn Material> = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let sphere = |center, radius| Sphere {
+            center,
+            radius,
+            material: material.clone(),
+            invert_normals: false,
+        };
+        let mut rng = SmallRng::seed_from_u64(13);
+        let distr_0_1: Uniform<Float> = Uniform::new(0.0, 1.0);
+        let mut spheres = vec![sphere(Point3::new(0.0, -1000.0, 0.0), 1000.0)];
+        for a in -11..11 {
+            for b in -11..11 {
+                let center = Point3::new(
+                    a as Float + 0.9 * distr_0_1.sample(&mut rng),
+                    0.2,
+                    b as Float + 0.9 * distr_0_1.sample(&mut rng),
+                );
+                spheres.push(sphere(center, 0.2));
+            }
+        }
+        for x in [-4.0, 0.0, 4.0] {
+            spheres.push(sphere(Point3::new(x, 1.0, 0.0), 1.0));
+        }
+        spheres
+    }
+
+    /// Rays from around the chapter13 camera into the scene
+    fn rays(n: usize) -> Vec<Ray> {
+        let mut rng = SmallRng::seed_from_u64(7);
+        let lookat = Point3::new(0.0, 0.0, 0.0);
+        (0..n)
+            .map(|_| {
+                let origin = Point3::new(13.0, 2.0, 3.0) + random_in_unit_sphere(&mut rng);
+                let target = lookat + 5.0 * random_in_unit_sphere(&mut rng);
+                Ray::new(origin, target - origin)
+            })
+            .collect()
+    }
+
+    /// The closest hit of `objects`, one virtual call per object
+    fn hit_each(objects: &[Arc<dyn Hittable>], r: &Ray) -> Option<HitRecord> {
+        let mut closest = None;
+        let mut t_max = Float::INFINITY;
+        for object in objects {
+            if let Some(hit) = object.hit(r, 0.001, t_max) {
+                t_max = hit.t;
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    fn as_objects(spheres: &[Sphere]) -> Vec<Arc<dyn Hittable>> {
+        spheres
+            .iter()
+            .map(|s| Sphere::new(s.center.x, s.center.y, s.center.z, s.radius, &s.material))
+            .collect()
+    }
+
+    #[test]
+    fn test_matches_single_spheres() {
+        let spheres = chapter13_spheres();
+        let objects = as_objects(&spheres);
+        let list = SphereList::new(spheres);
+
+        let mut hits = 0;
+        for r in rays(2000) {
+            match (list.hit(&r, 0.001, Float::INFINITY), hit_each(&objects, &r)) {
+                (Some(batched), Some(single)) => {
+                    assert_relative_eq!(batched.t, single.t, epsilon = 1e-6);
+                    assert_relative_eq!(batched.normal, single.normal, epsilon = 1e-6);
+                    hits += 1;
+                }
+                (None, None) => {}
+                (batched, single) => {
+                    panic!("{:?} vs {:?}", batched.map(|h| h.t), single.map(|h| h.t))
+                }
+            }
+        }
+        assert!(hits > 1000);
+    }
+
+    #[test]
+    fn test_ranges_and_padding() {
+        let material: Arc<dyn Material> = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        // Fewer spheres than lanes, the padding must never be hit
+        let spheres = [-2.0, 2.0]
+            .iter()
+            .map(|&z| Sphere {
+                center: Point3::new(0.0, 0.0, z),
+                radius: 0.5,
+                material: material.clone(),
+                invert_normals: false,
+            })
+            .collect();
+        let list = SphereList::new(spheres);
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_relative_eq!(list.hit(&r, 0.001, 100.0).unwrap().t, 2.5);
+        // From the inside of the first sphere
+        assert_relative_eq!(list.hit(&r, 3.0, 100.0).unwrap().t, 3.5);
+        assert_relative_eq!(list.hit(&r, 4.0, 100.0).unwrap().t, 6.5);
+        assert!(list.hit(&r, 0.001, 2.0).is_none());
+        assert!(list
+            .hit(
+                &Ray::new(Point3::new(3.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)),
+                0.001,
+                100.0
+            )
+            .is_none());
+
+        let bbox = list.bounding_box().unwrap();
+        assert_eq!(bbox.minimum, Point3::new(-0.5, -0.5, -2.5));
+        assert_eq!(bbox.maximum, Point3::new(0.5, 0.5, 2.5));
+    }
+
+    /// Benchmark, run with `cargo test --release sphere_list -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_sphere_list_vs_single_spheres() {
+        let spheres = chapter13_spheres();
+        let objects = as_objects(&spheres);
+        let num_spheres = spheres.len();
+        let list = SphereList::new(spheres);
+        let rays = rays(200_000);
+
+        let start = Instant::now();
+        let single_hits = rays
+            .iter()
+            .filter(|r| hit_each(&objects, r).is_some())
+            .count();
+        let single_time = start.elapsed();
+
+        let start = Instant::now();
+        let batched_hits = rays
+            .iter()
+            .filter(|r| list.hit(r, 0.001, Float::INFINITY).is_some())
+            .count();
+        let batched_time = start.elapsed();
+
+        println!(
+            "{} rays against {} spheres: one by one {:.2?}, in batches of {} {:.2?} ({:.1}x)",
+            rays.len(),
+            num_spheres,
+            single_time,
+            LANES,
+            batched_time,
+            single_time.as_secs_f64() / batched_time.as_secs_f64()
+        );
+        assert_eq!(single_hits, batched_hits);
+    }
+}
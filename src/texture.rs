@@ -0,0 +1,242 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::sync::Arc;
+
+use image::codecs::hdr::{HdrDecoder, HdrEncoder};
+use image::{ImageResult, Rgb32FImage, RgbImage};
+
+use crate::util::{Color, Float, Point3};
+
+/// Color that varies over a surface, looked up with the texture coordinates of a hit
+pub trait Texture: Send + Sync {
+    fn value(&self, u: Float, v: Float, p: &Point3) -> Color;
+}
+
+pub struct SolidColor {
+    pub color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> Arc<dyn Texture> {
+        Arc::new(SolidColor { color })
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: Float, _v: Float, _p: &Point3) -> Color {
+        self.color
+    }
+}
+
+/// Alternating squares of `scale`×`scale` in texture coordinates. The squares continue
+/// beyond 0..1, so it tiles objects with unbounded coordinates like `Plane` infinitely.
+pub struct CheckerTexture {
+    pub scale: Float,
+    pub even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: Float, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Arc<dyn Texture> {
+        Arc::new(CheckerTexture { scale, even, odd })
+    }
+
+    pub fn from_colors(scale: Float, even: Color, odd: Color) -> Arc<dyn Texture> {
+        CheckerTexture::new(scale, SolidColor::new(even), SolidColor::new(odd))
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: Float, v: Float, p: &Point3) -> Color {
+        // floor instead of truncation, which would make the squares at zero twice as large
+        let tile = (u / self.scale).floor() as i64 + (v / self.scale).floor() as i64;
+        if tile.rem_euclid(2) == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+/// How the 8 bit values of an `ImageTexture` relate to linear values. Float images (`.hdr`,
+/// `.exr`) are always linear.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureEncoding {
+    /// Colors as saved by paint programs and cameras, decoded with the sRGB curve
+    Srgb,
+    /// Data like normal or roughness maps, used as stored
+    Linear,
+}
+
+/// Image that covers the texture coordinates 0..1 and repeats beyond, (0, 0) is the bottom
+/// left corner. The texels are decoded to linear values once when loading.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    /// `.hdr` and `.exr` files keep their float values, values above 1 included, other
+    /// formats are decoded with `encoding`
+    pub fn load(path: impl AsRef<Path>, encoding: TextureEncoding) -> ImageResult<Self> {
+        let path = path.as_ref();
+        if is_float_image(path) {
+            Ok(Self::from_float_image(&load_float_image(path)?))
+        } else {
+            Ok(Self::from_image(&image::open(path)?.into_rgb8(), encoding))
+        }
+    }
+
+    pub fn from_image(img: &RgbImage, encoding: TextureEncoding) -> Self {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        assert!(width > 0 && height > 0, "Empty texture");
+        let decode = |c: u8| {
+            let c = c as Float / 255.0;
+            match encoding {
+                TextureEncoding::Srgb => srgb_to_linear(c),
+                TextureEncoding::Linear => c,
+            }
+        };
+        let pixels = img
+            .pixels()
+            .map(|p| Color::new(decode(p[0]), decode(p[1]), decode(p[2])))
+            .collect();
+        ImageTexture {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Linear values, like `EnvironmentMap::from_image`
+    pub fn from_float_image(img: &Rgb32FImage) -> Self {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        assert!(width > 0 && height > 0, "Empty texture");
+        let pixels = img
+            .pixels()
+            .map(|p| Color::new(p[0] as Float, p[1] as Float, p[2] as Float))
+            .collect();
+        ImageTexture {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: Float, v: Float, _p: &Point3) -> Color {
+        let x = (u.rem_euclid(1.0) * self.width as Float) as usize;
+        let y = ((1.0 - v.rem_euclid(1.0)) * self.height as Float) as usize;
+        self.pixels[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+    }
+}
+
+/// Loads an image with float values. Radiance `.hdr` files keep values above 1, which
+/// `image::open` would clamp to 8 bits, 8 bit formats end up in 0..1 without any decoding.
+pub fn load_float_image(path: impl AsRef<Path>) -> ImageResult<Rgb32FImage> {
+    let path = path.as_ref();
+    if !has_extension(path, "hdr") {
+        return Ok(image::open(path)?.into_rgb32f());
+    }
+    let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+    let meta = decoder.metadata();
+    let pixels = decoder.read_image_hdr()?;
+    let raw = pixels.iter().flat_map(|p| p.0).collect();
+    Ok(Rgb32FImage::from_raw(meta.width, meta.height, raw).expect("Decoder returned all pixels"))
+}
+
+/// Saves linear values as they are, as Radiance `.hdr` or OpenEXR depending on the extension
+pub fn save_float_image(img: &Rgb32FImage, path: impl AsRef<Path>) -> ImageResult<()> {
+    let path = path.as_ref();
+    if !has_extension(path, "hdr") {
+        return img.save(path);
+    }
+    // `image` 0.24 can only write `.hdr` through the encoder
+    let pixels: Vec<_> = img.pixels().copied().collect();
+    let file = BufWriter::new(File::create(path)?);
+    HdrEncoder::new(file).encode(&pixels, img.width() as usize, img.height() as usize)
+}
+
+/// Formats that store float values above 1, `.hdr` and `.exr`
+pub fn is_float_image(path: &Path) -> bool {
+    has_extension(path, "hdr") || has_extension(path, "exr")
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+/// Inverse of the sRGB transfer function, for values in 0..1
+pub fn srgb_to_linear(c: Float) -> Float {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::hdr::HdrEncoder;
+    use image::Rgb;
+
+    #[test]
+    fn test_checker_alternates_across_the_origin() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let black = Color::zeros();
+        let checker = CheckerTexture::from_colors(0.5, white, black);
+        let p = Point3::zeros();
+        let at = |u, v| checker.value(u, v, &p);
+
+        // Neighbors along u and v alternate, diagonal neighbors match
+        assert_eq!(at(0.25, 0.25), white);
+        assert_eq!(at(0.75, 0.25), black);
+        assert_eq!(at(0.25, 0.75), black);
+        assert_eq!(at(0.75, 0.75), white);
+        // Squares keep their size across zero
+        assert_eq!(at(-0.25, 0.25), black);
+        assert_eq!(at(-0.75, 0.25), white);
+        assert_eq!(at(-0.25, -0.25), white);
+        assert_eq!(at(-0.4, -0.1), white);
+    }
+
+    #[test]
+    fn test_image_texture_decodes_srgb() {
+        // Mid gray at the bottom left, white at the top left
+        let img = RgbImage::from_fn(2, 2, |x, y| match (x, y) {
+            (0, 1) => Rgb([128, 128, 128]),
+            (0, 0) => Rgb([255, 255, 255]),
+            _ => Rgb([0, 0, 0]),
+        });
+        let p = Point3::zeros();
+        let srgb = ImageTexture::from_image(&img, TextureEncoding::Srgb);
+        let gray = srgb.value(0.25, 0.25, &p);
+        assert!((gray.x - 0.2158).abs() < 1e-4, "{}", gray.x);
+        assert_eq!(srgb.value(0.25, 0.75, &p), Color::repeat(1.0));
+        // Repeats beyond 0..1
+        assert_eq!(srgb.value(1.25, -0.75, &p), gray);
+
+        let linear = ImageTexture::from_image(&img, TextureEncoding::Linear);
+        assert_eq!(linear.value(0.25, 0.25, &p), Color::repeat(128.0 / 255.0));
+    }
+
+    #[test]
+    fn test_hdr_texture_keeps_values_above_one() {
+        let img = Rgb32FImage::from_fn(4, 2, |x, _| Rgb([5.0, 0.25, x as f32]));
+        for extension in ["hdr", "exr"] {
+            let path = std::env::temp_dir().join(format!("raytracer_test_texture.{}", extension));
+            save_float_image(&img, &path).unwrap();
+
+            // Not decoded as sRGB, which would turn 0.25 into 0.05
+            let texture = ImageTexture::load(&path, TextureEncoding::Srgb).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let texel = texture.value(0.9, 0.5, &Point3::zeros());
+            assert_eq!(texel, Color::new(5.0, 0.25, 3.0), "{}", extension);
+        }
+    }
+}
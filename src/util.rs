@@ -1,66 +1,295 @@
 use std::cell::RefCell;
+use std::fmt;
 use std::ops::Neg;
+use std::time::Duration;
 
 use eframe::epaint::ColorImage;
 use image::{Rgb, Rgba};
 use indicatif::ProgressBar;
 use nalgebra::Vector3;
 
+use clap::ValueEnum;
 use rand::distributions::Uniform;
 use rand::prelude::{Distribution, Rng};
+use rand::rngs::SmallRng;
+use rand::{RngCore, SeedableRng};
 
-pub type Vec3 = Vector3<f64>;
+use crate::world::RayCounters;
+
+/// Scalar type of all geometry and color math.
+///
+/// `f64` by default. The `precision-f32` feature switches to `f32`, which halves the memory of
+/// the vectors and the accumulation image and lets the compiler pack more lanes into SIMD
+/// registers. The price is precision: intersection epsilons like `t_min = 0.001` are closer to
+/// the rounding error for large scene coordinates, and sums over thousands of samples lose
+/// their low bits.
+#[cfg(not(feature = "precision-f32"))]
+pub type Float = f64;
+#[cfg(feature = "precision-f32")]
+pub type Float = f32;
+#[cfg(feature = "precision-f32")]
+pub use std::f32::consts;
+#[cfg(not(feature = "precision-f32"))]
+pub use std::f64::consts;
+
+/// Random number generator of the render path: cameras, materials, lights and scenes.
+///
+/// `SmallRng` by default. The `buffered-rng` feature switches to `BufferedRng`, which returns
+/// the same numbers but generates them in blocks. Compare the two with a full render, e.g.
+/// `--scene chapter13`, and see `bench_buffered_rng` for the generator alone.
+#[cfg(not(feature = "buffered-rng"))]
+pub type RenderRng = SmallRng;
+#[cfg(feature = "buffered-rng")]
+pub type RenderRng = BufferedRng;
+
+/// `SmallRng` that generates `BUFFERED_RNG_BLOCK` numbers at a time, so the generator runs in
+/// a tight loop instead of between the math of each call site. Returns exactly the numbers of
+/// a `SmallRng` with the same seed.
+pub struct BufferedRng {
+    inner: SmallRng,
+    buffer: [u64; BUFFERED_RNG_BLOCK],
+    next: usize,
+}
+
+const BUFFERED_RNG_BLOCK: usize = 64;
+
+impl BufferedRng {
+    fn new(inner: SmallRng) -> Self {
+        BufferedRng {
+            inner,
+            buffer: [0; BUFFERED_RNG_BLOCK],
+            next: BUFFERED_RNG_BLOCK,
+        }
+    }
+}
+
+impl RngCore for BufferedRng {
+    fn next_u32(&mut self) -> u32 {
+        // Like `SmallRng` (xoshiro), which uses the upper bits because the lowest ones have
+        // some linear dependencies
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        if self.next == BUFFERED_RNG_BLOCK {
+            for value in self.buffer.iter_mut() {
+                *value = self.inner.next_u64();
+            }
+            self.next = 0;
+        }
+        self.next += 1;
+        self.buffer[self.next - 1]
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        // Same byte order as `SmallRng`
+        let mut chunks = dest.chunks_exact_mut(8);
+        for chunk in &mut chunks {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes());
+        }
+        let rest = chunks.into_remainder();
+        if rest.len() > 4 {
+            rest.copy_from_slice(&self.next_u64().to_le_bytes()[..rest.len()]);
+        } else if !rest.is_empty() {
+            rest.copy_from_slice(&self.next_u32().to_le_bytes()[..rest.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for BufferedRng {
+    type Seed = <SmallRng as SeedableRng>::Seed;
+
+    fn from_seed(seed: Self::Seed) -> Self {
+        BufferedRng::new(SmallRng::from_seed(seed))
+    }
+
+    // `SmallRng` expands a u64 seed differently than the default implementation
+    fn seed_from_u64(state: u64) -> Self {
+        BufferedRng::new(SmallRng::seed_from_u64(state))
+    }
+}
+
+pub type Vec3 = Vector3<Float>;
 pub type Color = Vec3;
 pub type Point3 = Vec3;
 
 pub trait AsRgb {
     fn as_rgb(self) -> Rgb<u8>;
-    fn as_f64_rgba(self) -> Rgba<f64>;
+    fn as_float_rgba(self) -> Rgba<Float>;
     fn as_rgb_multisample(self, samples_per_pixel: u32) -> Rgb<u8>;
     fn as_rgba_multisample(self, samples_per_pixel: u32) -> Rgba<u8>;
 }
 
+/// Largest encoded value before scaling, brighter colors are clipped to white
+pub const ENCODE_CEILING: Float = 1.0;
+/// 8 bit value of `ENCODE_CEILING`
+pub const ENCODE_SCALE: Float = 255.0;
+
+/// Gamma 2 encoding into 8 bit, rounded to the nearest value.
+/// `offset` is added in units of one quantization step (for dithering).
+pub fn encode_gamma2(linear: Float, offset: Float) -> u8 {
+    let encoded = linear.sqrt().clamp(0.0, ENCODE_CEILING) * ENCODE_SCALE;
+    (encoded + 0.5 + offset).clamp(0.0, ENCODE_SCALE) as u8
+}
+
 impl AsRgb for Color {
     fn as_rgb(self) -> Rgb<u8> {
         Rgb([
-            (self.x.sqrt().clamp(0.0, 0.999) * 256.0) as u8,
-            (self.y.sqrt().clamp(0.0, 0.999) * 256.0) as u8,
-            (self.z.sqrt().clamp(0.0, 0.999) * 256.0) as u8,
+            encode_gamma2(self.x, 0.0),
+            encode_gamma2(self.y, 0.0),
+            encode_gamma2(self.z, 0.0),
         ])
     }
 
     #[inline(always)]
-    fn as_f64_rgba(self) -> Rgba<f64> {
+    fn as_float_rgba(self) -> Rgba<Float> {
         Rgba([self.x, self.y, self.z, 1.0])
     }
 
     fn as_rgb_multisample(self, samples_per_pixel: u32) -> Rgb<u8> {
-        let scale = 1.0 / samples_per_pixel as f64;
-        Rgb([
-            ((self.x * scale).sqrt().clamp(0.0, 0.999) * 256.0) as u8,
-            ((self.y * scale).sqrt().clamp(0.0, 0.999) * 256.0) as u8,
-            ((self.z * scale).sqrt().clamp(0.0, 0.999) * 256.0) as u8,
-        ])
+        (self / samples_per_pixel as Float).as_rgb()
     }
 
     fn as_rgba_multisample(self, samples_per_pixel: u32) -> Rgba<u8> {
-        let scale = 1.0 / samples_per_pixel as f64;
-        Rgba([
-            ((self.x * scale).sqrt().clamp(0.0, 0.999) * 256.0) as u8,
-            ((self.y * scale).sqrt().clamp(0.0, 0.999) * 256.0) as u8,
-            ((self.z * scale).sqrt().clamp(0.0, 0.999) * 256.0) as u8,
-            255,
-        ])
+        let [r, g, b] = self.as_rgb_multisample(samples_per_pixel).0;
+        Rgba([r, g, b, 255])
+    }
+}
+
+/// SplitMix64 finalizer, scrambles all bits of `x` so that close inputs give unrelated outputs
+pub fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Seed of the `index`th random number stream derived from `base`
+pub fn derive_seed(base: u64, index: u64) -> u64 {
+    splitmix64(base ^ index)
+}
+
+/// How the sample positions within a pixel are chosen
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplePattern {
+    /// Independent uniform random positions
+    Uniform,
+    /// R2 low discrepancy sequence, shifted by a random offset per pixel (Cranley-Patterson
+    /// rotation). Successive samples fill the pixel evenly, which gives less noise at low
+    /// sample counts.
+    R2,
+}
+
+/// Weighting of the samples around a pixel center
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReconstructionFilter {
+    /// Samples cover exactly their pixel with equal weight
+    Box,
+    /// Samples spread over a two pixel wide footprint, weighted less towards its edge.
+    /// Softens edges and small bright details, like the sun disk of an environment map, that
+    /// a box filter turns into hard stair-steps.
+    Tent,
+    /// Gaussian with a standard deviation of half a pixel, cut off at three pixels width.
+    /// Even softer than the tent and without its corners, at the cost of some sharpness.
+    Gaussian,
+}
+
+/// Standard deviation of `ReconstructionFilter::Gaussian` in pixels
+const GAUSSIAN_FILTER_SIGMA: Float = 0.5;
+/// `ReconstructionFilter::Gaussian` is cut off at this many standard deviations
+const GAUSSIAN_FILTER_CUTOFF: Float = 3.0;
+
+impl ReconstructionFilter {
+    /// Distance from the pixel center in pixels at which the weight reaches zero
+    pub fn radius(self) -> Float {
+        match self {
+            ReconstructionFilter::Box => 0.5,
+            ReconstructionFilter::Tent => 1.0,
+            ReconstructionFilter::Gaussian => GAUSSIAN_FILTER_SIGMA * GAUSSIAN_FILTER_CUTOFF,
+        }
+    }
+
+    /// How many neighboring pixels on each side a sample within its pixel can reach
+    pub fn margin(self) -> u32 {
+        (self.radius() - 0.5).ceil() as u32
+    }
+
+    /// Weight of a sample at `offset` pixels from a pixel center, along one axis. The weight
+    /// of a sample is the product of both axes. Only relative weights matter, the sum of the
+    /// weights of a pixel normalizes it.
+    pub fn weight(self, offset: Float) -> Float {
+        let offset = offset.abs();
+        match self {
+            ReconstructionFilter::Box => {
+                if offset <= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            ReconstructionFilter::Tent => (1.0 - offset).max(0.0),
+            ReconstructionFilter::Gaussian => {
+                // Shifted down to reach zero at the cutoff instead of jumping there
+                let gaussian = |x: Float| (-x * x / (2.0 * GAUSSIAN_FILTER_SIGMA.powi(2))).exp();
+                (gaussian(offset) - gaussian(self.radius())).max(0.0)
+            }
+        }
+    }
+}
+
+/// Chooses the sub-pixel positions of the samples
+pub struct PixelSampler {
+    pattern: SamplePattern,
+    /// Decorrelates the per pixel rotations of different renders
+    seed: u64,
+}
+
+impl PixelSampler {
+    pub fn new(pattern: SamplePattern, seed: u64) -> Self {
+        PixelSampler { pattern, seed }
+    }
+
+    /// Position within `pixel` of sample number `sample_index`, both coordinates in 0..1
+    pub fn next_pixel_offset<R: Rng>(
+        &self,
+        pixel: (u32, u32),
+        sample_index: u32,
+        rng: &mut R,
+    ) -> (Float, Float) {
+        match self.pattern {
+            SamplePattern::Uniform => {
+                let distr: Uniform<Float> = Uniform::new(0.0, 1.0);
+                (distr.sample(rng), distr.sample(rng))
+            }
+            SamplePattern::R2 => {
+                // 1 / g and 1 / g² with the plastic number g
+                const A1: f64 = 0.754_877_666_246_692_8;
+                const A2: f64 = 0.569_840_290_998_053_3;
+                let hash = splitmix64(self.seed ^ ((pixel.0 as u64) << 32 | pixel.1 as u64));
+                let rotation_x = (hash >> 32) as f64 / (1u64 << 32) as f64;
+                let rotation_y = (hash & 0xFFFF_FFFF) as f64 / (1u64 << 32) as f64;
+                let n = sample_index as f64;
+                (
+                    (rotation_x + n * A1).fract() as Float,
+                    (rotation_y + n * A2).fract() as Float,
+                )
+            }
+        }
     }
 }
 
-pub fn vec3_random<D: Distribution<f64>, R: Rng>(distr: &D, rng: &mut R) -> Vec3 {
+pub fn vec3_random<D: Distribution<Float>, R: Rng>(distr: &D, rng: &mut R) -> Vec3 {
     Vec3::new(distr.sample(rng), distr.sample(rng), distr.sample(rng))
 }
 
 /// Random vector of length 0..1
 pub fn random_in_unit_sphere<R: Rng>(rng: &mut R) -> Vec3 {
-    let dist_m1p1: Uniform<f64> = Uniform::new(-1.0, 1.0);
+    let dist_m1p1: Uniform<Float> = Uniform::new(-1.0, 1.0);
     loop {
         let p = vec3_random(&dist_m1p1, rng);
         if p.magnitude_squared() < 1. {
@@ -74,9 +303,20 @@ pub fn random_unit_vector<R: Rng>(rng: &mut R) -> Vec3 {
     random_in_unit_sphere(rng).normalize()
 }
 
+/// Random unit vector with z > 0, distributed proportional to z, the cosine to the z axis.
+/// The density is cos(θ)/π. Turn it around a normal with `Onb::local`.
+pub fn random_cosine_direction<R: Rng>(rng: &mut R) -> Vec3 {
+    let dist_0_1: Uniform<Float> = Uniform::new(0.0, 1.0);
+    let phi = 2.0 * consts::PI * dist_0_1.sample(rng);
+    // Uniform on the unit disk, projected up onto the hemisphere
+    let r2 = dist_0_1.sample(rng);
+    let r = r2.sqrt();
+    Vec3::new(phi.cos() * r, phi.sin() * r, (1.0 - r2).sqrt())
+}
+
 /// Random vector of length 0..1 with z=0
 pub fn random_in_unit_disk<R: Rng>(rng: &mut R) -> Vec3 {
-    let dist_m1p1: Uniform<f64> = Uniform::new(-1.0, 1.0);
+    let dist_m1p1: Uniform<Float> = Uniform::new(-1.0, 1.0);
     loop {
         let p = Vector3::new(dist_m1p1.sample(rng), dist_m1p1.sample(rng), 0.0);
         if p.magnitude_squared() < 1.0 {
@@ -85,11 +325,65 @@ pub fn random_in_unit_disk<R: Rng>(rng: &mut R) -> Vec3 {
     }
 }
 
-pub fn near_zero(vec: &Vec3) -> bool {
-    let s = 1e-8;
-    vec.x.abs() < s && vec.y.abs() < s && vec.z.abs() < s
+/// Random vector inside the regular `n`-gon (n >= 3) inscribed in the unit circle with z=0.
+/// The first corner points to +y.
+pub fn random_in_unit_polygon<R: Rng>(n: u32, rng: &mut R) -> Vec3 {
+    let dist_0_1: Uniform<Float> = Uniform::new(0.0, 1.0);
+    // Pick one of the n triangles spanned by the center and two neighbouring corners
+    let segment = rng.gen_range(0..n) as Float;
+    let corner_angle = 2.0 * consts::PI / n as Float;
+    let corner = |i: Float| {
+        let angle = consts::FRAC_PI_2 + i * corner_angle;
+        Vec3::new(angle.cos(), angle.sin(), 0.0)
+    };
+    let a = corner(segment);
+    let b = corner(segment + 1.0);
+
+    // Uniform point in the triangle, mirror points from the far half back into the triangle
+    let mut s = dist_0_1.sample(rng);
+    let mut t = dist_0_1.sample(rng);
+    if s + t > 1.0 {
+        s = 1.0 - s;
+        t = 1.0 - t;
+    }
+    s * a + t * b
 }
 
+/// Perceived brightness of a linear color (Rec. 709 weights)
+pub fn luminance(c: &Color) -> Float {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// Orthonormal basis around the unit vector `w`, right-handed (u × v = w)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// Branchless construction by Duff et al., "Building an Orthonormal Basis, Revisited"
+    /// (2017). Stable for every direction of `w`, there is no helper axis that can end
+    /// up parallel to it.
+    pub fn from_w(w: &Vec3) -> Self {
+        let sign = Float::copysign(1.0, w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+        Onb {
+            u: Vec3::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x),
+            v: Vec3::new(b, sign + w.y * w.y * a, -w.y),
+            w: *w,
+        }
+    }
+
+    /// Converts coordinates relative to the basis to world coordinates
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct Ray {
     pub orig: Point3,
     pub dir: Vec3,
@@ -100,7 +394,7 @@ impl Ray {
         Ray { orig, dir }
     }
 
-    pub fn at(&self, t: f64) -> Point3 {
+    pub fn at(&self, t: Float) -> Point3 {
         self.orig + self.dir * t
     }
 
@@ -118,17 +412,90 @@ pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     v - 2.0 * v.dot(n) * n
 }
 
-pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {
+/// Direction of the unit vector `uv` after passing the surface with normal `n` (against
+/// `uv`) by Snell's law. `None` for total internal reflection.
+pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: Float) -> Option<Vec3> {
     let cos_theta = (-uv).dot(n).min(1.0);
     let r_out_perp = etai_over_etat * (uv + cos_theta * n);
-    let r_out_parallel = (1.0 - r_out_perp.magnitude_squared()).abs().sqrt().neg() * n;
-    r_out_perp + r_out_parallel
+    let cos_out_sq = 1.0 - r_out_perp.magnitude_squared();
+    if cos_out_sq < 0.0 {
+        return None;
+    }
+    Some(r_out_perp + cos_out_sq.sqrt().neg() * n)
 }
 
 pub trait ProgressBarWrapper: Send + Sync {
     fn set_length(&self, len: u64);
-    fn inc(&self, delta: u64, get_immediate_image: &dyn Fn() -> ColorImage);
+    /// `get_immediate_image` is only passed when a new preview of the image is available,
+    /// which happens at most `RaytraceParams::preview_fps` times per second, less often for
+    /// large images
+    fn inc(&self, delta: u64, get_immediate_image: Option<&dyn Fn() -> ColorImage>);
     fn finish(&self);
+
+    /// Called after each finished sample, right after `inc`. For frontends that draw their
+    /// own progress display.
+    fn on_sample_complete(&self, _event: &SampleEvent) {}
+
+    /// Called once before `finish` with the work done by the whole render
+    fn on_render_stats(&self, _counters: &RayCounters) {}
+}
+
+/// A sample of every pixel has been added to the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleEvent {
+    /// Index of the sample, as used for seeding. Samples finish in any order.
+    pub sample_index: u32,
+    /// Number of samples finished in this render, including this one
+    pub completed: u32,
+    /// Number of samples this render adds when it is not stopped
+    pub total: u32,
+    /// Time since the render started
+    pub elapsed: Duration,
+}
+
+impl SampleEvent {
+    /// Finished part of the render in 0..1
+    pub fn progress_fraction(&self) -> f32 {
+        self.completed as f32 / self.total.max(1) as f32
+    }
+}
+
+/// Distribution of the durations of repeated work, e.g. of the samples of a render
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimingSummary {
+    pub min: Duration,
+    pub median: Duration,
+    pub p95: Duration,
+    pub max: Duration,
+}
+
+impl TimingSummary {
+    /// `None` without any durations
+    pub fn new(durations: &[Duration]) -> Option<Self> {
+        let mut sorted = durations.to_vec();
+        sorted.sort();
+        // Nearest rank percentile
+        let percentile = |p: f64| {
+            let rank = (p * sorted.len() as f64).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        };
+        Some(TimingSummary {
+            min: *sorted.first()?,
+            median: percentile(0.5),
+            p95: percentile(0.95),
+            max: *sorted.last()?,
+        })
+    }
+}
+
+impl fmt::Display for TimingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "min {:.2?}, median {:.2?}, p95 {:.2?}, max {:.2?}",
+            self.min, self.median, self.p95, self.max
+        )
+    }
 }
 
 impl ProgressBarWrapper for ProgressBar {
@@ -136,7 +503,7 @@ impl ProgressBarWrapper for ProgressBar {
         self.set_length(len);
     }
 
-    fn inc(&self, delta: u64, _get_immediate_image: &dyn Fn() -> ColorImage) {
+    fn inc(&self, delta: u64, _get_immediate_image: Option<&dyn Fn() -> ColorImage>) {
         self.inc(delta);
     }
 
@@ -144,3 +511,281 @@ impl ProgressBarWrapper for ProgressBar {
         self.finish();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_onb() {
+        let mut rng = SmallRng::seed_from_u64(4);
+        let axes = [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            // Close to -z, where the construction divides by nearly zero
+            Vec3::new(1e-7, 0.0, -1.0).normalize(),
+        ];
+        let random_axes = (0..1000).map(|_| random_unit_vector(&mut rng));
+        for w in axes.into_iter().chain(random_axes) {
+            let onb = Onb::from_w(&w);
+            for (a, b) in [(onb.u, onb.v), (onb.v, onb.w), (onb.w, onb.u)] {
+                assert!(a.dot(&b).abs() < 1e-6, "{} {}", a, b);
+            }
+            for axis in [onb.u, onb.v, onb.w] {
+                assert!((axis.magnitude() - 1.0).abs() < 1e-6, "{}", axis);
+            }
+            assert!((onb.u.cross(&onb.v) - onb.w).amax() < 1e-6);
+            assert!(
+                (onb.local(Vec3::new(0.5, -2.0, 3.0)) - (0.5 * onb.u - 2.0 * onb.v + 3.0 * w))
+                    .amax()
+                    < 1e-6
+            );
+        }
+        assert_eq!(
+            Onb::from_w(&Vec3::new(0.0, 0.0, 1.0)).u,
+            Vec3::new(1.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_random_cosine_direction() {
+        let mut rng = SmallRng::seed_from_u64(5);
+        let n = 100_000;
+        let mut sum_z = 0.0;
+        for _ in 0..n {
+            let d = random_cosine_direction(&mut rng);
+            assert!(d.z > 0.0, "{}", d);
+            assert!((d.magnitude() - 1.0).abs() < 1e-5, "{}", d);
+            sum_z += d.z;
+        }
+        // The mean of cos(θ) over the density cos(θ)/π is 2/3
+        let mean_z = sum_z / n as Float;
+        assert!((mean_z - 2.0 / 3.0).abs() < 0.005, "{}", mean_z);
+    }
+
+    #[test]
+    fn test_random_in_unit_polygon() {
+        let mut rng = SmallRng::seed_from_u64(6);
+        for n in [3, 5, 6, 8] {
+            let corner_angle = 2.0 * consts::PI / n as Float;
+            // Distance of the edges from the center
+            let apothem = (corner_angle / 2.0).cos();
+            let samples = 40_000;
+            let mut per_sector = vec![0usize; n as usize];
+            for _ in 0..samples {
+                let p = random_in_unit_polygon(n, &mut rng);
+                assert_eq!(p.z, 0.0);
+                // Sector i lies between corner i and corner i + 1, its edge normal halfway
+                let angle = (p.y.atan2(p.x) - consts::FRAC_PI_2).rem_euclid(2.0 * consts::PI);
+                let sector = ((angle / corner_angle) as usize).min(n as usize - 1);
+                let normal_angle = consts::FRAC_PI_2 + (sector as Float + 0.5) * corner_angle;
+                let edge_normal = Vec3::new(normal_angle.cos(), normal_angle.sin(), 0.0);
+                assert!(
+                    p.dot(&edge_normal) <= apothem + 1e-5,
+                    "{} outside {}-gon",
+                    p,
+                    n
+                );
+                per_sector[sector] += 1;
+            }
+            // All sectors have the same area
+            let expected = samples / n as usize;
+            for count in per_sector {
+                assert!(
+                    count.abs_diff(expected) < expected / 10,
+                    "{} of {}",
+                    count,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_filter_weights() {
+        use ReconstructionFilter::*;
+        assert_eq!([Box.margin(), Tent.margin(), Gaussian.margin()], [0, 1, 1]);
+        // Anywhere within its own pixel, a sample gets the full box weight
+        assert_eq!(Box.weight(0.0), 1.0);
+        assert_eq!(Box.weight(-0.5), 1.0);
+        assert_eq!(Box.weight(0.5), 1.0);
+        assert_eq!(Box.weight(0.6), 0.0);
+        assert_eq!(Tent.weight(0.0), 1.0);
+        assert_eq!(Tent.weight(-0.25), 0.75);
+        assert_eq!(Tent.weight(1.0), 0.0);
+        assert_eq!(Tent.weight(1.4), 0.0);
+        assert!(Gaussian.weight(0.0) > Gaussian.weight(0.5));
+        assert_eq!(Gaussian.weight(0.5), Gaussian.weight(-0.5));
+        assert!(Gaussian.weight(1.4) > 0.0);
+        assert_eq!(Gaussian.weight(1.5), 0.0);
+        assert_eq!(Gaussian.weight(2.0), 0.0);
+    }
+
+    #[test]
+    fn test_encode_gamma2() {
+        assert_eq!(encode_gamma2(0.0, 0.0), 0);
+        assert_eq!(encode_gamma2(1.0, 0.0), 255);
+        assert_eq!(encode_gamma2(4.0, 0.0), 255);
+        assert_eq!(encode_gamma2(-1.0, 0.0), 0);
+        // Rounded, not truncated: 0.5 * 255 = 127.5
+        assert_eq!(encode_gamma2(0.25, 0.0), 128);
+        assert_eq!(encode_gamma2(1.0, 0.49), 255);
+        assert_eq!(Color::new(1.0, 0.0, 0.25).as_rgb(), Rgb([255, 0, 128]));
+        assert_eq!(
+            Color::new(4.0, 4.0, 4.0).as_rgba_multisample(4),
+            Rgba([255, 255, 255, 255])
+        );
+    }
+
+    #[test]
+    fn test_r2_pixel_offsets_converge_faster_than_uniform() {
+        // Estimate the area of a quarter disk with 16 samples in many pixels
+        let squared_error = |pattern: SamplePattern| {
+            let sampler = PixelSampler::new(pattern, 3);
+            let mut rng = SmallRng::seed_from_u64(3);
+            let mut sum = 0.0;
+            for pixel in 0..2000 {
+                let inside = (0..16)
+                    .map(|i| sampler.next_pixel_offset((pixel, 7), i, &mut rng))
+                    .inspect(|(x, y)| assert!((0.0..1.0).contains(x) && (0.0..1.0).contains(y)))
+                    .filter(|(x, y)| x * x + y * y < 1.0)
+                    .count();
+                let error = inside as Float / 16.0 - consts::FRAC_PI_4;
+                sum += error * error;
+            }
+            sum / 2000.0
+        };
+        let uniform = squared_error(SamplePattern::Uniform);
+        let r2 = squared_error(SamplePattern::R2);
+        assert!(r2 < 0.7 * uniform, "r2 {} uniform {}", r2, uniform);
+    }
+
+    #[test]
+    fn test_nearby_seeds_are_uncorrelated() {
+        let first_sample = |seed: u64| SmallRng::seed_from_u64(seed).gen::<f64>();
+        let correlation = |pairs: &[(f64, f64)]| {
+            let n = pairs.len() as f64;
+            let mean_a = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+            let mean_b = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+            let cov = pairs
+                .iter()
+                .map(|p| (p.0 - mean_a) * (p.1 - mean_b))
+                .sum::<f64>();
+            let var_a = pairs.iter().map(|p| (p.0 - mean_a).powi(2)).sum::<f64>();
+            let var_b = pairs.iter().map(|p| (p.1 - mean_b).powi(2)).sum::<f64>();
+            cov / (var_a * var_b).sqrt()
+        };
+
+        for base in [0, 1, 232008239771, u64::MAX] {
+            // Neighboring sample indices
+            let pairs: Vec<_> = (0..5000)
+                .map(|s| {
+                    (
+                        first_sample(derive_seed(base, s)),
+                        first_sample(derive_seed(base, s + 1)),
+                    )
+                })
+                .collect();
+            assert!(correlation(&pairs).abs() < 0.05);
+            // Neighboring bases
+            let pairs: Vec<_> = (0..5000)
+                .map(|s| {
+                    (
+                        first_sample(derive_seed(base, s)),
+                        first_sample(derive_seed(base.wrapping_add(1), s)),
+                    )
+                })
+                .collect();
+            assert!(correlation(&pairs).abs() < 0.05);
+        }
+        assert_ne!(derive_seed(0, 1), derive_seed(0, 2));
+    }
+
+    #[test]
+    fn test_timing_summary() {
+        assert_eq!(TimingSummary::new(&[]), None);
+        let durations: Vec<Duration> = (1..=20).rev().map(Duration::from_millis).collect();
+        let summary = TimingSummary::new(&durations).unwrap();
+        assert_eq!(summary.min, Duration::from_millis(1));
+        assert_eq!(summary.median, Duration::from_millis(10));
+        assert_eq!(summary.p95, Duration::from_millis(19));
+        assert_eq!(summary.max, Duration::from_millis(20));
+        let single = TimingSummary::new(&[Duration::from_millis(3)]).unwrap();
+        assert_eq!(single.median, single.p95);
+    }
+
+    #[test]
+    fn test_reflect() {
+        let n = Vec3::new(0.0, 1.0, 0.0);
+        let v = Vec3::new(1.0, -1.0, 0.0).normalize();
+        assert!((reflect(&v, &n) - Vec3::new(1.0, 1.0, 0.0).normalize()).amax() < 1e-6);
+        // Straight back at normal incidence, parallel to the surface stays
+        assert_eq!(reflect(&-n, &n), n);
+        let along = Vec3::new(0.0, 0.0, 1.0);
+        assert_eq!(reflect(&along, &n), along);
+    }
+
+    #[test]
+    fn test_refract() {
+        let n = Vec3::new(0.0, 1.0, 0.0);
+        let v = Vec3::new(1.0, -1.0, 0.0).normalize();
+        // Same medium on both sides
+        assert!((refract(&v, &n, 1.0).unwrap() - v).amax() < 1e-6);
+
+        // Into glass, bent towards the normal by Snell's law
+        let refracted = refract(&v, &n, 1.0 / 1.5).unwrap();
+        assert!((refracted.magnitude() - 1.0).abs() < 1e-6);
+        let sin_in = v.cross(&n).magnitude();
+        let sin_out = refracted.cross(&n).magnitude();
+        assert!(sin_out < sin_in);
+        assert!((sin_in - 1.5 * sin_out).abs() < 1e-6);
+
+        // Out of glass at 45°, beyond the critical angle of 41.8°
+        assert_eq!(refract(&v, &n, 1.5), None);
+        assert!(refract(&v, &n, 1.4).is_some());
+    }
+
+    #[test]
+    fn test_buffered_rng_matches_small_rng() {
+        let mut small = SmallRng::seed_from_u64(9);
+        let mut buffered = BufferedRng::seed_from_u64(9);
+        // More than one block, with all ways of taking numbers
+        for _ in 0..100 {
+            assert_eq!(small.next_u64(), buffered.next_u64());
+            assert_eq!(small.next_u32(), buffered.next_u32());
+            assert_eq!(small.gen::<f32>(), buffered.gen::<f32>());
+            assert_eq!(small.gen::<f64>(), buffered.gen::<f64>());
+            let (mut a, mut b) = ([0u8; 13], [0u8; 13]);
+            small.fill_bytes(&mut a);
+            buffered.fill_bytes(&mut b);
+            assert_eq!(a, b);
+        }
+    }
+
+    /// Benchmark, run with `cargo test --release buffered_rng -- --ignored --nocapture`.
+    /// Only the generator, see `RenderRng` for comparing full renders.
+    #[test]
+    #[ignore]
+    fn bench_buffered_rng() {
+        use std::time::Instant;
+
+        fn bench<R: Rng>(name: &str, rng: &mut R) -> Float {
+            let start = Instant::now();
+            let sum: Float = (0..10_000_000).map(|_| random_unit_vector(rng).x).sum();
+            println!(
+                "10M random_unit_vector with {}: {:.2?}",
+                name,
+                start.elapsed()
+            );
+            sum
+        }
+
+        let direct = bench("SmallRng", &mut SmallRng::seed_from_u64(1));
+        let buffered = bench("BufferedRng", &mut BufferedRng::seed_from_u64(1));
+        // Same numbers in the same order
+        assert_eq!(direct, buffered);
+    }
+}
@@ -1,34 +1,449 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::AddAssign;
 use std::sync::Arc;
 
 use crate::{
+    bvh::{Aabb, BoundingSphere, BvhNode, BvhOptions},
+    environment::EnvironmentMap,
     hittables::{HitRecord, Hittable},
-    util::Ray,
+    light::Light,
+    util::{Float, Ray},
 };
 
 pub struct World {
     objects: Vec<Arc<dyn Hittable>>,
+    bvh: Option<Bvh>,
+    /// Bounding sphere of each object if sphere culling is enabled, `None` for unbounded ones
+    bounding_spheres: Option<Vec<Option<BoundingSphere>>>,
+    /// Lights that are sampled explicitly at diffuse hits
+    lights: Vec<Arc<dyn Light>>,
+    /// Seen by rays that miss all objects, instead of the sky gradient
+    environment: Option<Arc<EnvironmentMap>>,
+}
+
+/// Acceleration structure of a `World`
+struct Bvh {
+    root: Option<Arc<dyn Hittable>>,
+    /// Objects without bounding box, tested one by one
+    unbounded: Vec<Arc<dyn Hittable>>,
 }
 
 impl World {
     pub fn new() -> Self {
-        World { objects: vec![] }
+        World {
+            objects: vec![],
+            bvh: None,
+            bounding_spheres: None,
+            lights: vec![],
+            environment: None,
+        }
+    }
+
+    pub fn set_environment(&mut self, environment: Arc<EnvironmentMap>) {
+        self.environment = Some(environment);
+    }
+
+    pub fn environment(&self) -> Option<&EnvironmentMap> {
+        self.environment.as_deref()
     }
 
+    pub fn add_light(&mut self, light: Arc<dyn Light>) {
+        self.lights.push(light);
+    }
+
+    pub fn lights(&self) -> &[Arc<dyn Light>] {
+        &self.lights
+    }
+
+    /// Adding an object drops the BVH, call `build_bvh()` again afterwards.
     pub fn add(&mut self, hittable: Arc<dyn Hittable>) {
-        self.objects.push(hittable)
+        if let Some(spheres) = &mut self.bounding_spheres {
+            spheres.push(hittable.bounding_sphere());
+        }
+        self.objects.push(hittable);
+        self.bvh = None;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn Hittable>> {
+        self.objects.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Builds a bounding volume hierarchy that `hit` uses from now on instead of testing
+    /// every object. Objects without bounding box are still tested one by one.
+    pub fn build_bvh(&mut self) {
+        self.build_bvh_with_options(BvhOptions::default());
+    }
+
+    pub fn build_bvh_with_options(&mut self, options: BvhOptions) {
+        let (mut bounded, unbounded): (Vec<_>, Vec<_>) = self
+            .objects
+            .iter()
+            .cloned()
+            .partition(|object| object.bounding_box().is_some());
+        let root = if bounded.is_empty() {
+            None
+        } else {
+            Some(BvhNode::with_options(&mut bounded, options))
+        };
+        self.bvh = Some(Bvh { root, unbounded });
+    }
+
+    pub fn has_bvh(&self) -> bool {
+        self.bvh.is_some()
+    }
+
+    /// Without BVH, `hit` skips objects whose bounding sphere the ray misses instead of
+    /// intersecting every object. Cheaper to set up than a BVH, but still linear in the
+    /// number of objects. Has no effect while a BVH is built.
+    pub fn enable_sphere_culling(&mut self) {
+        self.bounding_spheres = Some(
+            self.objects
+                .iter()
+                .map(|object| object.bounding_sphere())
+                .collect(),
+        );
     }
 
-    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    pub fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        match &self.bvh {
+            Some(bvh) => {
+                RayCounters::count(1, bvh.root.is_some() as u64 + bvh.unbounded.len() as u64);
+                let hit_record = bvh.root.as_ref().and_then(|root| root.hit(r, t_min, t_max));
+                let closest_so_far = hit_record.as_ref().map(|h| h.t).unwrap_or(t_max);
+                Self::hit_linear(&bvh.unbounded, r, t_min, closest_so_far).or(hit_record)
+            }
+            None => match &self.bounding_spheres {
+                Some(spheres) => self.hit_culled(spheres, r, t_min, t_max),
+                None => {
+                    RayCounters::count(1, self.objects.len() as u64);
+                    Self::hit_linear(&self.objects, r, t_min, t_max)
+                }
+            },
+        }
+    }
+
+    /// Like `hit_linear`, but only intersects objects whose bounding sphere the ray passes
+    fn hit_culled(
+        &self,
+        spheres: &[Option<BoundingSphere>],
+        r: &Ray,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<HitRecord> {
         let mut hit_record = None;
         let mut closest_so_far = t_max;
+        let mut intersection_tests = 0;
 
-        for object in self.objects.iter() {
+        for (object, sphere) in self.objects.iter().zip(spheres) {
+            if sphere.is_some_and(|sphere| !sphere.hit(r)) {
+                continue;
+            }
+            intersection_tests += 1;
             if let Some(new_hit_record) = object.hit(r, t_min, closest_so_far) {
                 closest_so_far = new_hit_record.t;
                 hit_record = Some(new_hit_record);
             }
         }
 
-        return hit_record;
+        RayCounters::count(1, intersection_tests);
+        hit_record
+    }
+
+    fn hit_linear(
+        objects: &[Arc<dyn Hittable>],
+        r: &Ray,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<HitRecord> {
+        let mut hit_record = None;
+        let mut closest_so_far = t_max;
+
+        for object in objects.iter() {
+            if let Some(new_hit_record) = object.hit(r, t_min, closest_so_far) {
+                closest_so_far = new_hit_record.t;
+                hit_record = Some(new_hit_record);
+            }
+        }
+
+        hit_record
+    }
+
+    /// Box around all bounded objects, unbounded objects are ignored.
+    /// `None` if there is no bounded object.
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.objects
+            .iter()
+            .filter_map(|object| object.bounding_box())
+            .reduce(|a, b| a.surrounding(&b))
+    }
+
+    pub fn stats(&self) -> WorldStats {
+        let mut per_type = BTreeMap::new();
+        for object in self.objects.iter() {
+            *per_type.entry(object.name()).or_insert(0) += 1;
+        }
+        WorldStats {
+            num_objects: self.objects.len(),
+            bounding_box: self.bounding_box(),
+            per_type,
+        }
+    }
+}
+
+impl fmt::Debug for World {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("World")
+            .field("objects", &self.objects)
+            .field("bvh", &self.has_bvh())
+            .field("lights", &self.lights.len())
+            .field("environment", &self.environment.is_some())
+            .finish()
+    }
+}
+
+pub struct WorldStats {
+    pub num_objects: usize,
+    pub bounding_box: Option<Aabb>,
+    /// Number of objects per hittable type name
+    pub per_type: BTreeMap<&'static str, usize>,
+}
+
+impl fmt::Display for WorldStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} objects", self.num_objects)?;
+        if let Some(bbox) = self.bounding_box {
+            write!(
+                f,
+                ", bounding box ({:.2}, {:.2}, {:.2}) to ({:.2}, {:.2}, {:.2})",
+                bbox.minimum.x,
+                bbox.minimum.y,
+                bbox.minimum.z,
+                bbox.maximum.x,
+                bbox.maximum.y,
+                bbox.maximum.z
+            )?;
+        }
+        for (name, count) in self.per_type.iter() {
+            write!(f, "\n  {}: {}", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    static RAY_COUNTERS: Cell<RayCounters> = Cell::new(RayCounters::default());
+}
+
+/// Work done by `World::hit`. Counted per thread to keep the hot path free of atomics,
+/// take the difference of two `RayCounters::current()` calls to measure a piece of work.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RayCounters {
+    pub rays: u64,
+    pub intersection_tests: u64,
+    /// Samples that were not finite or brighter than `--clamp`
+    pub clamped_samples: u64,
+}
+
+impl RayCounters {
+    /// Counters of the current thread
+    pub fn current() -> Self {
+        RAY_COUNTERS.with(|c| c.get())
+    }
+
+    /// Work done on this thread since `earlier` was taken
+    pub fn since(self, earlier: RayCounters) -> Self {
+        RayCounters {
+            rays: self.rays - earlier.rays,
+            intersection_tests: self.intersection_tests - earlier.intersection_tests,
+            clamped_samples: self.clamped_samples - earlier.clamped_samples,
+        }
+    }
+
+    pub(crate) fn count(rays: u64, intersection_tests: u64) {
+        RAY_COUNTERS.with(|c| {
+            let mut counters = c.get();
+            counters.rays += rays;
+            counters.intersection_tests += intersection_tests;
+            c.set(counters);
+        });
+    }
+
+    pub(crate) fn count_clamped_sample() {
+        RAY_COUNTERS.with(|c| {
+            let mut counters = c.get();
+            counters.clamped_samples += 1;
+            c.set(counters);
+        });
+    }
+}
+
+impl AddAssign for RayCounters {
+    fn add_assign(&mut self, rhs: Self) {
+        self.rays += rhs.rays;
+        self.intersection_tests += rhs.intersection_tests;
+        self.clamped_samples += rhs.clamped_samples;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittables::{Capsule, Cylinder, Plane, Sphere};
+    use crate::material::Lambertian;
+    use crate::util::{random_unit_vector, vec3_random, Color, Point3, Vec3};
+    use rand::distributions::Uniform;
+    use rand::prelude::Distribution;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_bvh_and_linear_hits_are_identical() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let distr_center = Uniform::new(-10.0, 10.0);
+        let distr_radius = Uniform::new(0.1, 1.5);
+
+        let mut linear = World::new();
+        let mut bvh = World::new();
+        for _ in 0..200 {
+            let c = vec3_random(&distr_center, &mut rng);
+            let sphere = Sphere::new(c.x, c.y, c.z, distr_radius.sample(&mut rng), &material);
+            linear.add(Arc::clone(&sphere));
+            bvh.add(sphere);
+        }
+        bvh.build_bvh();
+        assert!(bvh.has_bvh() && !linear.has_bvh());
+
+        let mut num_hits = 0;
+        for _ in 0..2000 {
+            let orig = vec3_random(&Uniform::new(-15.0, 15.0), &mut rng);
+            let ray = Ray::new(orig, random_unit_vector(&mut rng));
+            let linear_hit = linear.hit(&ray, 0.001, 1000.0);
+            let bvh_hit = bvh.hit(&ray, 0.001, 1000.0);
+            assert_eq!(linear_hit.is_some(), bvh_hit.is_some());
+            if let (Some(linear_hit), Some(bvh_hit)) = (linear_hit, bvh_hit) {
+                assert_eq!(linear_hit.t, bvh_hit.t);
+                assert_eq!(linear_hit.p, bvh_hit.p);
+                assert_eq!(linear_hit.normal, bvh_hit.normal);
+                num_hits += 1;
+            }
+        }
+        assert!(num_hits > 100);
+    }
+
+    #[test]
+    fn test_sphere_culling_finds_the_same_hits() {
+        let mut rng = SmallRng::seed_from_u64(3);
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let distr_center = Uniform::new(-10.0, 10.0);
+
+        let mut linear = World::new();
+        let mut culled = World::new();
+        culled.enable_sphere_culling();
+        for i in 0..100 {
+            let c = vec3_random(&distr_center, &mut rng);
+            let object: Arc<dyn Hittable> = if i % 2 == 0 {
+                Sphere::new(c.x, c.y, c.z, 1.0, &material)
+            } else {
+                Capsule::new(
+                    c,
+                    c + vec3_random(&Uniform::new(-2.0, 2.0), &mut rng),
+                    0.5,
+                    &material,
+                )
+            };
+            linear.add(Arc::clone(&object));
+            culled.add(object);
+        }
+        // Unbounded objects are never skipped
+        let plane = Plane::new(
+            Point3::new(0.0, -10.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            &material,
+        );
+        linear.add(Arc::clone(&plane));
+        culled.add(plane);
+
+        let before = RayCounters::current();
+        for _ in 0..2000 {
+            let orig = vec3_random(&Uniform::new(-15.0, 15.0), &mut rng);
+            let ray = Ray::new(orig, random_unit_vector(&mut rng));
+            let linear_hit = linear.hit(&ray, 0.001, 1000.0);
+            let culled_hit = culled.hit(&ray, 0.001, 1000.0);
+            assert_eq!(linear_hit.map(|h| h.t), culled_hit.map(|h| h.t));
+        }
+        let counters = RayCounters::current().since(before);
+        assert_eq!(counters.rays, 4000);
+        // The linear world did 2000 * 101 tests, the culled one far less
+        let culled_tests = counters.intersection_tests - 2000 * 101;
+        assert!(culled_tests < 2000 * 101 / 4, "{}", culled_tests);
+    }
+
+    #[test]
+    fn test_iter_and_debug() {
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let mut world = World::new();
+        assert!(world.is_empty());
+        world.add(Sphere::new(1.0, 2.0, 3.0, 0.5, &material));
+        world.add(Sphere::new(0.0, 0.0, 0.0, 1.0, &material));
+        assert_eq!(world.len(), 2);
+        assert!(world.iter().all(|object| object.name() == "Sphere"));
+
+        let debug = format!("{:?}", world);
+        assert!(
+            debug.contains("Sphere at (1.00, 2.00, 3.00) with radius 0.50"),
+            "{}",
+            debug
+        );
+        assert!(debug.contains("bvh: false"), "{}", debug);
+    }
+
+    #[test]
+    fn test_stats() {
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let mut world = World::new();
+        world.add(Sphere::new(1.0, 2.0, 3.0, 0.5, &material));
+        world.add(Sphere::new(0.0, 0.0, 0.0, 1.0, &material));
+        world.add(Cylinder::tapered(
+            Point3::new(-3.0, 0.0, 0.0),
+            Point3::new(-3.0, 4.0, 0.0),
+            1.0,
+            0.5,
+            &material,
+        ));
+        // Unbounded, counted but not part of the bounding box
+        world.add(Cylinder::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            0.5,
+            &material,
+        ));
+
+        let stats = world.stats();
+        assert_eq!(stats.num_objects, 4);
+        assert_eq!(
+            stats.per_type.into_iter().collect::<Vec<_>>(),
+            [("Cylinder", 1), ("Sphere", 2), ("TaperedCylinder", 1)]
+        );
+        let bbox = stats.bounding_box.unwrap();
+        assert_eq!(bbox.minimum, Point3::new(-4.0, -1.0, -1.0));
+        assert_eq!(bbox.maximum, Point3::new(1.5, 5.0, 3.5));
+
+        let display = world.stats().to_string();
+        assert_eq!(
+            display,
+            "4 objects, bounding box (-4.00, -1.00, -1.00) to (1.50, 5.00, 3.50)\n  \
+             Cylinder: 1\n  Sphere: 2\n  TaperedCylinder: 1"
+        );
     }
 }
@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+fn render_with_threads(threads: u32) -> Vec<u8> {
+    let output: PathBuf = std::env::temp_dir().join(format!(
+        "raytracer_determinism_{}_{}.png",
+        std::process::id(),
+        threads
+    ));
+    let status = Command::new(env!("CARGO_BIN_EXE_raytracer"))
+        .args([
+            "--scene",
+            "tutorial",
+            "--width",
+            "64",
+            "--samples-per-pixel",
+            "16",
+        ])
+        .args(["--seed", "42", "--threads", &threads.to_string()])
+        .arg("--output-filename")
+        .arg(&output)
+        .status()
+        .expect("Could not run raytracer");
+    assert!(status.success());
+    let bytes = std::fs::read(&output).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    bytes
+}
+
+#[test]
+fn test_output_independent_of_thread_count() {
+    let single_threaded = render_with_threads(1);
+    let multi_threaded = render_with_threads(8);
+    assert!(
+        single_threaded == multi_threaded,
+        "Output differs between 1 and 8 render threads"
+    );
+}
@@ -0,0 +1,84 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use image::RgbImage;
+
+/// The precision this test was built with and the other one
+const PRECISIONS: [&str; 2] = if cfg!(feature = "precision-f32") {
+    ["f32", "f64"]
+} else {
+    ["f64", "f32"]
+};
+
+fn render(raytracer: &mut Command, precision: &str) -> RgbImage {
+    let output: PathBuf = std::env::temp_dir().join(format!(
+        "raytracer_precision_{}_{}.png",
+        std::process::id(),
+        precision
+    ));
+    let status = raytracer
+        .args(["--scene", "tutorial", "--width", "96"])
+        .args(["--samples-per-pixel", "64", "--seed", "42"])
+        .arg("--output-filename")
+        .arg(&output)
+        .status()
+        .expect("Could not run raytracer");
+    assert!(status.success(), "Rendering with {} failed", precision);
+    let img = image::open(&output).unwrap().into_rgb8();
+    std::fs::remove_file(&output).unwrap();
+    img
+}
+
+/// Mean of each channel over `size`×`size` blocks, which averages out most of the noise
+fn block_means(img: &RgbImage, size: u32) -> Vec<f64> {
+    let mut means = vec![];
+    for by in 0..img.height() / size {
+        for bx in 0..img.width() / size {
+            for c in 0..3 {
+                let sum: u32 = (0..size * size)
+                    .map(|i| img.get_pixel(bx * size + i % size, by * size + i / size)[c] as u32)
+                    .sum();
+                means.push(sum as f64 / (size * size) as f64);
+            }
+        }
+    }
+    means
+}
+
+/// Both precisions draw different random numbers, so the noise differs, but the image has to
+/// look the same. Builds the binary with the other precision first, which takes a while:
+/// `cargo test --test precision -- --ignored`
+#[test]
+#[ignore]
+fn test_f32_and_f64_look_the_same() {
+    let [this, other] = PRECISIONS;
+    let this_img = render(&mut Command::new(env!("CARGO_BIN_EXE_raytracer")), this);
+
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut cargo = Command::new(env!("CARGO"));
+    cargo
+        .args(["run", "--quiet", "--bin", "raytracer"])
+        // Separate from the target directory that is locked by `cargo test`
+        .arg("--target-dir")
+        .arg(manifest_dir.join("target").join("precision"))
+        .arg("--manifest-path")
+        .arg(manifest_dir.join("Cargo.toml"));
+    if other == "f32" {
+        cargo.args(["--features", "precision-f32"]);
+    }
+    let other_img = render(cargo.arg("--"), other);
+
+    let (a, b) = (block_means(&this_img, 8), block_means(&other_img, 8));
+    let mean_abs_error = a.iter().zip(&b).map(|(a, b)| (a - b).abs()).sum::<f64>() / a.len() as f64;
+    let max_abs_error = a
+        .iter()
+        .zip(&b)
+        .map(|(a, b)| (a - b).abs())
+        .fold(0.0, f64::max);
+    println!(
+        "{} vs {}: block means differ by {:.2} on average, at most {:.2}",
+        this, other, mean_abs_error, max_abs_error
+    );
+    assert!(mean_abs_error < 0.5);
+    assert!(max_abs_error < 4.0);
+}